@@ -0,0 +1,122 @@
+use std::hint::black_box;
+
+use collections::RadixLinkTree;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+
+    for len in SIZES {
+        let tree = (0..len).collect::<RadixLinkTree<_>>();
+        let vector = (0..len).collect::<imbl::Vector<_>>();
+
+        group.bench_with_input(BenchmarkId::new("RadixLinkTree", len), &len, |b, &len| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 7919) % len;
+                black_box(tree.get(i))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("imbl::Vector", len), &len, |b, &len| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 7919) % len;
+                black_box(vector.get(i))
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn push(c: &mut Criterion) {
+    let mut group = c.benchmark_group("push");
+
+    for len in SIZES {
+        group.bench_with_input(BenchmarkId::new("RadixLinkTree", len), &len, |b, &len| {
+            b.iter(|| black_box((0..len).collect::<RadixLinkTree<_>>()))
+        });
+        group.bench_with_input(BenchmarkId::new("imbl::Vector", len), &len, |b, &len| {
+            b.iter(|| black_box((0..len).collect::<imbl::Vector<_>>()))
+        });
+    }
+
+    group.finish();
+}
+
+fn insert_middle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_middle");
+
+    for len in SIZES {
+        let tree = (0..len).collect::<RadixLinkTree<_>>();
+        let vector = (0..len).collect::<imbl::Vector<_>>();
+
+        group.bench_with_input(BenchmarkId::new("RadixLinkTree", len), &len, |b, &len| {
+            let mut tree = tree.clone();
+            b.iter(|| {
+                tree.insert(len / 2, 0);
+                black_box(tree.remove(len / 2))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("imbl::Vector", len), &len, |b, &len| {
+            let mut vector = vector.clone();
+            b.iter(|| {
+                vector.insert(len / 2, 0);
+                black_box(vector.remove(len / 2))
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn iter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iter");
+
+    for len in SIZES {
+        let tree = (0..len).collect::<RadixLinkTree<_>>();
+        let vector = (0..len).collect::<imbl::Vector<_>>();
+
+        group.bench_with_input(BenchmarkId::new("RadixLinkTree", len), &len, |b, _| {
+            b.iter(|| black_box(tree.iter().sum::<usize>()))
+        });
+        group.bench_with_input(BenchmarkId::new("imbl::Vector", len), &len, |b, _| {
+            b.iter(|| black_box(vector.iter().sum::<usize>()))
+        });
+    }
+
+    group.finish();
+}
+
+fn split_append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("split_append");
+
+    for len in SIZES {
+        let tree = (0..len).collect::<RadixLinkTree<_>>();
+        let vector = (0..len).collect::<imbl::Vector<_>>();
+
+        group.bench_with_input(BenchmarkId::new("RadixLinkTree", len), &len, |b, &len| {
+            let mut tree = tree.clone();
+            b.iter(|| {
+                let mut tail = tree.split_off(len / 3);
+                tree.append(&mut tail);
+                black_box(tree.len())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("imbl::Vector", len), &len, |b, &len| {
+            let mut vector = vector.clone();
+            b.iter(|| {
+                let tail = vector.split_off(len / 3);
+                vector.append(tail);
+                black_box(vector.len())
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, get, push, insert_middle, iter, split_append);
+criterion_main!(benches);
@@ -0,0 +1,855 @@
+use std::fmt;
+use std::ops::{Bound, Index, IndexMut, RangeBounds};
+use std::sync::Arc;
+
+/// The most values or children a node holds.
+const WIDTH: usize = 64;
+/// The fewest values or children a node other than the root holds.
+const MIN: usize = WIDTH / 2;
+
+type NodeIdx = usize;
+
+#[derive(Debug, Clone)]
+enum NodeData<T> {
+    Internal {
+        children: Vec<NodeIdx>,
+        // How many values are below each of `children`
+        sizes: Vec<usize>,
+    },
+    Leaf {
+        values: Vec<T>,
+        // Stable pointers to neighbors to allow iterating without going back up
+        next: Option<NodeIdx>,
+    },
+}
+
+impl<T> NodeData<T> {
+    fn leaf() -> Self {
+        Self::Leaf {
+            values: Vec::with_capacity(WIDTH),
+            next: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            NodeData::Internal { children, .. } => children.len(),
+            NodeData::Leaf { values, .. } => values.len(),
+        }
+    }
+}
+
+/// A 64-ary sequence of counted nodes whose leaves are linked left to right.
+///
+/// Despite the name it's a counted B+tree rather than a radix tree: positions are
+/// found through the sizes kept in each node rather than the digits of the index, so
+/// values can be inserted and removed anywhere.
+///
+/// Every node but the root holds between 32 and 64 values or children, and internal
+/// nodes keep how many values are below each child. `get`, `insert` and `remove`
+/// find their leaf in `O(log64 n)` steps and shift at most 64 values in it; a node
+/// that overflows is split, one that underflows is merged with or refilled from a
+/// sibling, so each level is touched once. Iterating from any position follows the
+/// `next` links between leaves without going back up the tree.
+///
+/// `split_off` and `append` cut and extend the tree along its edges and move whole
+/// leaves: `O(log n)` plus one leaf per 64 values moved.
+///
+/// Nodes live in an arena of shared pointers and refer to each other by position in
+/// it, so `Clone` copies a pointer per node, `O(n / 64)`, and a node shared between
+/// clones is copied the first time one of them changes it.
+pub struct RadixLinkTree<T> {
+    nodes: Vec<Arc<NodeData<T>>>,
+    free: Vec<NodeIdx>,
+    root: NodeIdx,
+    // Levels of internal nodes above the leaves
+    height: usize,
+    len: usize,
+    first_leaf: NodeIdx,
+    last_leaf: NodeIdx,
+}
+
+impl<T> Default for RadixLinkTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for RadixLinkTree<T> {
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            free: self.free.clone(),
+            root: self.root,
+            height: self.height,
+            len: self.len,
+            first_leaf: self.first_leaf,
+            last_leaf: self.last_leaf,
+        }
+    }
+}
+
+/// The child of a node with `sizes` that holds `index`, and the index within it.
+/// One past the end maps to one past the end of the last child, to insert there.
+fn child_at(sizes: &[usize], mut index: usize) -> (usize, usize) {
+    for (slot, &size) in sizes.iter().enumerate() {
+        if index < size {
+            return (slot, index);
+        }
+        index -= size;
+    }
+
+    let last = sizes.len() - 1;
+    (last, sizes[last] + index)
+}
+
+impl<T> RadixLinkTree<T> {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Arc::new(NodeData::leaf())],
+            free: Vec::new(),
+            root: 0,
+            height: 0,
+            len: 0,
+            first_leaf: 0,
+            last_leaf: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+
+        let (leaf, index) = self.locate(index);
+        self.values(leaf).get(index)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.values(self.last_leaf).last()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.range(..)
+    }
+
+    /// Iterates over the values in `range`, `O(log n)` to find its start.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range starts after it ends, or ends after `len`.
+    pub fn range(&self, range: impl RangeBounds<usize>) -> Iter<'_, T> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start <= end && end <= self.len,
+            "range {start}..{end} out of bounds for len {}",
+            self.len
+        );
+
+        if start == end {
+            return Iter {
+                tree: self,
+                leaf: None,
+                values: [].iter(),
+                remaining: 0,
+            };
+        }
+
+        let (leaf, index) = self.locate(start);
+        Iter {
+            tree: self,
+            leaf: self.next(leaf),
+            values: self.values(leaf)[index..].iter(),
+            remaining: end - start,
+        }
+    }
+
+    // --- HELPERS ---
+
+    /// The leaf holding `index`, and the index within it.
+    fn locate(&self, mut index: usize) -> (NodeIdx, usize) {
+        let mut curr = self.root;
+        for _ in 0..self.height {
+            let (slot, child_index) = child_at(self.sizes(curr), index);
+            curr = self.children(curr)[slot];
+            index = child_index;
+        }
+
+        (curr, index)
+    }
+
+    fn node_len(&self, idx: NodeIdx) -> usize {
+        self.nodes[idx].len()
+    }
+
+    fn is_leaf(&self, idx: NodeIdx) -> bool {
+        matches!(*self.nodes[idx], NodeData::Leaf { .. })
+    }
+
+    /// How many values are below `idx`.
+    fn subtree_len(&self, idx: NodeIdx) -> usize {
+        match &*self.nodes[idx] {
+            NodeData::Internal { sizes, .. } => sizes.iter().sum(),
+            NodeData::Leaf { values, .. } => values.len(),
+        }
+    }
+
+    fn children(&self, idx: NodeIdx) -> &[NodeIdx] {
+        match &*self.nodes[idx] {
+            NodeData::Internal { children, .. } => children,
+            NodeData::Leaf { .. } => unreachable!(),
+        }
+    }
+
+    fn sizes(&self, idx: NodeIdx) -> &[usize] {
+        match &*self.nodes[idx] {
+            NodeData::Internal { sizes, .. } => sizes,
+            NodeData::Leaf { .. } => unreachable!(),
+        }
+    }
+
+    fn values(&self, idx: NodeIdx) -> &[T] {
+        match &*self.nodes[idx] {
+            NodeData::Leaf { values, .. } => values,
+            NodeData::Internal { .. } => unreachable!(),
+        }
+    }
+
+    fn next(&self, idx: NodeIdx) -> Option<NodeIdx> {
+        match &*self.nodes[idx] {
+            NodeData::Leaf { next, .. } => *next,
+            NodeData::Internal { .. } => unreachable!(),
+        }
+    }
+
+    /// The path from the root to the last leaf, as `(node, slot)` pairs.
+    fn right_spine(&self) -> Vec<(NodeIdx, usize)> {
+        let mut path = Vec::with_capacity(self.height);
+        let mut curr = self.root;
+        for _ in 0..self.height {
+            let slot = self.children(curr).len() - 1;
+            path.push((curr, slot));
+            curr = self.children(curr)[slot];
+        }
+
+        path
+    }
+
+    fn alloc(&mut self, node: NodeData<T>) -> NodeIdx {
+        match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = Arc::new(node);
+                idx
+            }
+            None => {
+                self.nodes.push(Arc::new(node));
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, idx: NodeIdx) {
+        // Drop the payload now, the slot itself is reused by `alloc`.
+        self.nodes[idx] = Arc::new(NodeData::Internal {
+            children: Vec::new(),
+            sizes: Vec::new(),
+        });
+        self.free.push(idx);
+    }
+
+    /// Releases `idx` and every node below it, `height` levels of them.
+    fn release_subtree(&mut self, idx: NodeIdx, height: usize) {
+        if height > 0 {
+            for child in self.children(idx).to_vec() {
+                self.release_subtree(child, height - 1);
+            }
+        }
+        self.release(idx);
+    }
+}
+
+impl<T: Clone> RadixLinkTree<T> {
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+
+        let (leaf, index) = self.locate(index);
+        self.values_mut(leaf).get_mut(index)
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.insert(self.len, value);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        Some(self.remove(self.len - 1))
+    }
+
+    /// Inserts `value` at `index`, shifting everything after it one position right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(
+            index <= self.len,
+            "insertion index (is {index}) should be <= len (is {})",
+            self.len
+        );
+
+        let (leaf, index, path) = self.descend(index, |size| *size += 1);
+        self.values_mut(leaf).insert(index, value);
+        self.len += 1;
+
+        self.split_overflowing(leaf, path);
+    }
+
+    /// Removes and returns the value at `index`, shifting everything after it one
+    /// position left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "removal index (is {index}) should be < len (is {})",
+            self.len
+        );
+
+        let (leaf, index, mut path) = self.descend(index, |size| *size -= 1);
+        let value = self.values_mut(leaf).remove(index);
+        self.len -= 1;
+
+        let mut node = leaf;
+        while let Some((parent, slot)) = path.pop() {
+            if self.node_len(node) >= MIN {
+                return value;
+            }
+
+            // Nodes other than the root have at least two children.
+            if self.children(parent).len() > 1 {
+                self.rebalance(parent, slot.saturating_sub(1));
+            }
+            node = parent;
+        }
+        self.shrink_root();
+
+        value
+    }
+
+    /// Swaps the values at `a` and `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either is out of bounds.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+
+        let value = self[a].clone();
+        let value = std::mem::replace(&mut self[b], value);
+        self[a] = value;
+    }
+
+    /// Keeps only the values `f` returns true for. `O(n)`.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        *self = std::mem::take(self)
+            .into_iter()
+            .filter(|value| f(value))
+            .collect();
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.split_off(len);
+        }
+    }
+
+    /// Splits off everything from `at` onwards into a new tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(
+            at <= self.len,
+            "split index (is {at}) should be <= len (is {})",
+            self.len
+        );
+
+        if at == 0 {
+            return std::mem::take(self);
+        }
+        if at == self.len {
+            return Self::new();
+        }
+
+        // Cut after the last value that stays, along the path to it.
+        let (leaf, index, path) = self.descend(at - 1, |_| {});
+
+        let mut tail = vec![self.values_mut(leaf).split_off(index + 1)];
+        let mut cursor = self.next(leaf);
+        while let Some(idx) = cursor {
+            tail.push(std::mem::take(self.values_mut(idx)));
+            cursor = self.next(idx);
+        }
+
+        for (height, &(node, slot)) in path.iter().rev().enumerate() {
+            let (children, sizes) = self.internal_mut(node);
+            let cut = children.split_off(slot + 1);
+            sizes.truncate(slot + 1);
+
+            for child in cut {
+                self.release_subtree(child, height);
+            }
+        }
+        for &(node, slot) in path.iter().rev() {
+            let child = self.children(node)[slot];
+            let size = self.subtree_len(child);
+            self.internal_mut(node).1[slot] = size;
+        }
+
+        *self.next_mut(leaf) = None;
+        self.last_leaf = leaf;
+        self.len = at;
+        self.rebalance_right_spine();
+
+        let mut other = Self::new();
+        for values in tail {
+            other.push_leaf(values);
+        }
+
+        other
+    }
+
+    /// Moves every value of `other` to the end of `self`, leaving `other` empty.
+    pub fn append(&mut self, other: &mut Self) {
+        let mut other = std::mem::take(other);
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+
+        let mut cursor = Some(other.first_leaf);
+        while let Some(idx) = cursor {
+            let values = std::mem::take(other.values_mut(idx));
+            cursor = other.next(idx);
+            self.push_leaf(values);
+        }
+    }
+
+    // --- HELPERS ---
+
+    /// Walks down to the leaf holding `index`, applying `update` to the size of every
+    /// child on the way. Returns the leaf, the index within it and the path to it.
+    fn descend(
+        &mut self,
+        mut index: usize,
+        update: impl Fn(&mut usize),
+    ) -> (NodeIdx, usize, Vec<(NodeIdx, usize)>) {
+        let mut path = Vec::with_capacity(self.height);
+        let mut curr = self.root;
+        for _ in 0..self.height {
+            let (slot, child_index) = child_at(self.sizes(curr), index);
+            let (children, sizes) = self.internal_mut(curr);
+            update(&mut sizes[slot]);
+            path.push((curr, slot));
+            curr = children[slot];
+            index = child_index;
+        }
+
+        (curr, index, path)
+    }
+
+    /// Adds `values` after the last one, as a leaf of their own unless they, or the
+    /// last leaf, are too few to stand alone.
+    fn push_leaf(&mut self, values: Vec<T>) {
+        let len = values.len();
+        if len == 0 {
+            return;
+        }
+
+        let mut path = self.right_spine();
+        self.len += len;
+
+        if len < MIN || self.node_len(self.last_leaf) < MIN {
+            for &(node, slot) in &path {
+                self.internal_mut(node).1[slot] += len;
+            }
+
+            let leaf = self.last_leaf;
+            self.values_mut(leaf).extend(values);
+            self.split_overflowing(leaf, path);
+            return;
+        }
+
+        let leaf = self.alloc(NodeData::Leaf { values, next: None });
+        let last_leaf = self.last_leaf;
+        *self.next_mut(last_leaf) = Some(leaf);
+        self.last_leaf = leaf;
+
+        let parent = path.pop();
+        for &(node, slot) in &path {
+            self.internal_mut(node).1[slot] += len;
+        }
+        match parent {
+            Some((parent, _)) => {
+                let (children, sizes) = self.internal_mut(parent);
+                children.push(leaf);
+                sizes.push(len);
+                self.split_overflowing(parent, path);
+            }
+            None => {
+                let size = self.len - len;
+                self.root = self.alloc(NodeData::Internal {
+                    children: vec![last_leaf, leaf],
+                    sizes: vec![size, len],
+                });
+                self.height += 1;
+            }
+        }
+    }
+
+    /// Splits `node` in halves if it holds more than `WIDTH`, then its parent, up
+    /// along `path`, growing a new root if the old one splits.
+    fn split_overflowing(&mut self, mut node: NodeIdx, mut path: Vec<(NodeIdx, usize)>) {
+        while self.node_len(node) > WIDTH {
+            let right = self.split_node(node);
+            let right_size = self.subtree_len(right);
+
+            match path.pop() {
+                Some((parent, slot)) => {
+                    let (children, sizes) = self.internal_mut(parent);
+                    children.insert(slot + 1, right);
+                    sizes[slot] -= right_size;
+                    sizes.insert(slot + 1, right_size);
+                    node = parent;
+                }
+                None => {
+                    let left_size = self.len - right_size;
+                    self.root = self.alloc(NodeData::Internal {
+                        children: vec![node, right],
+                        sizes: vec![left_size, right_size],
+                    });
+                    self.height += 1;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Moves the upper half of `node` into a new node right after it.
+    fn split_node(&mut self, node: NodeIdx) -> NodeIdx {
+        if self.is_leaf(node) {
+            let values = self.values_mut(node);
+            let values = values.split_off(values.len() / 2);
+            let next = self.next(node);
+
+            let right = self.alloc(NodeData::Leaf { values, next });
+            *self.next_mut(node) = Some(right);
+            if self.last_leaf == node {
+                self.last_leaf = right;
+            }
+
+            right
+        } else {
+            let (children, sizes) = self.internal_mut(node);
+            let at = children.len() / 2;
+            let children = children.split_off(at);
+            let sizes = sizes.split_off(at);
+
+            self.alloc(NodeData::Internal { children, sizes })
+        }
+    }
+
+    /// Evens out the children of `parent` at `slot` and `slot + 1`, merging them if
+    /// they fit in one node.
+    fn rebalance(&mut self, parent: NodeIdx, slot: usize) {
+        let left = self.children(parent)[slot];
+        let right = self.children(parent)[slot + 1];
+        let total = self.node_len(left) + self.node_len(right);
+
+        if total <= WIDTH {
+            if self.is_leaf(left) {
+                let values = std::mem::take(self.values_mut(right));
+                let next = self.next(right);
+                self.values_mut(left).extend(values);
+                *self.next_mut(left) = next;
+                if self.last_leaf == right {
+                    self.last_leaf = left;
+                }
+            } else {
+                let (children, sizes) = self.internal_mut(right);
+                let (children, sizes) = (std::mem::take(children), std::mem::take(sizes));
+                let (left_children, left_sizes) = self.internal_mut(left);
+                left_children.extend(children);
+                left_sizes.extend(sizes);
+            }
+
+            let (children, sizes) = self.internal_mut(parent);
+            children.remove(slot + 1);
+            let size = sizes.remove(slot + 1);
+            sizes[slot] += size;
+            self.release(right);
+            return;
+        }
+
+        let keep = total / 2;
+        let left_len = self.node_len(left);
+        if self.is_leaf(left) {
+            if left_len > keep {
+                let moved = self.values_mut(left).split_off(keep);
+                self.values_mut(right).splice(0..0, moved);
+            } else {
+                let moved = self
+                    .values_mut(right)
+                    .drain(..keep - left_len)
+                    .collect::<Vec<_>>();
+                self.values_mut(left).extend(moved);
+            }
+        } else if left_len > keep {
+            let (children, sizes) = self.internal_mut(left);
+            let (children, sizes) = (children.split_off(keep), sizes.split_off(keep));
+            let (right_children, right_sizes) = self.internal_mut(right);
+            right_children.splice(0..0, children);
+            right_sizes.splice(0..0, sizes);
+        } else {
+            let (children, sizes) = self.internal_mut(right);
+            let children = children.drain(..keep - left_len).collect::<Vec<_>>();
+            let sizes = sizes.drain(..keep - left_len).collect::<Vec<_>>();
+            let (left_children, left_sizes) = self.internal_mut(left);
+            left_children.extend(children);
+            left_sizes.extend(sizes);
+        }
+
+        let (left_size, right_size) = (self.subtree_len(left), self.subtree_len(right));
+        let sizes = self.internal_mut(parent).1;
+        sizes[slot] = left_size;
+        sizes[slot + 1] = right_size;
+    }
+
+    /// Refills the nodes along the right edge after `split_off` cut them short. An
+    /// underfull node without a left sibling is an only child, which is evened out
+    /// once its parent has been.
+    fn rebalance_right_spine(&mut self) {
+        'retry: loop {
+            let mut path = self.right_spine();
+            let mut node = self.last_leaf;
+            while let Some((parent, slot)) = path.pop() {
+                if self.node_len(node) < MIN && slot > 0 {
+                    self.rebalance(parent, slot - 1);
+                    continue 'retry;
+                }
+                node = parent;
+            }
+
+            if self.height > 0 && self.children(self.root).len() == 1 {
+                self.shrink_root();
+                continue;
+            }
+
+            return;
+        }
+    }
+
+    /// Drops root levels that only have a single child.
+    fn shrink_root(&mut self) {
+        while self.height > 0 && self.children(self.root).len() == 1 {
+            let old_root = self.root;
+            self.root = self.children(old_root)[0];
+            self.height -= 1;
+            self.release(old_root);
+        }
+    }
+
+    fn node_mut(&mut self, idx: NodeIdx) -> &mut NodeData<T> {
+        Arc::make_mut(&mut self.nodes[idx])
+    }
+
+    fn internal_mut(&mut self, idx: NodeIdx) -> (&mut Vec<NodeIdx>, &mut Vec<usize>) {
+        match self.node_mut(idx) {
+            NodeData::Internal { children, sizes } => (children, sizes),
+            NodeData::Leaf { .. } => unreachable!(),
+        }
+    }
+
+    fn values_mut(&mut self, idx: NodeIdx) -> &mut Vec<T> {
+        match self.node_mut(idx) {
+            NodeData::Leaf { values, .. } => values,
+            NodeData::Internal { .. } => unreachable!(),
+        }
+    }
+
+    fn next_mut(&mut self, idx: NodeIdx) -> &mut Option<NodeIdx> {
+        match self.node_mut(idx) {
+            NodeData::Leaf { next, .. } => next,
+            NodeData::Internal { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<T> Index<usize> for RadixLinkTree<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+impl<T: Clone> IndexMut<usize> for RadixLinkTree<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).expect("index out of bounds")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RadixLinkTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for RadixLinkTree<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for RadixLinkTree<T> {}
+
+impl<T: Clone> Extend<T> for RadixLinkTree<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        loop {
+            let values = iter.by_ref().take(WIDTH).collect::<Vec<_>>();
+            if values.is_empty() {
+                return;
+            }
+            self.push_leaf(values);
+        }
+    }
+}
+
+impl<T: Clone> FromIterator<T> for RadixLinkTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+pub struct Iter<'a, T> {
+    tree: &'a RadixLinkTree<T>,
+    leaf: Option<NodeIdx>,
+    values: std::slice::Iter<'a, T>,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        loop {
+            if let Some(value) = self.values.next() {
+                self.remaining -= 1;
+                return Some(value);
+            }
+
+            let leaf = self.leaf?;
+            self.values = self.tree.values(leaf).iter();
+            self.leaf = self.tree.next(leaf);
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<'a, T> IntoIterator for &'a RadixLinkTree<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct IntoIter<T> {
+    tree: RadixLinkTree<T>,
+    leaf: Option<NodeIdx>,
+    values: std::vec::IntoIter<T>,
+}
+
+impl<T: Clone> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(value) = self.values.next() {
+                self.tree.len -= 1;
+                return Some(value);
+            }
+
+            let leaf = self.leaf?;
+            self.values = std::mem::take(self.tree.values_mut(leaf)).into_iter();
+            self.leaf = self.tree.next(leaf);
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.tree.len, Some(self.tree.len))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for IntoIter<T> {}
+
+impl<T: Clone> IntoIterator for RadixLinkTree<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            leaf: Some(self.first_leaf),
+            tree: self,
+            values: Vec::new().into_iter(),
+        }
+    }
+}
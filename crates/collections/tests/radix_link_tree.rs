@@ -0,0 +1,153 @@
+use collections::RadixLinkTree;
+use proptest::prelude::*;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Push(u32),
+    Pop,
+    Insert(usize, u32),
+    Remove(usize),
+    Set(usize, u32),
+    SplitAppend(usize),
+    Truncate(usize),
+    Swap(usize, usize),
+    Range(usize, usize),
+    Retain(u32),
+    Snapshot,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => any::<u32>().prop_map(Op::Push),
+        1 => Just(Op::Pop),
+        3 => (any::<usize>(), any::<u32>()).prop_map(|(i, v)| Op::Insert(i, v)),
+        2 => any::<usize>().prop_map(Op::Remove),
+        1 => (any::<usize>(), any::<u32>()).prop_map(|(i, v)| Op::Set(i, v)),
+        1 => any::<usize>().prop_map(Op::SplitAppend),
+        1 => any::<usize>().prop_map(Op::Truncate),
+        1 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Swap(a, b)),
+        1 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Range(a, b)),
+        1 => (2..8u32).prop_map(Op::Retain),
+        1 => Just(Op::Snapshot),
+    ]
+}
+
+fn assert_same(tree: &RadixLinkTree<u32>, vec: &[u32]) {
+    assert_eq!(tree.len(), vec.len());
+    assert_eq!(tree.iter().len(), vec.len());
+    assert!(tree.iter().eq(vec.iter()));
+    for (i, v) in vec.iter().enumerate() {
+        assert_eq!(tree.get(i), Some(v));
+    }
+    assert_eq!(tree.get(vec.len()), None);
+    assert_eq!(tree.last(), vec.last());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn behaves_like_vec(
+        init in prop::collection::vec(any::<u32>(), 0..5000),
+        ops in prop::collection::vec(op(), 0..200),
+    ) {
+        let mut tree = init.iter().copied().collect::<RadixLinkTree<_>>();
+        let mut vec = init;
+        let mut snapshots = vec![];
+        assert_same(&tree, &vec);
+
+        for op in ops {
+            match op {
+                Op::Push(v) => {
+                    tree.push(v);
+                    vec.push(v);
+                }
+                Op::Pop => prop_assert_eq!(tree.pop(), vec.pop()),
+                Op::Insert(i, v) => {
+                    let i = i % (vec.len() + 1);
+                    tree.insert(i, v);
+                    vec.insert(i, v);
+                }
+                Op::Remove(i) if !vec.is_empty() => {
+                    let i = i % vec.len();
+                    prop_assert_eq!(tree.remove(i), vec.remove(i));
+                }
+                Op::Remove(_) => {}
+                Op::Set(i, v) if !vec.is_empty() => {
+                    let i = i % vec.len();
+                    tree[i] = v;
+                    vec[i] = v;
+                }
+                Op::Set(..) => {}
+                Op::SplitAppend(at) => {
+                    let at = at % (vec.len() + 1);
+                    let mut tail = tree.split_off(at);
+                    let vec_tail = vec.split_off(at);
+                    assert_same(&tree, &vec);
+                    assert_same(&tail, &vec_tail);
+
+                    tree.append(&mut tail);
+                    vec.extend(vec_tail);
+                    prop_assert!(tail.is_empty());
+                }
+                Op::Truncate(len) => {
+                    let len = len % (vec.len() + 1);
+                    tree.truncate(len);
+                    vec.truncate(len);
+                }
+                Op::Swap(a, b) if !vec.is_empty() => {
+                    let (a, b) = (a % vec.len(), b % vec.len());
+                    tree.swap(a, b);
+                    vec.swap(a, b);
+                }
+                Op::Swap(..) => {}
+                Op::Range(a, b) => {
+                    let (a, b) = (a % (vec.len() + 1), b % (vec.len() + 1));
+                    let (start, end) = (a.min(b), a.max(b));
+                    prop_assert_eq!(tree.range(start..end).len(), end - start);
+                    prop_assert!(tree.range(start..end).eq(vec[start..end].iter()));
+                }
+                Op::Retain(n) => {
+                    tree.retain(|v| v % n != 0);
+                    vec.retain(|v| v % n != 0);
+                }
+                Op::Snapshot => snapshots.push((tree.clone(), vec.clone())),
+            }
+
+            assert_same(&tree, &vec);
+        }
+
+        // Changing the tree after cloning it leaves the clones as they were.
+        for (snapshot, vec) in &snapshots {
+            assert_same(snapshot, vec);
+        }
+
+        let cloned = tree.clone();
+        prop_assert_eq!(&cloned, &tree);
+        prop_assert!(tree.into_iter().eq(vec));
+    }
+}
+
+#[test]
+fn grows_and_shrinks_across_levels() {
+    const LEN: usize = 64 * 64 * 2 + 1;
+
+    let mut tree = RadixLinkTree::new();
+    for i in 0..LEN {
+        tree.push(i);
+    }
+    assert!(tree.iter().copied().eq(0..LEN));
+
+    tree.insert(0, usize::MAX);
+    assert_eq!(tree[0], usize::MAX);
+    assert_eq!(tree[LEN], LEN - 1);
+    assert_eq!(tree.remove(0), usize::MAX);
+
+    while let Some(v) = tree.pop() {
+        assert_eq!(v, tree.len());
+    }
+    assert!(tree.is_empty());
+
+    tree.push(7);
+    assert_eq!(tree.first(), Some(&7));
+}
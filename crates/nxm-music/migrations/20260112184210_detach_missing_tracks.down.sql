@@ -0,0 +1,21 @@
+-- Add down migration script here
+CREATE TABLE IF NOT EXISTS tracks_old (
+    id          BLOB(16) NOT NULL,
+
+    filenode_id BLOB(16) NOT NULL,
+
+    artist      TEXT,
+    title       TEXT,
+
+    PRIMARY KEY (id),
+
+    FOREIGN KEY (filenode_id) REFERENCES filenodes (id) ON DELETE CASCADE
+);
+
+INSERT INTO tracks_old (id, filenode_id, artist, title)
+SELECT id, filenode_id, artist, title FROM tracks
+WHERE filenode_id IS NOT NULL;
+
+DROP TABLE tracks;
+
+ALTER TABLE tracks_old RENAME TO tracks;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+-- SQLite can't alter a foreign key, so `tracks` is rebuilt with a nullable
+-- `filenode_id`. A track whose file was deleted keeps its row with `filenode_id`
+-- set to NULL, see `SyncOptions::keep_missing_tracks`.
+CREATE TABLE IF NOT EXISTS tracks_new (
+    id          BLOB(16) NOT NULL,
+
+    filenode_id BLOB(16),
+
+    artist      TEXT,
+    title       TEXT,
+
+    PRIMARY KEY (id),
+
+    FOREIGN KEY (filenode_id) REFERENCES filenodes (id) ON DELETE SET NULL
+);
+
+INSERT INTO tracks_new (id, filenode_id, artist, title)
+SELECT id, filenode_id, artist, title FROM tracks;
+
+DROP TABLE tracks;
+
+ALTER TABLE tracks_new RENAME TO tracks;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_filenode ON tracks (filenode_id);
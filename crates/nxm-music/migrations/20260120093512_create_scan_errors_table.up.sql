@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- Files the last scan covering them couldn't read. Rows are replaced whenever their
+-- directory is rescanned, so this always describes the library as it is now.
+CREATE TABLE IF NOT EXISTS scan_errors (
+    path    TEXT NOT NULL,
+
+    kind    TEXT NOT NULL CHECK (kind IN ('walk', 'metadata', 'open', 'tags')),
+    message TEXT NOT NULL,
+
+    PRIMARY KEY (path)
+);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_audio_hash;
+DROP INDEX IF EXISTS idx_filenodes_audio_hash;
+
+ALTER TABLE tracks DROP COLUMN audio_hash;
+
+ALTER TABLE filenodes DROP COLUMN meta_hash;
+ALTER TABLE filenodes DROP COLUMN audio_hash;
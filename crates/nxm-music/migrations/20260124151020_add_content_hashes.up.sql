@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- BLAKE3 hashes of a file's audio packets and of its tags, used to follow files
+-- across copies, re-downloads and retagging. See `FileHashes`.
+ALTER TABLE filenodes ADD COLUMN audio_hash BLOB(32);
+ALTER TABLE filenodes ADD COLUMN meta_hash BLOB(32);
+
+-- Kept on the track as well, so that a track detached from its file can be matched
+-- with the file once it's back.
+ALTER TABLE tracks ADD COLUMN audio_hash BLOB(32);
+
+CREATE INDEX IF NOT EXISTS idx_filenodes_audio_hash ON filenodes (audio_hash);
+CREATE INDEX IF NOT EXISTS idx_tracks_audio_hash ON tracks (audio_hash);
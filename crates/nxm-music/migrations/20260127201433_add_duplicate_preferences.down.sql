@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_duplicate_of;
+
+ALTER TABLE tracks DROP COLUMN duplicate_of;
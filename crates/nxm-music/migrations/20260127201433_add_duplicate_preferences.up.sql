@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- Set on the copies of a track that were hidden in favour of it, see
+-- `PersistenceState::prefer_duplicate`.
+ALTER TABLE tracks ADD COLUMN duplicate_of BLOB(16) REFERENCES tracks (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_duplicate_of ON tracks (duplicate_of);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE tracks DROP COLUMN codec;
+ALTER TABLE tracks DROP COLUMN container;
+
+ALTER TABLE libraries DROP COLUMN exclude_extensions;
+ALTER TABLE libraries DROP COLUMN include_extensions;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Comma separated extensions, see `ExtensionFilter`. Libraries without an include
+-- list scan `DEFAULT_EXTENSIONS`.
+ALTER TABLE libraries ADD COLUMN include_extensions TEXT;
+ALTER TABLE libraries ADD COLUMN exclude_extensions TEXT;
+
+-- As detected by the scan, see `AudioFormat`.
+ALTER TABLE tracks ADD COLUMN container TEXT;
+ALTER TABLE tracks ADD COLUMN codec TEXT;
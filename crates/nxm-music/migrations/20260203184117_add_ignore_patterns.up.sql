@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Newline separated globs, relative to the library root. See `IgnoreRules`.
+ALTER TABLE libraries ADD COLUMN ignore_patterns TEXT;
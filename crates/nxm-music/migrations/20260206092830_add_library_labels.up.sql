@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE libraries ADD COLUMN label TEXT;
@@ -0,0 +1,12 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_album;
+
+ALTER TABLE tracks DROP COLUMN composer;
+ALTER TABLE tracks DROP COLUMN genre;
+ALTER TABLE tracks DROP COLUMN year;
+ALTER TABLE tracks DROP COLUMN disc_total;
+ALTER TABLE tracks DROP COLUMN disc_number;
+ALTER TABLE tracks DROP COLUMN track_total;
+ALTER TABLE tracks DROP COLUMN track_number;
+ALTER TABLE tracks DROP COLUMN album_artist;
+ALTER TABLE tracks DROP COLUMN album;
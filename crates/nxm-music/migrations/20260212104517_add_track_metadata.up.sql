@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- As read by the scan, see `TrackTags`.
+ALTER TABLE tracks ADD COLUMN album TEXT;
+ALTER TABLE tracks ADD COLUMN album_artist TEXT;
+ALTER TABLE tracks ADD COLUMN track_number INTEGER;
+ALTER TABLE tracks ADD COLUMN track_total INTEGER;
+ALTER TABLE tracks ADD COLUMN disc_number INTEGER;
+ALTER TABLE tracks ADD COLUMN disc_total INTEGER;
+ALTER TABLE tracks ADD COLUMN year INTEGER;
+ALTER TABLE tracks ADD COLUMN genre TEXT;
+ALTER TABLE tracks ADD COLUMN composer TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_album ON tracks (album, album_artist);
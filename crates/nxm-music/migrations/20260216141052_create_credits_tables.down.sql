@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS track_genres;
+DROP TABLE IF EXISTS genres;
+DROP TABLE IF EXISTS artist_tracks;
+DROP TABLE IF EXISTS artists;
@@ -0,0 +1,53 @@
+-- Add up migration script here
+-- Artists and genres as credited by the tags of each track, see `read_credits` and
+-- `read_genres`. Rows no track refers to are deleted after every scan.
+CREATE TABLE IF NOT EXISTS artists (
+    id   BLOB(16) NOT NULL,
+
+    name TEXT NOT NULL COLLATE NOCASE,
+
+    PRIMARY KEY (id),
+    UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS artist_tracks (
+    artist_id BLOB(16) NOT NULL,
+    track_id  BLOB(16) NOT NULL,
+
+    role      INTEGER NOT NULL CHECK (
+        -- 1=Creator
+        -- 2=Feature
+        -- 3=Remixer
+        -- 4=Vocalist
+        -- 5=Composer
+        role IN (1, 2, 3, 4, 5)
+    ),
+
+    PRIMARY KEY (artist_id, track_id, role),
+
+    FOREIGN KEY (artist_id) REFERENCES artists (id) ON DELETE CASCADE,
+    FOREIGN KEY (track_id) REFERENCES tracks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_artist_tracks_track ON artist_tracks (track_id);
+
+CREATE TABLE IF NOT EXISTS genres (
+    id   INTEGER NOT NULL,
+
+    name TEXT NOT NULL COLLATE NOCASE,
+
+    PRIMARY KEY (id AUTOINCREMENT),
+    UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS track_genres (
+    track_id BLOB(16) NOT NULL,
+    genre_id INTEGER NOT NULL,
+
+    PRIMARY KEY (track_id, genre_id),
+
+    FOREIGN KEY (track_id) REFERENCES tracks (id) ON DELETE CASCADE,
+    FOREIGN KEY (genre_id) REFERENCES genres (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_track_genres_genre ON track_genres (genre_id);
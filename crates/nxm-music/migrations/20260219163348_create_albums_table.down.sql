@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_album_id;
+
+ALTER TABLE tracks DROP COLUMN album_id;
+ALTER TABLE tracks DROP COLUMN album_key;
+ALTER TABLE tracks DROP COLUMN duration_ms;
+ALTER TABLE tracks DROP COLUMN has_picture;
+ALTER TABLE tracks DROP COLUMN compilation;
+ALTER TABLE tracks DROP COLUMN musicbrainz_album_id;
+
+DROP TABLE IF EXISTS albums;
@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- Tracks grouped by `tracks.album_key`, see `album_key`. Everything but the key is
+-- refreshed from the tracks after every scan, see `sync_albums`.
+CREATE TABLE IF NOT EXISTS albums (
+    id           BLOB(16) NOT NULL,
+
+    grouping_key TEXT NOT NULL,
+    title        TEXT NOT NULL,
+    artist       TEXT,
+    compilation  BOOLEAN NOT NULL DEFAULT FALSE,
+    year         INTEGER,
+
+    PRIMARY KEY (id),
+    UNIQUE (grouping_key)
+);
+
+ALTER TABLE tracks ADD COLUMN musicbrainz_album_id TEXT;
+ALTER TABLE tracks ADD COLUMN compilation BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE tracks ADD COLUMN has_picture BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE tracks ADD COLUMN duration_ms INTEGER;
+ALTER TABLE tracks ADD COLUMN album_key TEXT;
+ALTER TABLE tracks ADD COLUMN album_id BLOB(16) REFERENCES albums (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_album_id ON tracks (album_id);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_artwork_hash;
+
+ALTER TABLE tracks DROP COLUMN artwork_hash;
+
+DROP TABLE IF EXISTS folder_artworks;
+DROP TABLE IF EXISTS artworks;
@@ -0,0 +1,32 @@
+-- Add up migration script here
+-- Pictures in the artwork cache, keyed by the BLAKE3 hash of their bytes, see
+-- `ArtworkCache`. Rows nothing refers to are deleted after every scan, see
+-- `delete_unused_artwork`.
+CREATE TABLE IF NOT EXISTS artworks (
+    hash   BLOB(32) NOT NULL,
+
+    mime   TEXT     NOT NULL,
+    width  INTEGER  NOT NULL,
+    height INTEGER  NOT NULL,
+
+    PRIMARY KEY (hash)
+);
+
+-- The `cover.jpg` or the like of a directory, with the size and mtime it had when it
+-- was read.
+CREATE TABLE IF NOT EXISTS folder_artworks (
+    filenode_id  BLOB(16) NOT NULL,
+
+    name         TEXT     NOT NULL,
+    mtime        INTEGER  NOT NULL,
+    size         INTEGER  NOT NULL,
+    artwork_hash BLOB(32) NOT NULL,
+
+    PRIMARY KEY (filenode_id),
+    FOREIGN KEY (filenode_id) REFERENCES filenodes (id) ON DELETE CASCADE,
+    FOREIGN KEY (artwork_hash) REFERENCES artworks (hash)
+);
+
+ALTER TABLE tracks ADD COLUMN artwork_hash BLOB(32) REFERENCES artworks (hash) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_artwork_hash ON tracks (artwork_hash);
@@ -0,0 +1,27 @@
+-- Add up migration script here
+-- The undo log of tag edits written to files, see `edit_tags`.
+CREATE TABLE IF NOT EXISTS tag_edits (
+    id         BLOB(16) NOT NULL,
+
+    created_at INTEGER  NOT NULL,
+    undone     BOOLEAN  NOT NULL DEFAULT FALSE,
+
+    PRIMARY KEY (id)
+);
+
+-- The fields an edit changed, with what they were before.
+CREATE TABLE IF NOT EXISTS tag_edit_changes (
+    edit_id   BLOB(16) NOT NULL,
+    track_id  BLOB(16) NOT NULL,
+    field     TEXT     NOT NULL CHECK (field IN (
+        'artist', 'title', 'album', 'album_artist', 'track_number', 'track_total',
+        'disc_number', 'disc_total', 'year', 'genre', 'composer'
+    )),
+
+    old_value TEXT,
+    new_value TEXT,
+
+    PRIMARY KEY (edit_id, track_id, field),
+    FOREIGN KEY (edit_id) REFERENCES tag_edits (id) ON DELETE CASCADE,
+    FOREIGN KEY (track_id) REFERENCES tracks (id) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS artists_fts_delete;
+DROP TRIGGER IF EXISTS artists_fts_update;
+DROP TRIGGER IF EXISTS artists_fts_insert;
+DROP TRIGGER IF EXISTS albums_fts_delete;
+DROP TRIGGER IF EXISTS albums_fts_update;
+DROP TRIGGER IF EXISTS albums_fts_insert;
+DROP TRIGGER IF EXISTS filenodes_fts_update;
+DROP TRIGGER IF EXISTS tracks_fts_delete;
+DROP TRIGGER IF EXISTS tracks_fts_update;
+DROP TRIGGER IF EXISTS tracks_fts_insert;
+
+DROP TABLE IF EXISTS artists_fts;
+DROP TABLE IF EXISTS albums_fts;
+DROP TABLE IF EXISTS tracks_fts;
+
+DROP TABLE IF EXISTS artists_fts_keys;
+DROP TABLE IF EXISTS albums_fts_keys;
+DROP TABLE IF EXISTS tracks_fts_keys;
@@ -0,0 +1,178 @@
+-- Add up migration script here
+-- Full-text indexes of tracks, albums and artists, see `search`. The trigram tokenizer
+-- matches any part of a word, which is what typo tolerance is built on. Values are
+-- padded with spaces, so that the trigrams of the first and last word of a value
+-- include the start and end of the word, like those of the other words do.
+--
+-- FTS5 rows are keyed by an integer rowid, but the indexed tables have BLOB primary
+-- keys whose implicit rowids `VACUUM` may renumber. Each indexed row is given an
+-- `INTEGER PRIMARY KEY` in a `*_fts_keys` table instead, which the index rows share
+-- and which is kept in sync with the triggers below.
+CREATE TABLE IF NOT EXISTS tracks_fts_keys (
+    key      INTEGER PRIMARY KEY,
+    track_id BLOB(16) NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS albums_fts_keys (
+    key      INTEGER PRIMARY KEY,
+    album_id BLOB(16) NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS artists_fts_keys (
+    key       INTEGER PRIMARY KEY,
+    artist_id BLOB(16) NOT NULL UNIQUE
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS tracks_fts USING fts5 (
+    title,
+    artist,
+    album,
+    genre,
+    filename,
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS albums_fts USING fts5 (
+    title,
+    artist,
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS artists_fts USING fts5 (
+    name,
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+-- Tracks are only indexed while they have a file, but keep their key either way.
+CREATE TRIGGER IF NOT EXISTS tracks_fts_insert AFTER INSERT ON tracks BEGIN
+    INSERT INTO tracks_fts_keys (track_id) VALUES (NEW.id);
+    INSERT INTO tracks_fts (rowid, title, artist, album, genre, filename)
+    SELECT
+        k.key,
+        ' ' || NEW.title || ' ',
+        ' ' || NEW.artist || ' ',
+        ' ' || NEW.album || ' ',
+        ' ' || NEW.genre || ' ',
+        ' ' || f.name || ' '
+    FROM tracks_fts_keys k
+    INNER JOIN filenodes f
+        ON f.id = NEW.filenode_id
+    WHERE k.track_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS tracks_fts_update
+AFTER UPDATE OF title, artist, album, genre, filenode_id ON tracks BEGIN
+    DELETE FROM tracks_fts
+    WHERE rowid = (SELECT key FROM tracks_fts_keys WHERE track_id = OLD.id);
+    INSERT INTO tracks_fts (rowid, title, artist, album, genre, filename)
+    SELECT
+        k.key,
+        ' ' || NEW.title || ' ',
+        ' ' || NEW.artist || ' ',
+        ' ' || NEW.album || ' ',
+        ' ' || NEW.genre || ' ',
+        ' ' || f.name || ' '
+    FROM tracks_fts_keys k
+    INNER JOIN filenodes f
+        ON f.id = NEW.filenode_id
+    WHERE k.track_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS tracks_fts_delete AFTER DELETE ON tracks BEGIN
+    DELETE FROM tracks_fts
+    WHERE rowid = (SELECT key FROM tracks_fts_keys WHERE track_id = OLD.id);
+    DELETE FROM tracks_fts_keys WHERE track_id = OLD.id;
+END;
+
+-- Renamed files.
+CREATE TRIGGER IF NOT EXISTS filenodes_fts_update AFTER UPDATE OF name ON filenodes BEGIN
+    UPDATE tracks_fts
+    SET filename = ' ' || NEW.name || ' '
+    WHERE rowid IN (
+        SELECT k.key
+        FROM tracks t
+        INNER JOIN tracks_fts_keys k
+            ON k.track_id = t.id
+        WHERE t.filenode_id = NEW.id
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS albums_fts_insert AFTER INSERT ON albums BEGIN
+    INSERT INTO albums_fts_keys (album_id) VALUES (NEW.id);
+    INSERT INTO albums_fts (rowid, title, artist)
+    SELECT key, ' ' || NEW.title || ' ', ' ' || NEW.artist || ' '
+    FROM albums_fts_keys
+    WHERE album_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS albums_fts_update AFTER UPDATE OF title, artist ON albums BEGIN
+    UPDATE albums_fts
+    SET
+        title = ' ' || NEW.title || ' ',
+        artist = ' ' || NEW.artist || ' '
+    WHERE rowid = (SELECT key FROM albums_fts_keys WHERE album_id = NEW.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS albums_fts_delete AFTER DELETE ON albums BEGIN
+    DELETE FROM albums_fts
+    WHERE rowid = (SELECT key FROM albums_fts_keys WHERE album_id = OLD.id);
+    DELETE FROM albums_fts_keys WHERE album_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS artists_fts_insert AFTER INSERT ON artists BEGIN
+    INSERT INTO artists_fts_keys (artist_id) VALUES (NEW.id);
+    INSERT INTO artists_fts (rowid, name)
+    SELECT key, ' ' || NEW.name || ' '
+    FROM artists_fts_keys
+    WHERE artist_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS artists_fts_update AFTER UPDATE OF name ON artists BEGIN
+    UPDATE artists_fts
+    SET name = ' ' || NEW.name || ' '
+    WHERE rowid = (SELECT key FROM artists_fts_keys WHERE artist_id = NEW.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS artists_fts_delete AFTER DELETE ON artists BEGIN
+    DELETE FROM artists_fts
+    WHERE rowid = (SELECT key FROM artists_fts_keys WHERE artist_id = OLD.id);
+    DELETE FROM artists_fts_keys WHERE artist_id = OLD.id;
+END;
+
+INSERT INTO tracks_fts_keys (track_id)
+SELECT id
+FROM tracks;
+
+INSERT INTO albums_fts_keys (album_id)
+SELECT id
+FROM albums;
+
+INSERT INTO artists_fts_keys (artist_id)
+SELECT id
+FROM artists;
+
+INSERT INTO tracks_fts (rowid, title, artist, album, genre, filename)
+SELECT
+    k.key,
+    ' ' || t.title || ' ',
+    ' ' || t.artist || ' ',
+    ' ' || t.album || ' ',
+    ' ' || t.genre || ' ',
+    ' ' || f.name || ' '
+FROM tracks t
+INNER JOIN tracks_fts_keys k
+    ON k.track_id = t.id
+INNER JOIN filenodes f
+    ON f.id = t.filenode_id;
+
+INSERT INTO albums_fts (rowid, title, artist)
+SELECT k.key, ' ' || a.title || ' ', ' ' || a.artist || ' '
+FROM albums a
+INNER JOIN albums_fts_keys k
+    ON k.album_id = a.id;
+
+INSERT INTO artists_fts (rowid, name)
+SELECT k.key, ' ' || a.name || ' '
+FROM artists a
+INNER JOIN artists_fts_keys k
+    ON k.artist_id = a.id;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_tracks_added_at;
+
+ALTER TABLE tracks DROP COLUMN added_at;
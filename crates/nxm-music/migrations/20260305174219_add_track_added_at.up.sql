@@ -0,0 +1,13 @@
+-- Add up migration script here
+ALTER TABLE tracks ADD COLUMN added_at TEXT;
+
+-- Tracks that are already in the library were added when their file last changed,
+-- as far as we know.
+UPDATE tracks
+SET added_at = (
+    SELECT f.mtime
+    FROM filenodes f
+    WHERE f.id = tracks.filenode_id
+);
+
+CREATE INDEX IF NOT EXISTS idx_tracks_added_at ON tracks (added_at);
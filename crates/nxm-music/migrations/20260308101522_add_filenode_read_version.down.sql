@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE filenodes DROP COLUMN read_version;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- The `READ_VERSION` a file was last read with. Files indexed before are read again
+-- by the next scan, which fills in their formats, tags, credits, albums and pictures.
+ALTER TABLE filenodes ADD COLUMN read_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP VIEW IF EXISTS visible_tracks;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- The tracks that are listed and queued: indexed ones, less the copies hidden in
+-- favor of a preferred duplicate that is indexed itself.
+CREATE VIEW IF NOT EXISTS visible_tracks AS
+SELECT t.*
+FROM tracks t
+WHERE t.filenode_id IS NOT NULL
+    AND t.id NOT IN (
+        SELECT h.id
+        FROM tracks h
+        INNER JOIN tracks p
+            ON p.id = h.duplicate_of
+        WHERE p.filenode_id IS NOT NULL
+    );
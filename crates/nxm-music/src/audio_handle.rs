@@ -1,4 +1,6 @@
 use std::{
+    ops::Range,
+    path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
@@ -13,16 +15,26 @@ use cpal::{
 };
 use creek::{ReadDiskStream, SymphoniaDecoder};
 use fixed_resample::FixedResampler;
+use uuid::Uuid;
 
 use crate::{
-    io_thread,
-    server::{ControllerMsg, MainStreamMsg, UserMainMsg, main_thread},
+    Album, Artist, ArtistCredit, ArtworkImage, ArtworkOwner, BrowseMsg, DuplicateGroup,
+    ExtensionFilter, Genre, Library, LibraryMsg, MainIoMsg, PlayContext, ScanError, ScanJob,
+    ScanTarget, SearchResults, TagEdit, TagEditEntry, TagEditMsg, TagEditOutcome, TrackCursor,
+    TrackPage, TrackQuery, io_thread,
+    queue::{QueueId, QueueInfo, QueueWindow},
+    server::{ControllerMsg, MainStreamMsg, QueueMsg, UserMainMsg, main_thread},
 };
 
 pub struct AudioHandle {
     user_main_tx: crossbeam_channel::Sender<UserMainMsg>,
+    main_io_tx: flume::Sender<MainIoMsg>,
+    #[allow(dead_code)]
     main_join_handle: JoinHandle<anyhow::Result<()>>,
+    /// Runs the io thread for as long as the handle lives.
+    #[allow(dead_code)]
     rt: tokio::runtime::Runtime,
+    #[allow(dead_code)]
     events: Option<flume::Receiver<()>>,
 }
 
@@ -41,18 +53,21 @@ impl AudioHandle {
         let (user_main_tx, user_main_rx) = crossbeam_channel::unbounded();
 
         let (main_io_tx, main_io_rx) = flume::unbounded();
+        let (io_main_tx, io_main_rx) = crossbeam_channel::unbounded();
 
-        let _io_join_handle = rt.spawn(io_thread(main_io_rx));
+        let _io_join_handle = rt.spawn(io_thread(main_io_rx, main_io_tx.clone(), io_main_tx));
 
         let main_join_handle = std::thread::spawn({
+            let main_io_tx = main_io_tx.clone();
             move || {
-                main_thread(user_main_rx, main_io_tx)?;
+                main_thread(user_main_rx, main_io_tx, io_main_rx)?;
                 anyhow::Ok(())
             }
         });
 
         AudioHandle {
             user_main_tx,
+            main_io_tx,
             main_join_handle,
             rt,
             events: None,
@@ -75,6 +90,380 @@ impl AudioHandle {
         anyhow::Ok(())
     }
 
+    pub fn create_queue(&self, name: impl Into<String>) -> anyhow::Result<QueueId> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Create {
+                name: name.into(),
+                reply: tx,
+            }))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Create"))?;
+
+        Ok(rx.recv()?)
+    }
+
+    pub fn switch_queue(&self, id: QueueId) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Switch(id)))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Switch"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn rename_queue(&self, id: QueueId, name: impl Into<String>) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Rename {
+                id,
+                name: name.into(),
+            }))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Rename"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn remove_queue(&self, id: QueueId) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Remove(id)))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Remove"))?;
+
+        anyhow::Ok(())
+    }
+
+    /// Replaces the active queue with the tracks of `context` and starts playing at
+    /// `start` (or the first track of the context).
+    pub fn play_context(&self, context: PlayContext, start: Option<Uuid>) -> anyhow::Result<()> {
+        let tracks = match context {
+            PlayContext::Tracks(tracks) => tracks,
+            context => {
+                let (tx, rx) = flume::bounded(1);
+
+                self.main_io_tx
+                    .try_send(MainIoMsg::ResolveContext { context, reply: tx })
+                    .map_err(|_| anyhow::anyhow!("MainIoMsg::ResolveContext"))?;
+
+                rx.recv()??
+            }
+        };
+
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Replace { tracks, start }))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Replace"))?;
+
+        anyhow::Ok(())
+    }
+
+    /// Rescans `target` in the background, changes reach the library once it's done.
+    /// Only one such scan runs at a time, the job of another one fails right away.
+    pub fn scan(&self, target: ScanTarget) -> anyhow::Result<ScanJob> {
+        let (job, control) = ScanJob::new();
+
+        self.main_io_tx
+            .try_send(MainIoMsg::Scan { target, control })
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::Scan"))?;
+
+        Ok(job)
+    }
+
+    /// Files that couldn't be read as of the last scan that covered them.
+    pub fn scan_errors(&self) -> anyhow::Result<Vec<ScanError>> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::ScanErrors { reply: tx })
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::ScanErrors"))?;
+
+        rx.recv()?
+    }
+
+    pub fn libraries(&self) -> anyhow::Result<Vec<Library>> {
+        self.library_request(|reply| LibraryMsg::List { reply })
+    }
+
+    /// Registers the directory at `path` as a library and scans it. Fails with a
+    /// [`crate::LibraryPathError`] if it's not a directory or overlaps another library.
+    pub fn add_library(&self, path: PathBuf, label: Option<String>) -> anyhow::Result<Uuid> {
+        self.library_request(|reply| LibraryMsg::Add { path, label, reply })
+    }
+
+    /// Forgets a library and its tracks, leaving its files alone.
+    pub fn remove_library(&self, id: Uuid) -> anyhow::Result<()> {
+        self.library_request(|reply| LibraryMsg::Remove { id, reply })
+    }
+
+    pub fn relabel_library(&self, id: Uuid, label: Option<String>) -> anyhow::Result<()> {
+        self.library_request(|reply| LibraryMsg::Relabel { id, label, reply })
+    }
+
+    /// Points a library at `path`, e.g. after its drive was mounted somewhere else,
+    /// keeping the tracks of the files found under the same relative paths.
+    pub fn relocate_library(&self, id: Uuid, path: PathBuf) -> anyhow::Result<()> {
+        self.library_request(|reply| LibraryMsg::Relocate { id, path, reply })
+    }
+
+    /// Changes which files of a library are scanned, and rescans it.
+    pub fn set_extension_filter(
+        &self,
+        id: Uuid,
+        extensions: ExtensionFilter,
+    ) -> anyhow::Result<()> {
+        self.library_request(|reply| LibraryMsg::SetExtensionFilter {
+            id,
+            extensions,
+            reply,
+        })
+    }
+
+    /// Replaces the glob patterns of paths a library leaves out, and rescans it.
+    pub fn set_ignore_patterns(&self, id: Uuid, patterns: Vec<String>) -> anyhow::Result<()> {
+        self.library_request(|reply| LibraryMsg::SetIgnorePatterns {
+            id,
+            patterns,
+            reply,
+        })
+    }
+
+    fn library_request<T>(
+        &self,
+        msg: impl FnOnce(flume::Sender<anyhow::Result<T>>) -> LibraryMsg,
+    ) -> anyhow::Result<T> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::Library(msg(tx)))
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::Library"))?;
+
+        rx.recv()?
+    }
+
+    pub fn albums(&self) -> anyhow::Result<Vec<Album>> {
+        self.browse_request(|reply| BrowseMsg::Albums { reply })
+    }
+
+    pub fn album(&self, album_id: Uuid) -> anyhow::Result<Album> {
+        self.browse_request(|reply| BrowseMsg::Album { album_id, reply })
+    }
+
+    /// The tracks of `album_id` in disc/track order.
+    pub fn album_tracks(&self, album_id: Uuid) -> anyhow::Result<Vec<Uuid>> {
+        self.browse_request(|reply| BrowseMsg::AlbumTracks { album_id, reply })
+    }
+
+    pub fn artists(&self) -> anyhow::Result<Vec<Artist>> {
+        self.browse_request(|reply| BrowseMsg::Artists { reply })
+    }
+
+    /// Every track crediting `artist_id`, including features, remixes and
+    /// compositions.
+    pub fn artist_credits(&self, artist_id: Uuid) -> anyhow::Result<Vec<ArtistCredit>> {
+        self.browse_request(|reply| BrowseMsg::ArtistCredits { artist_id, reply })
+    }
+
+    /// The artwork of a track or album, with the path of its thumbnail for `size`, or
+    /// of the picture itself without one.
+    pub fn artwork(
+        &self,
+        owner: ArtworkOwner,
+        size: Option<u32>,
+    ) -> anyhow::Result<Option<ArtworkImage>> {
+        self.browse_request(|reply| BrowseMsg::Artwork { owner, size, reply })
+    }
+
+    pub fn genres(&self) -> anyhow::Result<Vec<Genre>> {
+        self.browse_request(|reply| BrowseMsg::Genres { reply })
+    }
+
+    pub fn genre_tracks(&self, genre_id: i64) -> anyhow::Result<Vec<Uuid>> {
+        self.browse_request(|reply| BrowseMsg::GenreTracks { genre_id, reply })
+    }
+
+    /// Tracks, albums and artists matching `query`, at most `limit` of each, best
+    /// first. Tolerates typos and ignores diacritics.
+    pub fn search(&self, query: String, limit: usize) -> anyhow::Result<SearchResults> {
+        self.browse_request(|reply| BrowseMsg::Search {
+            query,
+            limit,
+            reply,
+        })
+    }
+
+    /// The tracks matching `query`, in its order. See [`TrackQuery`] for the syntax.
+    pub fn query_tracks(&self, query: TrackQuery) -> anyhow::Result<Vec<Uuid>> {
+        self.browse_request(|reply| BrowseMsg::Query { query, reply })
+    }
+
+    /// Up to `limit` tracks matching `query`, in its order, starting after the `next`
+    /// cursor of a previous page, to list the library without loading all of it.
+    pub fn track_page(
+        &self,
+        query: TrackQuery,
+        after: Option<TrackCursor>,
+        limit: usize,
+    ) -> anyhow::Result<TrackPage> {
+        self.browse_request(|reply| BrowseMsg::TrackPage {
+            query,
+            after,
+            limit,
+            reply,
+        })
+    }
+
+    fn browse_request<T>(
+        &self,
+        msg: impl FnOnce(flume::Sender<anyhow::Result<T>>) -> BrowseMsg,
+    ) -> anyhow::Result<T> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::Browse(msg(tx)))
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::Browse"))?;
+
+        rx.recv()?
+    }
+
+    /// Sets tag fields of `track_ids` in their files, or only reports what would change
+    /// with `dry_run`.
+    pub fn edit_tags(
+        &self,
+        track_ids: Vec<Uuid>,
+        edits: Vec<TagEdit>,
+        dry_run: bool,
+    ) -> anyhow::Result<TagEditOutcome> {
+        self.tag_edit_request(|reply| TagEditMsg::Edit {
+            track_ids,
+            edits,
+            dry_run,
+            reply,
+        })
+    }
+
+    /// Sets the fields changed by a logged edit back to what they were.
+    pub fn undo_tag_edit(&self, id: Uuid) -> anyhow::Result<TagEditOutcome> {
+        self.tag_edit_request(|reply| TagEditMsg::Undo { id, reply })
+    }
+
+    /// The undo log, latest first.
+    pub fn tag_edits(&self) -> anyhow::Result<Vec<TagEditEntry>> {
+        self.tag_edit_request(|reply| TagEditMsg::History { reply })
+    }
+
+    fn tag_edit_request<T>(
+        &self,
+        msg: impl FnOnce(flume::Sender<anyhow::Result<T>>) -> TagEditMsg,
+    ) -> anyhow::Result<T> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::TagEdit(msg(tx)))
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::TagEdit"))?;
+
+        rx.recv()?
+    }
+
+    /// Tracks that are in the libraries more than once.
+    pub fn duplicates(&self) -> anyhow::Result<Vec<DuplicateGroup>> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::Duplicates { reply: tx })
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::Duplicates"))?;
+
+        rx.recv()?
+    }
+
+    /// Keeps `track_id` and hides its other copies from the library and the queues.
+    pub fn prefer_duplicate(&self, track_id: Uuid) -> anyhow::Result<()> {
+        self.send_prefer_duplicate(track_id, true)
+    }
+
+    /// Shows the copies hidden in favour of `track_id` again.
+    pub fn unhide_duplicates(&self, track_id: Uuid) -> anyhow::Result<()> {
+        self.send_prefer_duplicate(track_id, false)
+    }
+
+    fn send_prefer_duplicate(&self, track_id: Uuid, preferred: bool) -> anyhow::Result<()> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.main_io_tx
+            .try_send(MainIoMsg::PreferDuplicate {
+                track_id,
+                preferred,
+                reply: tx,
+            })
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::PreferDuplicate"))?;
+
+        rx.recv()?
+    }
+
+    pub fn remove_from_queue(&self, id: Uuid) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::RemoveTrack(id)))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::RemoveTrack"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn shuffle_queue(&self) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Shuffle))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Shuffle"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn clear_queue(&self) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Clear))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Clear"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn undo_queue(&self) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Undo))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Undo"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn redo_queue(&self) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Redo))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Redo"))?;
+
+        anyhow::Ok(())
+    }
+
+    pub fn play_position(&self, position: usize) -> anyhow::Result<()> {
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::PlayPosition(position)))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::PlayPosition"))?;
+
+        anyhow::Ok(())
+    }
+
+    /// The tracks of the active queue at the positions in `range`, clamped to its length.
+    pub fn queue_window(&self, range: Range<usize>) -> anyhow::Result<QueueWindow> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::Window { range, reply: tx }))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::Window"))?;
+
+        Ok(rx.recv()?)
+    }
+
+    pub fn queues(&self) -> anyhow::Result<Vec<QueueInfo>> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.user_main_tx
+            .try_send(UserMainMsg::Queue(QueueMsg::List { reply: tx }))
+            .map_err(|_| anyhow::anyhow!("QueueMsg::List"))?;
+
+        Ok(rx.recv()?)
+    }
+
     // pub fn pause(&self) {
     //     // let _ = self.user_main_tx.try_send(UserMainMsg::Pause);
     // }
@@ -298,8 +687,11 @@ impl AudioOutputController {
 #[derive(Default)]
 pub struct AudioOutputSharedState {
     pub playing: AtomicBool,
+    #[allow(dead_code)]
     pub position: AtomicU64,
+    #[allow(dead_code)]
     pub muted: AtomicBool,
+    #[allow(dead_code)]
     pub volume: AtomicU32, // AtomicF32, -1.0..=1.0
 }
 
@@ -309,6 +701,7 @@ pub struct AudioOutputController {
 }
 
 pub struct AudioDevice {
+    #[allow(dead_code)]
     device: cpal::Device,
     default_output_config: SupportedStreamConfig,
 }
@@ -371,6 +764,10 @@ impl AudioOutput {
         Ok(())
     }
 
+    pub fn is_playing(&self) -> bool {
+        self.shared_state.playing.load(Ordering::Relaxed)
+    }
+
     pub fn play_stream(&self) -> anyhow::Result<()> {
         self.controller.stream.play()?;
         self.shared_state.playing.store(true, Ordering::Relaxed);
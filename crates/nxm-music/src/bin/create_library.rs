@@ -1,6 +1,7 @@
-use std::{env, str::FromStr as _};
-use uuid::Uuid;
+use nxm_music::{MIGRATOR, PersistenceState};
+use std::{env, path::PathBuf, str::FromStr as _};
 
+/// `create_library [PATH] [LABEL]`, `PATH` defaulting to the audio directory.
 pub fn main() -> anyhow::Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async move {
@@ -11,18 +12,21 @@ pub fn main() -> anyhow::Result<()> {
             sqlx::sqlite::SqliteConnectOptions::from_str(&database_url)?.create_if_missing(true),
         )
         .await?;
+        MIGRATOR.run(&db).await?;
 
-        let library_dir = dirs::audio_dir().unwrap_or_else(|| {
-            let home = dirs::home_dir().expect("No Home Dir???");
-            home.join("Music")
+        let mut args = env::args().skip(1);
+        let library_dir = args.next().map(PathBuf::from).unwrap_or_else(|| {
+            dirs::audio_dir().unwrap_or_else(|| {
+                let home = dirs::home_dir().expect("No Home Dir???");
+                home.join("Music")
+            })
         });
-        let r = sqlx::query!(
-            r#"INSERT INTO libraries (id, path) VALUES (?, ?)"#,
-            Uuid::new_v4(),
-            library_dir.to_str().expect("Should be a valid utf-8 str"),
-        )
-        .execute(&db)
-        .await?;
+        let label = args.next();
+
+        let id = PersistenceState::new(db)
+            .add_library(&library_dir, label)
+            .await?;
+        println!("created library {} at {:#?}", id, library_dir);
 
         anyhow::Ok(())
     })?;
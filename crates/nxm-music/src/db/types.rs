@@ -9,10 +9,57 @@ pub enum FileNodeType {
     Directory,
 }
 
-#[derive(Debug)]
+/// Why a file was left out of, or couldn't be fully read by, a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ScanErrorKind {
+    /// The directory listing failed.
+    Walk,
+    Metadata,
+    Open,
+    /// The file opened but isn't a readable audio file.
+    Tags,
+}
+
+/// A tag that can be edited, see `tag_edit_changes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum TagField {
+    Artist,
+    Title,
+    Album,
+    AlbumArtist,
+    TrackNumber,
+    TrackTotal,
+    DiscNumber,
+    DiscTotal,
+    Year,
+    Genre,
+    Composer,
+}
+
+/// How an artist is credited on a track, see `artist_tracks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, sqlx::Type)]
+#[repr(i32)]
+pub enum ArtistRole {
+    Creator = 1,
+    Feature = 2,
+    Remixer = 3,
+    /// Not filled in by scans, since no common tag names the vocalists of a track.
+    Vocalist = 4,
+    Composer = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct Blake3Hash(blake3::Hash);
 
+impl Blake3Hash {
+    pub fn to_hex(&self) -> String {
+        self.0.to_hex().to_string()
+    }
+}
+
 impl From<blake3::Hash> for Blake3Hash {
     fn from(value: blake3::Hash) -> Self {
         Self(value)
@@ -29,3 +76,22 @@ impl sqlx::Decode<'_, sqlx::Sqlite> for Blake3Hash {
         )))
     }
 }
+
+impl sqlx::Encode<'_, sqlx::Sqlite> for Blake3Hash {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <sqlx::Sqlite as sqlx::Database>::ArgumentBuffer,
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <Vec<u8> as sqlx::Encode<'_, sqlx::Sqlite>>::encode(self.0.as_bytes().to_vec(), buf)
+    }
+}
+
+impl sqlx::Type<sqlx::Sqlite> for Blake3Hash {
+    fn type_info() -> <sqlx::Sqlite as sqlx::Database>::TypeInfo {
+        <Vec<u8> as sqlx::Type<sqlx::Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<sqlx::Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <Vec<u8> as sqlx::Type<sqlx::Sqlite>>::compatible(ty)
+    }
+}
@@ -10,5 +10,6 @@ pub mod reexports;
 pub use audio_handle::AudioHandle;
 pub use db::*;
 pub use library::*;
+pub use queue::{QueueId, QueueInfo, QueueWindow};
 
 pub struct FFITag;
@@ -1,54 +1,453 @@
+mod albums;
+mod artwork;
+mod browse;
+mod credits;
+mod duplicates;
+mod formats;
+mod ignore;
+mod libraries;
+mod pages;
+mod query;
+mod scan;
+mod search;
+mod tag_edits;
+mod tags;
+mod watcher;
+
+pub use albums::*;
+pub use artwork::*;
+pub use browse::*;
+pub use credits::*;
+pub use duplicates::*;
+pub use formats::*;
+pub use ignore::*;
+pub use libraries::*;
+pub use pages::*;
+pub use query::*;
+pub use scan::*;
+pub use search::*;
+pub use tag_edits::*;
+pub use tags::*;
+
 use crate::db::types::Blake3Hash;
 use crate::db::types::FileNodeType;
+use crate::db::types::ScanErrorKind;
 use anyhow::Context as _;
 use chrono::{DateTime, Utc};
 use collections::FxIndexMap;
-use creek::ReadDiskStream;
-use creek::SymphoniaDecoder;
 use crossbeam_channel::Sender;
 use lofty::file::AudioFile as _;
+use lofty::file::TaggedFile;
 use lofty::file::TaggedFileExt as _;
 use lofty::probe::Probe;
-use lofty::tag::Accessor as _;
-use lofty::tag::ItemKey;
+use lofty::tag::ItemValue;
+use orx_parallel::*;
+use orx_tree::Dyn;
 use orx_tree::DynTree;
+use orx_tree::NodeIdx;
 use orx_tree::NodeRef;
 use orx_tree::Traversal;
-use orx_tree::Traverser as _;
-use rkyv::string::ArchivedString;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::env;
 use std::os::unix::fs::MetadataExt as _;
+use std::path::Component;
+use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr as _;
 use std::sync::Arc;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
 use uuid::Uuid;
+use walkdir::DirEntry;
 use walkdir::WalkDir;
+use watcher::LibraryWatcher;
+use watcher::poll_library_roots;
 
-#[derive(Debug, Clone, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize)]
+#[derive(Debug, Clone, PartialEq, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize)]
 pub struct Track {
     pub id: Uuid,
     pub artist: String, // Arc<str>???
     pub title: String,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub album_id: Option<Uuid>,
+    pub duration_ms: Option<u64>,
     pub filepath: String,
+    /// False while the library of the track is offline.
+    pub available: bool,
 }
 
 pub struct PersistenceState {
     db: sqlx::Pool<sqlx::Sqlite>,
+    sync_options: SyncOptions,
+    artwork_cache: ArtworkCache,
+    /// The library as last sent to the main thread.
+    snapshot: FxIndexMap<Uuid, Arc<Track>>,
 }
 impl PersistenceState {
+    pub fn new(db: sqlx::Pool<sqlx::Sqlite>) -> Self {
+        Self {
+            db,
+            sync_options: SyncOptions::default(),
+            artwork_cache: ArtworkCache::default(),
+            snapshot: FxIndexMap::default(),
+        }
+    }
+
+    pub fn with_sync_options(mut self, sync_options: SyncOptions) -> Self {
+        self.sync_options = sync_options;
+        self
+    }
+
+    /// Replies with the library as it is in the database. The libraries are synced
+    /// by a scan afterwards, which sends what changed as a `LibraryDelta`.
     async fn handle_fetch_library(
         &mut self,
         reply: flume::Sender<FetchLibraryRes>,
     ) -> anyhow::Result<()> {
-        let db_libraries = sqlx::query_as!(
-            DbLibrary,
-            r#"SELECT id as "id: Uuid", path, node as "node: Uuid" FROM libraries"#
+        let library_snapshot = self.library_snapshot().await?;
+        self.snapshot = library_snapshot.clone();
+
+        reply
+            .try_send(FetchLibraryRes::Snapshot(library_snapshot))
+            .map_err(|e| anyhow::anyhow!("FetchLibraryRes::Snapshot: {:#?}", e))?;
+
+        Ok(())
+    }
+
+    async fn handle_fs_changed(
+        &mut self,
+        paths: Vec<PathBuf>,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        self.sync_paths(paths).await?;
+        self.send_delta(io_main_tx).await
+    }
+
+    /// Runs the scan as a task of its own, which reports back with
+    /// `MainIoMsg::ScanFinished` once it's done.
+    fn handle_scan(
+        &self,
+        target: ScanTarget,
+        control: ScanControl,
+        main_io_tx: &flume::Sender<MainIoMsg>,
+    ) {
+        let scanner = self.scanner();
+        let main_io_tx = main_io_tx.clone();
+
+        tokio::spawn(async move {
+            let res = scanner.scan(target, &control).await;
+            control.finish(&res);
+
+            // Failures are reported to the job, a failed scan has written nothing.
+            let _ = main_io_tx.send(MainIoMsg::ScanFinished {
+                committed: res.is_ok(),
+            });
+        });
+    }
+
+    async fn handle_scan_finished(
+        &mut self,
+        committed: bool,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        if committed {
+            self.send_delta(io_main_tx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// A state sharing the database and artwork cache, for scanning outside the IO
+    /// loop. Its snapshot is empty, deltas are sent by the loop's own state.
+    fn scanner(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            sync_options: self.sync_options,
+            artwork_cache: self.artwork_cache.clone(),
+            snapshot: FxIndexMap::default(),
+        }
+    }
+
+    async fn send_delta(&mut self, io_main_tx: &Sender<IoMainMsg>) -> anyhow::Result<()> {
+        let delta = self.refresh_snapshot().await?;
+        if !delta.is_empty() {
+            io_main_tx
+                .try_send(IoMainMsg::LibraryDelta(delta))
+                .map_err(|_| anyhow::anyhow!("IoMainMsg::LibraryDelta"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the library and returns how it differs from the previous snapshot.
+    pub async fn refresh_snapshot(&mut self) -> anyhow::Result<LibraryDelta> {
+        let library_snapshot = self.library_snapshot().await?;
+
+        let upserted = library_snapshot
+            .values()
+            .filter(|track| self.snapshot.get(&track.id) != Some(track))
+            .cloned()
+            .collect();
+        let removed = self
+            .snapshot
+            .keys()
+            .filter(|id| !library_snapshot.contains_key(*id))
+            .copied()
+            .collect();
+
+        self.snapshot = library_snapshot;
+
+        Ok(LibraryDelta { upserted, removed })
+    }
+
+    pub async fn library_snapshot(&self) -> anyhow::Result<FxIndexMap<Uuid, Arc<Track>>> {
+        let (_, filenodes_tracks) = self.load_libraries().await?;
+        let hidden = self.hidden_duplicates().await?;
+
+        Ok(filenodes_tracks
+            .into_iter()
+            .filter_map(|(_, track)| track)
+            .filter(|track| !hidden.contains(&track.id))
+            .map(|track| (track.id, track))
+            .collect())
+    }
+
+    /// Scans every library root and applies the differences to `filenodes`/`tracks`
+    /// in a single transaction.
+    pub async fn sync_libraries(&self) -> anyhow::Result<()> {
+        self.scan(ScanTarget::Libraries, &ScanControl::default())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Like [`Self::sync_libraries`], but only rescans the directories containing
+    /// `paths`.
+    pub async fn sync_paths(&self, paths: Vec<PathBuf>) -> anyhow::Result<()> {
+        self.scan(ScanTarget::Changed(paths), &ScanControl::default())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Scans `target` and applies the differences to `filenodes`/`tracks` in a single
+    /// transaction, which is rolled back if the scan fails or is cancelled.
+    pub async fn scan(
+        &self,
+        target: ScanTarget,
+        control: &ScanControl,
+    ) -> anyhow::Result<ScanProgress> {
+        let (db_libraries, filenodes_tracks) = self.load_libraries().await?;
+        let detached_tracks = self.load_detached_tracks().await?;
+        let folder_artwork = self.load_folder_artwork().await?;
+
+        match &target {
+            ScanTarget::Library(id) if !db_libraries.iter().any(|l| l.id == *id) => {
+                anyhow::bail!("Library {} doesn't exist", id);
+            }
+            ScanTarget::Subtree(dir) if !db_libraries.iter().any(|l| dir.starts_with(&l.path)) => {
+                anyhow::bail!("{:#?} isn't inside a library", dir);
+            }
+            _ => {}
+        }
+
+        // The walk and tag reads are blocking IO, and keeping the `DynTree` traversal
+        // out of this future also keeps it `Send`.
+        let (library_states, library_status, folder_artwork, mut progress) =
+            tokio::task::spawn_blocking({
+                let control = control.clone();
+                let artwork_cache = self.artwork_cache.clone();
+                move || {
+                    let mut file_nodes_state = FileNodesState::new(filenodes_tracks)
+                        .with_control(control)
+                        .with_artwork_cache(artwork_cache.clone());
+
+                    let mut library_states = vec![];
+                    for l in db_libraries {
+                        log::info!("library_id={}", l.id);
+                        library_states.extend(file_nodes_state.scan_library(l, &target)?);
+                    }
+                    file_nodes_state.retain_unclaimed(&mut library_states);
+                    file_nodes_state.relink_by_hash(&mut library_states, detached_tracks);
+                    let folder_artwork =
+                        read_folder_artwork(&artwork_cache, &library_states, &folder_artwork);
+
+                    anyhow::Ok((
+                        library_states,
+                        file_nodes_state.library_status,
+                        folder_artwork,
+                        file_nodes_state.progress,
+                    ))
+                }
+            })
+            .await??;
+
+        progress.changes = library_states.iter().map(LibraryState::changes).sum();
+        control.send(ScanEvent::Progress(progress));
+
+        let mut tx = self.db.begin().await?;
+
+        for library_state in &library_states {
+            control.check()?;
+            library_state.sync_with_db(&mut tx).await?;
+        }
+        for library_state in &library_states {
+            control.check()?;
+            library_state
+                .delete_from_db(&mut tx, self.sync_options)
+                .await?;
+        }
+        for library_state in &library_states {
+            library_state.sync_errors_with_db(&mut tx).await?;
+        }
+        for (library_id, online) in library_status {
+            sqlx::query!(
+                "UPDATE libraries SET online = ? WHERE id = ?",
+                online,
+                library_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        sync_folder_artwork(&mut tx, &folder_artwork).await?;
+        delete_unused_credits(&mut tx).await?;
+        sync_albums(&mut tx).await?;
+        let unused_artwork = delete_unused_artwork(&mut tx).await?;
+
+        tx.commit().await?;
+
+        for hash in &unused_artwork {
+            self.artwork_cache.remove(hash);
+        }
+
+        Ok(progress)
+    }
+
+    /// Files that couldn't be read as of the last scan that covered them.
+    pub async fn scan_errors(&self) -> anyhow::Result<Vec<ScanError>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT path, kind as "kind: ScanErrorKind", message
+            FROM scan_errors
+            ORDER BY path
+            "#
         )
         .fetch_all(&self.db)
         .await?;
 
-        let mut library_snapshot = FxIndexMap::default();
+        Ok(recs
+            .into_iter()
+            .map(|rec| ScanError {
+                path: rec.path.into(),
+                kind: rec.kind,
+                message: rec.message,
+            })
+            .collect())
+    }
+
+    async fn handle_scan_errors(
+        &self,
+        reply: flume::Sender<anyhow::Result<Vec<ScanError>>>,
+    ) -> anyhow::Result<()> {
+        reply
+            .try_send(self.scan_errors().await)
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::ScanErrors"))?;
+
+        Ok(())
+    }
+
+    async fn handle_duplicates(
+        &self,
+        reply: flume::Sender<anyhow::Result<Vec<DuplicateGroup>>>,
+    ) -> anyhow::Result<()> {
+        reply
+            .try_send(self.duplicates().await)
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::Duplicates"))?;
+
+        Ok(())
+    }
+
+    async fn handle_prefer_duplicate(
+        &mut self,
+        track_id: Uuid,
+        preferred: bool,
+        reply: flume::Sender<anyhow::Result<()>>,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        let res = if preferred {
+            self.prefer_duplicate(track_id).await
+        } else {
+            self.unhide_duplicates(track_id).await
+        };
+
+        // Hidden tracks leave the library and the queues like deleted ones.
+        let res = match res {
+            Ok(_) => self.send_delta(io_main_tx).await,
+            Err(e) => Err(e),
+        };
+
+        reply
+            .try_send(res)
+            .map_err(|_| anyhow::anyhow!("MainIoMsg::PreferDuplicate"))?;
+
+        Ok(())
+    }
+
+    pub async fn library_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let recs = sqlx::query!("SELECT path FROM libraries")
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|rec| PathBuf::from(rec.path))
+            .collect())
+    }
+
+    /// Every library, plus all `filenodes` below their roots along with the track
+    /// for each file node, if there is one.
+    async fn load_libraries(
+        &self,
+    ) -> anyhow::Result<(Vec<DbLibrary>, Vec<(DbFileNode, Option<Arc<Track>>)>)> {
+        let db_libraries = sqlx::query!(
+            r#"
+            SELECT
+                id as "id: Uuid",
+                path,
+                node as "node: Uuid",
+                include_extensions,
+                exclude_extensions,
+                ignore_patterns,
+                online as "online: bool"
+            FROM libraries
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|rec| DbLibrary {
+            id: rec.id,
+            path: rec.path.into(),
+            node: rec.node,
+            extensions: ExtensionFilter::from_db(
+                rec.include_extensions.as_deref(),
+                rec.exclude_extensions.as_deref(),
+            ),
+            ignore: IgnoreRules::from_db(rec.ignore_patterns.as_deref()),
+            online: rec.online,
+        })
+        .collect::<Vec<_>>();
+
         let mut filenodes_tracks = vec![];
 
         for l in &db_libraries {
@@ -68,6 +467,9 @@ impl PersistenceState {
                         f.size,
                         f.node_type,
                         f.name,
+                        f.audio_hash,
+                        f.meta_hash,
+                        f.read_version,
                         l.path as path
                     FROM filenodes f
                     JOIN libraries l
@@ -85,25 +487,40 @@ impl PersistenceState {
                         f.size,
                         f.node_type,
                         f.name,
+                        f.audio_hash,
+                        f.meta_hash,
+                        f.read_version,
                         (t.path || '/' || f.name) as path
                     FROM filenodes f
                     JOIN filenodes_tree t ON f.parent_id = t.id
                 )
                 SELECT
-                    fn.id as "id: Uuid",
-                    fn.inode as "inode: u64",
-                    fn.device as "device: u64",
+                    fn.id as "id!: Uuid",
+                    fn.inode as "inode!: u64",
+                    fn.device as "device!: u64",
                     fn.parent_id as "parent_id: Uuid",
-                    fn.mtime as "mtime: DateTime<Utc>",
-                    fn.size as "size: u64",
-                    fn.node_type as "node_type: FileNodeType",
-                    fn.name as "name: String",
+                    fn.mtime as "mtime!: DateTime<Utc>",
+                    fn.size as "size!: u64",
+                    fn.node_type as "node_type!: FileNodeType",
+                    fn.name as "name!: String",
+                    fn.audio_hash as "audio_hash: Blake3Hash",
+                    fn.meta_hash as "meta_hash: Blake3Hash",
+                    fn.read_version as "read_version!",
                     fn.path as "path: Arc<str>",
-                    t.id AS "track_id: Uuid",
+                    t.id AS "track_id?: Uuid",
                     t.artist,
-                    t.title
+                    t.title,
+                    t.album,
+                    t.album_artist,
+                    t.track_number as "track_number: u32",
+                    t.disc_number as "disc_number: u32",
+                    t.year as "year: i32",
+                    t.genre,
+                    t.composer,
+                    t.album_id as "album_id: Uuid",
+                    t.duration_ms as "duration_ms: u64"
                 FROM filenodes_tree fn
-                INNER JOIN tracks t
+                LEFT JOIN tracks t
                     ON t.filenode_id == fn.id;
                 "#,
                 l.node,
@@ -111,71 +528,159 @@ impl PersistenceState {
             .fetch_all(&self.db)
             .await?;
 
-            library_snapshot.reserve(recs.len());
             filenodes_tracks.reserve(recs.len());
 
             for rec in recs {
                 let db_file_node = DbFileNode {
-                    id: rec.id.unwrap(),
-                    inode: rec.inode.unwrap(),
-                    device: rec.device.unwrap(),
+                    id: rec.id,
+                    inode: rec.inode,
+                    device: rec.device,
                     parent_id: rec.parent_id,
-                    name: rec.name.unwrap(),
-                    mtime: rec.mtime.unwrap(),
-                    size: rec.size.unwrap(),
-                    node_type: rec.node_type.unwrap(),
+                    name: rec.name,
+                    mtime: rec.mtime,
+                    size: rec.size,
+                    node_type: rec.node_type,
+                    audio_hash: rec.audio_hash,
+                    meta_hash: rec.meta_hash,
+                    read_version: rec.read_version,
                 };
-                let track = Arc::new(Track {
-                    id: rec.track_id,
-                    artist: rec.artist.unwrap_or_else(|| String::new()),
-                    title: rec.title.unwrap_or_else(|| String::new()),
-                    filepath: rec.path.unwrap().to_string(),
+                let track = rec.track_id.map(|track_id| {
+                    Arc::new(Track {
+                        id: track_id,
+                        artist: rec.artist.unwrap_or_default(),
+                        title: rec.title.unwrap_or_default(),
+                        album: rec.album,
+                        album_artist: rec.album_artist,
+                        track_number: rec.track_number,
+                        disc_number: rec.disc_number,
+                        year: rec.year,
+                        genre: rec.genre,
+                        composer: rec.composer,
+                        album_id: rec.album_id,
+                        duration_ms: rec.duration_ms,
+                        filepath: rec.path.unwrap().to_string(),
+                        available: l.online,
+                    })
                 });
 
-                library_snapshot.insert(track.id, track.clone());
                 filenodes_tracks.push((db_file_node, track));
             }
         }
 
-        reply
-            .try_send(FetchLibraryRes::Snapshot(library_snapshot))
-            .map_err(|e| anyhow::anyhow!("FetchLibraryRes::Snapshot: {:#?}", e))?;
+        Ok((db_libraries, filenodes_tracks))
+    }
 
-        // let mut tx = self.db.begin().await?;
-        //
-        // let mut file_nodes_state = FileNodesState::new(filenodes_tracks);
-        //
-        // for l in db_libraries {
-        //     println!("library_id={}", l.id);
-        //
-        //     let library_state = file_nodes_state.create_library_state(l)?;
-        //     // println!("fs_tree={:#?}", ());
-        //
-        //     // Uncommenting triggers this
-        //     //
-        //     // 1. rustc: lifetime bound not satisfied
-        //     //    this is a known limitation that will be removed in the future (see issue #100013 <https://github.com/rust-lang/rust/issues/100013> for more information)
-        //     // 2. rustc: implementation of `orx_tree::node_ref::NodeRefCore` is not general enough
-        //     //    `orx_tree::node_ref::NodeRefCore<'_, orx_tree::Dyn<library::FsNode>, orx_tree::Auto, orx_tree::pinned_storage::SplitRecursive>` would have to be implemented for the type `orx_tree::Node<'_, orx_tree::Dyn<library::FsNode>>`
-        //     //    ...but `orx_tree::node_ref::NodeRefCore<'0, orx_tree::Dyn<library::FsNode>, orx_tree::Auto, orx_tree::pinned_storage::SplitRecursive>` is actually implemented for the type `orx_tree::Node<'0, orx_tree::Dyn<library::FsNode>>`, for some specific lifetime `'0`
-        //     //
-        //     // due to the `sqlx::query!()` calls inside the function,
-        //     // but not as this callsitte but in `rt.spawn(io_thread(...))` function call in the  `audio_handle.rs` file. Strange...
-        //     // library_state.sync_with_db(&mut *tx).await?;
-        // }
-        //
-        // tx.commit().await?;
+    /// Tracks detached from their file, see [`SyncOptions::keep_missing_tracks`], that
+    /// can be matched with a file again.
+    async fn load_detached_tracks(&self) -> anyhow::Result<Vec<(Uuid, Blake3Hash)>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT id as "id: Uuid", audio_hash as "audio_hash!: Blake3Hash"
+            FROM tracks
+            WHERE filenode_id IS NULL AND audio_hash IS NOT NULL
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|rec| (rec.id, rec.audio_hash))
+            .collect())
+    }
+
+    async fn handle_resolve_context(
+        &mut self,
+        context: PlayContext,
+        reply: flume::Sender<anyhow::Result<Vec<Uuid>>>,
+    ) -> anyhow::Result<()> {
+        let res = self.resolve_context(context).await;
+
+        reply
+            .try_send(res)
+            .map_err(|e| anyhow::anyhow!("ResolveContext reply: {:#?}", e))?;
 
         Ok(())
     }
+
+    /// The tracks of `context`, in the order they're queued.
+    pub async fn resolve_context(&self, context: PlayContext) -> anyhow::Result<Vec<Uuid>> {
+        let track_ids = match context {
+            PlayContext::Folder(filenode_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    WITH RECURSIVE subtree AS (
+                        SELECT f.id, f.name AS path
+                        FROM filenodes f
+                        WHERE f.id = ?
+
+                        UNION ALL
+
+                        SELECT f.id, (s.path || '/' || f.name) AS path
+                        FROM filenodes f
+                        JOIN subtree s ON f.parent_id = s.id
+                    )
+                    SELECT t.id AS "id: Uuid"
+                    FROM subtree s
+                    INNER JOIN visible_tracks t
+                        ON t.filenode_id == s.id
+                    ORDER BY s.path;
+                    "#,
+                    filenode_id,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            PlayContext::Album(album_id) => self.album_tracks(album_id).await?,
+            PlayContext::Artist(artist) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT t.id AS "id: Uuid"
+                    FROM visible_tracks t
+                    WHERE t.artist = ?
+                    ORDER BY t.title;
+                    "#,
+                    artist,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            PlayContext::Query(query) => self.query_tracks(&query).await?,
+            PlayContext::Tracks(track_ids) => track_ids,
+        };
+
+        Ok(track_ids)
+    }
+}
+
+/// A set of tracks to replace the active queue with.
+#[derive(Debug, Clone)]
+pub enum PlayContext {
+    /// Every track in the `filenodes` subtree rooted at this node, in path order.
+    Folder(Uuid),
+    /// An [`Album`] in disc/track order.
+    Album(Uuid),
+    Artist(String),
+    /// The tracks matching a [`TrackQuery`], in its order.
+    Query(TrackQuery),
+    Tracks(Vec<Uuid>),
 }
 
 pub struct DbLibrary {
     pub id: Uuid,
     pub path: PathBuf,
     pub node: Option<Uuid>,
+    pub extensions: ExtensionFilter,
+    pub ignore: IgnoreRules,
+    /// As of the last scan or check, see [`root_is_online`].
+    pub online: bool,
 }
 
+/// Bumped whenever scans start reading something new from files. Files read with an
+/// older version are read again by the next scan that covers them, which fills in
+/// whatever wasn't extracted for them yet.
+pub const READ_VERSION: i64 = 1;
+
 #[derive(Debug, Clone)]
 pub struct DbFileNode {
     pub id: Uuid,
@@ -186,8 +691,10 @@ pub struct DbFileNode {
     pub mtime: DateTime<Utc>,
     pub size: u64,
     pub node_type: FileNodeType,
-    // audio_hash: Option<Blake3Hash>,
-    // meta_hash: Option<Blake3Hash>,
+    pub audio_hash: Option<Blake3Hash>,
+    pub meta_hash: Option<Blake3Hash>,
+    /// The [`READ_VERSION`] the file was last read with.
+    pub read_version: i64,
     // node_hash: Option<Blake3Hash>,
 }
 
@@ -197,25 +704,25 @@ pub struct FileIdentity {
     pub device: u64, // Always check device + inode together
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)]
 pub enum FsFileType {
     Directory,
-    AudioFile {
-        artist: Option<String>,
-        title: Option<String>,
-    },
+    AudioFile(TrackTags),
+    /// An audio file whose tags couldn't be read, see `scan_errors`.
+    Unreadable,
 }
 
 impl From<&FsFileType> for FileNodeType {
     fn from(value: &FsFileType) -> Self {
         match value {
             FsFileType::Directory => FileNodeType::Directory,
-            FsFileType::AudioFile { .. } => FileNodeType::File,
+            FsFileType::AudioFile(_) | FsFileType::Unreadable => FileNodeType::File,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FsNode {
     pub path: PathBuf,
     pub file_type: FsFileType,
@@ -223,55 +730,218 @@ pub struct FsNode {
     pub size: u64,
     pub mtime: DateTime<Utc>,
     pub parent_id: Option<Uuid>,
+    /// Only known for audio files whose tags were read in this scan.
+    pub hashes: Option<FileHashes>,
+    /// Only known for audio files whose tags were read in this scan.
+    pub format: Option<AudioFormat>,
 
     pub db_id: Uuid,
     pub op: SyncOp,
+    /// An existing track to attach an inserted file to, instead of creating one.
+    pub relinked_track: Option<Uuid>,
+}
+
+/// BLAKE3 hashes identifying a file's content regardless of its path and inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHashes {
+    /// Of the demuxed audio packets, so that retagging doesn't change it.
+    pub audio: Blake3Hash,
+    /// Of the tag items and pictures.
+    pub meta: Blake3Hash,
 }
 
 impl FsNode {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .expect("Invalid Dir Path")
+            .to_string_lossy()
+            .to_string()
+    }
+
     pub async fn insert_into_db(
         &self,
         connection: &mut sqlx::SqliteConnection,
     ) -> anyhow::Result<()> {
+        let name = self.name();
+        let audio_hash = self.hashes.map(|hashes| hashes.audio);
+        let meta_hash = self.hashes.map(|hashes| hashes.meta);
+        let container = self.format.as_ref().map(|format| format.container.as_str());
+        let codec = self
+            .format
+            .as_ref()
+            .and_then(|format| format.codec.as_deref());
+        let duration_ms = self
+            .format
+            .as_ref()
+            .and_then(|format| format.duration_ms)
+            .map(|ms| ms as i64);
+
         sqlx::query!(
             r#"
-            INSERT INTO filenodes (id, inode, device, parent_id, name, mtime, size, node_type)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?);
+            INSERT INTO filenodes (
+                id,
+                inode,
+                device,
+                parent_id,
+                name,
+                mtime,
+                size,
+                node_type,
+                audio_hash,
+                meta_hash,
+                read_version
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
             "#,
             self.db_id,
             self.identity.inode as i64,
             self.identity.device as i64,
             self.parent_id,
-            self.path
-                .file_name()
-                .expect("Invalid Dir Path")
-                .to_string_lossy()
-                .to_string(),
+            name,
             self.mtime,
             self.size as i64,
             Into::<FileNodeType>::into(&self.file_type),
+            audio_hash,
+            meta_hash,
+            READ_VERSION,
         )
         .execute(&mut *connection)
         .await
         .context("filenodes insert failed")?;
 
-        match &self.file_type {
-            FsFileType::Directory => {}
-            FsFileType::AudioFile { artist, title } => {
-                sqlx::query!(
-                    r#"
-                    INSERT INTO tracks (id, filenode_id, artist, title)
-                    VALUES (?, ?, ?, ?);
-                    "#,
-                    Uuid::new_v4(),
-                    self.db_id,
-                    artist,
-                    title
+        let tags = match &self.file_type {
+            FsFileType::Directory => return Ok(()),
+            FsFileType::AudioFile(tags) => Some(tags),
+            // Still listed, so that it shows up once it's fixed or replaced.
+            FsFileType::Unreadable => None,
+        };
+
+        if let Some(track_id) = self.relinked_track {
+            log::debug!("relinked track {:#?} to {:#?}", track_id, self.path);
+
+            sqlx::query!(
+                r#"
+                UPDATE tracks
+                SET
+                    filenode_id = ?,
+                    audio_hash = ?,
+                    container = ?,
+                    codec = ?,
+                    duration_ms = ?
+                WHERE id = ?;
+                "#,
+                self.db_id,
+                audio_hash,
+                container,
+                codec,
+                duration_ms,
+                track_id,
+            )
+            .execute(&mut *connection)
+            .await
+            .context("tracks relink failed")?;
+        } else {
+            sqlx::query!(
+                r#"
+                INSERT INTO tracks (
+                    id,
+                    filenode_id,
+                    audio_hash,
+                    container,
+                    codec,
+                    duration_ms,
+                    added_at
                 )
-                .execute(&mut *connection)
+                VALUES (?, ?, ?, ?, ?, ?, ?);
+                "#,
+                Uuid::new_v4(),
+                self.db_id,
+                audio_hash,
+                container,
+                codec,
+                duration_ms,
+                Utc::now(),
+            )
+            .execute(&mut *connection)
+            .await
+            .context("tracks insert failed")?;
+        }
+
+        if let Some(tags) = tags {
+            tags.write_to_track(&mut *connection, self.db_id, &self.path)
                 .await
-                .context("tracks insert failed")?;
-            }
+                .context("tracks tags update failed")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes what changed about an indexed file, which may have been retagged.
+    pub async fn update_in_db(
+        &self,
+        connection: &mut sqlx::SqliteConnection,
+    ) -> anyhow::Result<()> {
+        let audio_hash = self.hashes.map(|hashes| hashes.audio);
+        let meta_hash = self.hashes.map(|hashes| hashes.meta);
+        let container = self.format.as_ref().map(|format| format.container.as_str());
+        let codec = self
+            .format
+            .as_ref()
+            .and_then(|format| format.codec.as_deref());
+        let duration_ms = self
+            .format
+            .as_ref()
+            .and_then(|format| format.duration_ms)
+            .map(|ms| ms as i64);
+
+        sqlx::query!(
+            r#"
+            UPDATE filenodes
+            SET
+                inode = ?,
+                device = ?,
+                mtime = ?,
+                size = ?,
+                audio_hash = ?,
+                meta_hash = ?,
+                read_version = ?
+            WHERE id = ?;
+            "#,
+            self.identity.inode as i64,
+            self.identity.device as i64,
+            self.mtime,
+            self.size as i64,
+            audio_hash,
+            meta_hash,
+            READ_VERSION,
+            self.db_id,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        if let FsFileType::AudioFile(tags) = &self.file_type {
+            sqlx::query!(
+                r#"
+                UPDATE tracks
+                SET
+                    audio_hash = ?,
+                    container = ?,
+                    codec = ?,
+                    duration_ms = ?
+                WHERE filenode_id = ?;
+                "#,
+                audio_hash,
+                container,
+                codec,
+                duration_ms,
+                self.db_id,
+            )
+            .execute(&mut *connection)
+            .await?;
+
+            tags.write_to_track(&mut *connection, self.db_id, &self.path)
+                .await?;
         }
 
         Ok(())
@@ -287,6 +957,13 @@ pub enum SyncOp {
     NewRoot { library_id: Uuid },
 }
 
+impl SyncOp {
+    /// Whether the synced node needs the tags of its file.
+    pub fn reads_tags(&self) -> bool {
+        matches!(self, Self::Insert | Self::UpdateMeta)
+    }
+}
+
 fn get_identity(meta: &std::fs::Metadata) -> FileIdentity {
     #[cfg(unix)]
     {
@@ -312,14 +989,211 @@ fn get_identity(meta: &std::fs::Metadata) -> FileIdentity {
     }
 }
 
+/// What a scan learns from the content of an audio file.
+#[derive(Debug, Clone)]
+pub struct AudioFileInfo {
+    pub file_type: FsFileType,
+    /// Only known if the file could be demuxed.
+    pub hashes: Option<FileHashes>,
+    pub format: AudioFormat,
+}
+
+/// The tags, hashes and format of the audio file at `path`.
+fn read_audio_file(path: &Path, artwork_cache: &ArtworkCache) -> Result<AudioFileInfo, ScanError> {
+    let probe = Probe::open(path)
+        .and_then(|probe| Ok(probe.guess_file_type()?))
+        .map_err(|e| ScanError::new(path, ScanErrorKind::Open, e))?;
+
+    if probe.file_type().is_none() {
+        // Formats lofty can't read tags from, like Matroska, still play through
+        // symphonia.
+        let (audio, codec) =
+            hash_audio(path).map_err(|e| ScanError::new(path, ScanErrorKind::Open, e))?;
+
+        return Ok(AudioFileInfo {
+            file_type: FsFileType::AudioFile(TrackTags::default()),
+            hashes: Some(FileHashes {
+                audio,
+                // What `hash_tags` makes of a file without tags.
+                meta: blake3::Hasher::new().finalize().into(),
+            }),
+            format: AudioFormat::detect(path, None, codec, None),
+        });
+    }
+
+    let tagged_file = probe
+        .read()
+        .map_err(|e| ScanError::new(path, ScanErrorKind::Tags, e))?;
+
+    // let tag = match tagged_file.primary_tag() {
+    //     Some(primary_tag) => primary_tag,
+    //     // If the "primary" tag doesn't exist, we just grab the
+    //     // first tag we can find. Realistically, a tag reader would likely
+    //     // iterate through the tags to find a suitable one.
+    //     None => tagged_file
+    //         .first_tag()
+    //         .context("Unable to read first tag")?,
+    // };
+
+    let tag = tagged_file.primary_tag().or(tagged_file.first_tag());
+
+    let file_type = if let Some(tag) = tag {
+        let tags = TrackTags {
+            artwork: store_embedded_artwork(artwork_cache, tag, path),
+            ..TrackTags::from_tag(tag)
+        };
+
+        // let properties = tagged_file.properties();
+        //
+        // let duration = properties.duration();
+        // let seconds = duration.as_secs() % 60;
+        //
+        // let duration_display =
+        //     format!("{:02}:{:02}", (duration.as_secs() - seconds) / 60, seconds);
+        //
+        // println!("--- Audio Properties ---");
+        // println!(
+        //     "Bitrate (Audio): {}",
+        //     properties.audio_bitrate().unwrap_or(0)
+        // );
+        // println!(
+        //     "Bitrate (Overall): {}",
+        //     properties.overall_bitrate().unwrap_or(0)
+        // );
+        // println!("Sample Rate: {}", properties.sample_rate().unwrap_or(0));
+        // println!("Bit depth: {}", properties.bit_depth().unwrap_or(0));
+        // println!("Channels: {}", properties.channels().unwrap_or(0));
+        // println!("Duration: {duration_display}");
+
+        log::trace!(
+            "found tag for {:#?}, {:#?} - {:#?}",
+            path,
+            tags.artist,
+            tags.title
+        );
+
+        FsFileType::AudioFile(tags)
+    } else {
+        log::trace!("not found tag for {:#?}", path);
+        FsFileType::AudioFile(TrackTags::default())
+    };
+
+    let (hashes, codec) = match hash_audio(path) {
+        Ok((audio, codec)) => (
+            Some(FileHashes {
+                audio,
+                meta: hash_tags(&tagged_file),
+            }),
+            codec,
+        ),
+        Err(e) => {
+            log::warn!("Unable to hash {:#?}: {:#}", path, e);
+            (None, None)
+        }
+    };
+
+    Ok(AudioFileInfo {
+        file_type,
+        hashes,
+        format: AudioFormat::detect(
+            path,
+            Some(&tagged_file.file_type()),
+            codec,
+            Some(tagged_file.properties().duration()),
+        ),
+    })
+}
+
+/// Hashes the audio packets of every track in the file, leaving out the container's
+/// tags and other metadata. Also returns the codec of the default track, if symphonia
+/// has a decoder for it.
+fn hash_audio(path: &Path) -> anyhow::Result<(Blake3Hash, Option<&'static str>)> {
+    let mss = MediaSourceStream::new(Box::new(std::fs::File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let codec = format
+        .default_track()
+        .and_then(|track| symphonia::default::get_codecs().get_codec(track.codec_params.codec))
+        .map(|descriptor| descriptor.short_name);
+
+    let mut hasher = blake3::Hasher::new();
+    loop {
+        match format.next_packet() {
+            Ok(packet) => {
+                hasher.update(&packet.data);
+            }
+            Err(symphonia::core::errors::Error::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok((hasher.finalize().into(), codec))
+}
+
+/// Hashes every tag item and picture, independent of the order they're stored in.
+fn hash_tags(tagged_file: &TaggedFile) -> Blake3Hash {
+    let mut items = vec![];
+    for tag in tagged_file.tags() {
+        for item in tag.items() {
+            let value = match item.value() {
+                ItemValue::Text(text) | ItemValue::Locator(text) => text.as_bytes(),
+                ItemValue::Binary(bytes) => bytes,
+            };
+            items.push((format!("{:?}", item.key()), value));
+        }
+        for picture in tag.pictures() {
+            items.push((format!("{:?}", picture.pic_type()), picture.data()));
+        }
+    }
+    items.sort();
+
+    let mut hasher = blake3::Hasher::new();
+    for (key, value) in items {
+        // Length prefixed, so that no two lists of items hash the same bytes.
+        hasher.update(&(key.len() as u64).to_le_bytes());
+        hasher.update(key.as_bytes());
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+
+    hasher.finalize().into()
+}
+
 pub struct FileNodesState {
-    pub entries: HashMap<Uuid, (DbFileNode, Arc<Track>)>,
+    pub entries: HashMap<Uuid, (DbFileNode, Option<Arc<Track>>)>,
     pub children_map: HashMap<Uuid, Vec<Uuid>>,
     pub identity_map: HashMap<FileIdentity, Uuid>,
+    /// Existing nodes found on disk by any library scanned so far.
+    pub claimed: HashSet<Uuid>,
+    pub progress: ScanProgress,
+    control: ScanControl,
+    /// Errors of the root being scanned.
+    errors: Vec<ScanError>,
+    /// Whether the root of each library covered by the scan is online.
+    pub library_status: Vec<(Uuid, bool)>,
+    /// Where the pictures embedded in read files are stored.
+    artwork_cache: ArtworkCache,
 }
 
 impl FileNodesState {
-    pub fn new(filenodes_tracks: Vec<(DbFileNode, Arc<Track>)>) -> Self {
+    pub fn new(filenodes_tracks: Vec<(DbFileNode, Option<Arc<Track>>)>) -> Self {
         let len = filenodes_tracks.len();
 
         let mut entries = HashMap::with_capacity(len);
@@ -347,22 +1221,244 @@ impl FileNodesState {
             entries,
             children_map,
             identity_map,
+            claimed: HashSet::new(),
+            progress: ScanProgress::default(),
+            control: ScanControl::default(),
+            errors: vec![],
+            library_status: vec![],
+            artwork_cache: ArtworkCache::default(),
         }
     }
 
-    pub fn create_library_state(&mut self, l: DbLibrary) -> anyhow::Result<LibraryState> {
-        let (root_id, op) = l
-            .node
-            .map(|id| (id, SyncOp::Synced))
-            .unwrap_or((Uuid::new_v4(), SyncOp::NewRoot { library_id: l.id }));
+    pub fn with_control(mut self, control: ScanControl) -> Self {
+        self.control = control;
+        self
+    }
 
-        let meta = l.path.metadata().context("Unable to read file metadata")?;
-        assert!(meta.is_dir());
+    pub fn with_artwork_cache(mut self, artwork_cache: ArtworkCache) -> Self {
+        self.artwork_cache = artwork_cache;
+        self
+    }
 
-        let mut path_map = HashMap::new();
-        let mut identity_map = HashMap::new();
+    /// The states of whichever parts of `l` are covered by `target`.
+    pub fn scan_library(
+        &mut self,
+        l: DbLibrary,
+        target: &ScanTarget,
+    ) -> anyhow::Result<Vec<LibraryState>> {
+        let dirs = match target {
+            ScanTarget::Libraries => None,
+            ScanTarget::Library(id) if *id == l.id => None,
+            ScanTarget::Library(_) => return Ok(vec![]),
+            ScanTarget::Subtree(dir) => Some(vec![dir.clone()]),
+            ScanTarget::Changed(paths) => Some(
+                paths
+                    .iter()
+                    .filter_map(|path| path.parent())
+                    .map(Path::to_path_buf)
+                    .collect(),
+            ),
+        };
+
+        let dirs = dirs.map(|dirs| {
+            dirs.into_iter()
+                .filter(|dir| dir.starts_with(&l.path))
+                .collect::<Vec<_>>()
+        });
+        if dirs.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(vec![]);
+        }
 
-        let mut stack = vec![root_id];
+        let has_children = l.node.is_some_and(|id| {
+            self.children_map
+                .get(&id)
+                .is_some_and(|ids| !ids.is_empty())
+        });
+        if !root_is_online(&l.path, has_children) {
+            log::warn!("Library {} at {:#?} is offline", l.id, l.path);
+            self.library_status.push((l.id, false));
+            return Ok(vec![]);
+        }
+        self.library_status.push((l.id, true));
+
+        let Some(dirs) = dirs else {
+            return Ok(vec![self.create_library_state(l)?]);
+        };
+
+        // A library that was never scanned has nothing to diff against, and one that
+        // was offline may have changed anywhere.
+        if l.node.is_none() || !l.online {
+            return Ok(vec![self.create_library_state(l)?]);
+        }
+
+        self.scan_roots(&l, &dirs)
+            .into_iter()
+            .map(|root| self.create_subtree_state(root))
+            .collect()
+    }
+
+    pub fn create_library_state(&mut self, l: DbLibrary) -> anyhow::Result<LibraryState> {
+        let (id, op) = l
+            .node
+            .map(|id| (id, SyncOp::Synced))
+            .unwrap_or((Uuid::new_v4(), SyncOp::NewRoot { library_id: l.id }));
+
+        self.create_subtree_state(ScanRoot {
+            id,
+            path: l.path.clone(),
+            parent_id: None,
+            op,
+            library_path: l.path,
+            extensions: l.extensions,
+            ignore: l.ignore,
+        })
+    }
+
+    /// The node at `components` below `root_id`, looked up by name.
+    pub fn find_node(&self, root_id: Uuid, components: &[Component<'_>]) -> Option<Uuid> {
+        components.iter().try_fold(root_id, |id, component| {
+            self.children_map
+                .get(&id)?
+                .iter()
+                .copied()
+                .find(|child_id| {
+                    self.entries.get(child_id).is_some_and(|(filenode, _)| {
+                        component.as_os_str() == filenode.name.as_str()
+                    })
+                })
+        })
+    }
+
+    /// The smallest set of known directories of `l` that together contain `dirs`.
+    ///
+    /// A directory that's gone or new is covered by its closest ancestor that is both
+    /// on disk and in `filenodes`.
+    pub fn scan_roots(&self, l: &DbLibrary, dirs: &[PathBuf]) -> Vec<ScanRoot> {
+        let Some(root_id) = l.node else {
+            return vec![];
+        };
+
+        let mut known_dirs = vec![];
+
+        for dir in dirs {
+            let Ok(relative) = dir.strip_prefix(&l.path) else {
+                continue;
+            };
+            let mut components = relative.components().collect::<Vec<_>>();
+
+            loop {
+                let dir = components
+                    .iter()
+                    .fold(l.path.clone(), |dir, component| dir.join(component));
+                if dir.is_dir()
+                    && let Some(id) = self.find_node(root_id, &components)
+                {
+                    known_dirs.push((dir, id));
+                    break;
+                }
+
+                if components.pop().is_none() {
+                    break;
+                }
+            }
+        }
+
+        known_dirs.sort();
+        known_dirs.dedup();
+
+        // Sorted paths put every directory right after its ancestors.
+        let mut roots: Vec<(PathBuf, Uuid)> = vec![];
+        for (dir, id) in known_dirs {
+            if roots
+                .last()
+                .is_some_and(|(ancestor, _)| dir.starts_with(ancestor))
+            {
+                continue;
+            }
+            roots.push((dir, id));
+        }
+
+        roots
+            .into_iter()
+            .map(|(path, id)| {
+                if id == root_id {
+                    return ScanRoot {
+                        id,
+                        path,
+                        parent_id: None,
+                        op: SyncOp::Synced,
+                        library_path: l.path.clone(),
+                        extensions: l.extensions.clone(),
+                        ignore: l.ignore.clone(),
+                    };
+                }
+
+                let (filenode, _) = &self.entries[&id];
+                let changed = path.metadata().is_ok_and(|meta| {
+                    meta.modified()
+                        .is_ok_and(|mtime| DateTime::<Utc>::from(mtime) != filenode.mtime)
+                        || meta.len() != filenode.size
+                });
+
+                ScanRoot {
+                    id,
+                    path,
+                    parent_id: filenode.parent_id,
+                    op: if changed {
+                        SyncOp::UpdateMeta
+                    } else {
+                        SyncOp::Synced
+                    },
+                    library_path: l.path.clone(),
+                    extensions: l.extensions.clone(),
+                    ignore: l.ignore.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Diffs the directory at `root.path` against the `filenodes` subtree at `root.id`.
+    /// Takes `id` and every node indexed below it out of the maps a subtree walk
+    /// matches against, so that they aren't deleted for not having been found.
+    fn keep_unexamined(
+        &self,
+        id: Uuid,
+        path_map: &mut HashMap<(Option<Uuid>, String), Uuid>,
+        identity_map: &mut HashMap<FileIdentity, Uuid>,
+    ) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some((filenode, _)) = self.entries.get(&id) {
+                path_map.remove(&(filenode.parent_id, filenode.name.clone()));
+                identity_map.remove(&FileIdentity {
+                    inode: filenode.inode,
+                    device: filenode.device,
+                });
+            }
+
+            stack.extend(self.children_map.get(&id).into_iter().flatten());
+        }
+    }
+
+    pub fn create_subtree_state(&mut self, root: ScanRoot) -> anyhow::Result<LibraryState> {
+        let ScanRoot {
+            id: root_id,
+            path,
+            parent_id,
+            op,
+            library_path,
+            extensions,
+            ignore,
+        } = root;
+
+        let meta = path.metadata().context("Unable to read file metadata")?;
+        anyhow::ensure!(meta.is_dir(), "{:#?} isn't a directory", path);
+        self.progress.dirs_visited += 1;
+
+        let mut path_map = HashMap::new();
+        let mut identity_map = HashMap::new();
+
+        let mut stack = vec![root_id];
 
         while let Some(id) = stack.pop() {
             let Some(children) = self.children_map.get(&id) else {
@@ -395,7 +1491,7 @@ impl FileNodesState {
 
         let mut tree = DynTree::new(FsNode {
             db_id: root_id,
-            path: l.path,
+            path,
             file_type: FsFileType::Directory,
             identity: FileIdentity {
                 inode: meta.ino(),
@@ -403,261 +1499,568 @@ impl FileNodesState {
             },
             size: meta.size(),
             mtime: DateTime::<Utc>::from(meta.modified()?),
-            parent_id: None,
+            parent_id,
+            hashes: None,
+            format: None,
             op,
+            relinked_track: None,
         });
         let mut stack = vec![tree.root().idx()];
-
-        for entry in WalkDir::new(&tree.root().data().path)
+        let mut tag_reads = vec![];
+        let mut inserted_files = vec![];
+        let mut folder_artwork = HashMap::<Uuid, (usize, PathBuf)>::new();
+        let mut sniff_candidates = vec![];
+
+        // An ignored root is walked as if it was empty, so that whatever was indexed
+        // below it is removed.
+        let root_ignored = ignore.is_ignored(&library_path, &tree.root().data().path, true);
+        let mut walk = WalkDir::new(&tree.root().data().path)
             .min_depth(1)
             .sort_by_file_name()
-        {
+            .into_iter()
+            .filter_entry(|entry| {
+                !root_ignored
+                    && !ignore.is_ignored(&library_path, entry.path(), entry.file_type().is_dir())
+            });
+
+        while let Some(entry) = walk.next() {
             let entry = match entry {
                 Ok(e) => e,
-                Err(_) => {
-                    log::info!("Unable to read entry: {:#?}", entry);
+                Err(e) => {
+                    let path = e
+                        .path()
+                        .map_or_else(|| tree.root().data().path.clone(), Path::to_path_buf);
+
+                    // A directory that couldn't be listed keeps whatever was indexed
+                    // below it, as if it hadn't been part of the scan.
+                    if let Some(dir) = stack
+                        .iter()
+                        .map(|&idx| tree.node(idx).data())
+                        .find(|dir| dir.path == path)
+                    {
+                        self.keep_unexamined(dir.db_id, &mut path_map, &mut identity_map);
+                    }
+
+                    self.errors
+                        .push(ScanError::new(path, ScanErrorKind::Walk, e));
                     continue;
                 }
             };
 
             let file_type = entry.file_type();
-
-            if file_type.is_file()
-                && !entry
-                    .path()
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| {
-                        matches!(
-                            ext.to_lowercase().as_str(),
-                            "mp3" | "flac" | "wav" | "m4a" | "ogg"
-                        )
-                    })
-                    .unwrap_or(false)
-            {
-                continue;
+            let parent_idx = stack[entry.depth() - 1];
+
+            if file_type.is_file() {
+                let is_audio = match extensions.detect(entry.path()) {
+                    Detection::Audio => true,
+                    Detection::Skip => false,
+                    Detection::Sniff => {
+                        let key = (
+                            Some(tree.node(parent_idx).data().db_id),
+                            entry.file_name().to_string_lossy().to_string(),
+                        );
+
+                        // Indexed already, so it was found to be audio before. Anything
+                        // else is probed along with the others once the walk is done.
+                        if !path_map.contains_key(&key) {
+                            sniff_candidates.push((parent_idx, entry));
+                            continue;
+                        }
+                        true
+                    }
+                };
+                if !is_audio {
+                    let dir_id = tree.node(parent_idx).data().db_id;
+                    offer_folder_artwork(&mut folder_artwork, dir_id, entry.into_path());
+                    continue;
+                }
             }
 
-            let meta = match entry.metadata() {
-                Ok(m) => m,
-                Err(_) => {
-                    log::info!("Unable to read entry metadata: {:#?}", entry);
-                    continue;
+            let Some(child_idx) = self.push_entry(
+                &mut tree,
+                parent_idx,
+                &entry,
+                &mut path_map,
+                &mut identity_map,
+            )?
+            else {
+                if file_type.is_dir() {
+                    walk.skip_current_dir();
                 }
+                continue;
             };
 
-            let is_dir = meta.is_dir();
-            let name = entry.file_name().to_string_lossy().to_string();
-            let depth = entry.depth();
-            let identity = get_identity(&meta);
-            let mtime = DateTime::<Utc>::from(meta.modified()?);
-            let size = meta.len();
-            let file_type = if file_type.is_dir() {
-                FsFileType::Directory
+            if file_type.is_dir() {
+                // `WalkDir` is depth first, so everything deeper than this directory
+                // belongs to a sibling that has been fully visited already.
+                stack.truncate(entry.depth());
+                stack.push(child_idx);
             } else {
-                let tagged_file = Probe::open(entry.path())?
-                    .read()
-                    .expect("ERROR: Failed to read file!");
-
-                // let tag = match tagged_file.primary_tag() {
-                //     Some(primary_tag) => primary_tag,
-                //     // If the "primary" tag doesn't exist, we just grab the
-                //     // first tag we can find. Realistically, a tag reader would likely
-                //     // iterate through the tags to find a suitable one.
-                //     None => tagged_file
-                //         .first_tag()
-                //         .context("Unable to read first tag")?,
-                // };
-
-                let tag = tagged_file.primary_tag().or(tagged_file.first_tag());
-
-                if let Some(tag) = tag {
-                    // println!("--- Tag Information ---");
-                    // println!("Title: {}", tag.title().as_deref().unwrap_or("None"));
-                    // println!("Artist: {}", tag.artist().as_deref().unwrap_or("None"));
-                    // println!("Album: {}", tag.album().as_deref().unwrap_or("None"));
-                    // println!("Genre: {}", tag.genre().as_deref().unwrap_or("None"));
-                    //
-                    let artist = tag.artist().as_deref().unwrap_or("").to_string();
-                    let title = tag.title().as_deref().unwrap_or("").to_string();
-
-                    // // import keys from https://docs.rs/lofty/latest/lofty/tag/enum.ItemKey.html
-                    // println!(
-                    //     "Album Artist: {}",
-                    //     tag.get_string(&ItemKey::AlbumArtist).unwrap_or("None")
-                    // );
-
-                    // let properties = tagged_file.properties();
-                    //
-                    // let duration = properties.duration();
-                    // let seconds = duration.as_secs() % 60;
-                    //
-                    // let duration_display =
-                    //     format!("{:02}:{:02}", (duration.as_secs() - seconds) / 60, seconds);
-                    //
-                    // println!("--- Audio Properties ---");
-                    // println!(
-                    //     "Bitrate (Audio): {}",
-                    //     properties.audio_bitrate().unwrap_or(0)
-                    // );
-                    // println!(
-                    //     "Bitrate (Overall): {}",
-                    //     properties.overall_bitrate().unwrap_or(0)
-                    // );
-                    // println!("Sample Rate: {}", properties.sample_rate().unwrap_or(0));
-                    // println!("Bit depth: {}", properties.bit_depth().unwrap_or(0));
-                    // println!("Channels: {}", properties.channels().unwrap_or(0));
-                    // println!("Duration: {duration_display}");
-
-                    println!(
-                        "found tag for {:#?}, {:#?} - {:#?}",
-                        entry.path(),
-                        artist,
-                        title
-                    );
-
-                    FsFileType::AudioFile {
-                        artist: Some(artist),
-                        title: Some(title),
-                    }
-                } else {
-                    println!("not found tag for {:#?}", entry.path());
-                    FsFileType::AudioFile {
-                        artist: None,
-                        title: None,
+                let op = tree.node(child_idx).data().op;
+                if op.reads_tags() {
+                    if matches!(op, SyncOp::Insert) {
+                        inserted_files.push(child_idx);
                     }
+                    tag_reads.push((child_idx, entry.into_path()));
                 }
-            };
+            }
+        }
 
-            let parent_idx = stack[depth - 1];
-            let parent_id = tree.node(parent_idx).data().db_id;
+        let (parent_idxs, entries): (Vec<_>, Vec<_>) = sniff_candidates.into_iter().unzip();
+        let is_audio = self.sniff_audio(&entries)?;
+        for ((parent_idx, entry), is_audio) in parent_idxs.into_iter().zip(entries).zip(is_audio) {
+            if !is_audio {
+                let dir_id = tree.node(parent_idx).data().db_id;
+                offer_folder_artwork(&mut folder_artwork, dir_id, entry.into_path());
+                continue;
+            }
 
-            let (node_id, op) = if let Some(id) = path_map.remove(&(Some(parent_id), name)) {
-                let filenode_track = self.entries.get(&id).unwrap();
+            let Some(child_idx) = self.push_entry(
+                &mut tree,
+                parent_idx,
+                &entry,
+                &mut path_map,
+                &mut identity_map,
+            )?
+            else {
+                continue;
+            };
 
-                let filenode_identity = FileIdentity {
-                    inode: filenode_track.0.inode,
-                    device: filenode_track.0.device,
-                };
-                identity_map.remove(&filenode_identity);
+            let op = tree.node(child_idx).data().op;
+            if op.reads_tags() {
+                if matches!(op, SyncOp::Insert) {
+                    inserted_files.push(child_idx);
+                }
+                tag_reads.push((child_idx, entry.into_path()));
+            }
+        }
 
-                if filenode_identity != identity
-                    || filenode_track.0.mtime != mtime
-                    || filenode_track.0.size != size
-                {
-                    (filenode_track.0.id, SyncOp::UpdateMeta)
-                } else {
-                    (filenode_track.0.id, SyncOp::Synced)
+        self.read_tags(&mut tree, tag_reads)?;
+
+        // Whatever wasn't matched by path or identity no longer exists under this root.
+        // Nodes below a deleted directory are listed as well, deleting them is a
+        // no-op once the `ON DELETE CASCADE` from their parent has run.
+        let deleted = path_map.into_values().collect();
+
+        let errors = std::mem::take(&mut self.errors);
+        self.progress.errors += errors.len();
+
+        Ok(LibraryState {
+            fs_tree: tree,
+            deleted,
+            inserted_files,
+            errors,
+            folder_artwork: folder_artwork
+                .into_iter()
+                .map(|(dir_id, (_, path))| (dir_id, path))
+                .collect(),
+        })
+    }
+
+    /// Adds the node of `entry` below `parent_idx`, matched against what was indexed
+    /// by its path, then by its identity to detect moves. `None` if its metadata
+    /// couldn't be read, in which case whatever was indexed there is kept.
+    fn push_entry(
+        &mut self,
+        tree: &mut DynTree<FsNode>,
+        parent_idx: NodeIdx<Dyn<FsNode>>,
+        entry: &DirEntry,
+        path_map: &mut HashMap<(Option<Uuid>, String), Uuid>,
+        identity_map: &mut HashMap<FileIdentity, Uuid>,
+    ) -> anyhow::Result<Option<NodeIdx<Dyn<FsNode>>>> {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let parent_id = tree.node(parent_idx).data().db_id;
+
+        let meta = match entry
+            .metadata()
+            .map_err(anyhow::Error::from)
+            .and_then(|meta| {
+                let mtime = meta.modified()?;
+                Ok((meta, mtime))
+            }) {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.errors
+                    .push(ScanError::new(entry.path(), ScanErrorKind::Metadata, e));
+
+                // Don't delete a node just because it couldn't be examined this time,
+                // nor anything below it.
+                if let Some(&id) = path_map.get(&(Some(parent_id), name)) {
+                    self.keep_unexamined(id, path_map, identity_map);
                 }
-            } else if let Some(existing_id) = identity_map.remove(&identity) {
-                let filenode_track = &self.entries[&existing_id];
+                return Ok(None);
+            }
+        };
+        let (meta, mtime) = meta;
 
-                println!(
-                    "Detected Move (Inode): {:#?} -> {:#?}",
-                    entry.path(),
-                    tree.node(parent_idx).data().path
-                );
-                (
-                    existing_id,
-                    SyncOp::Move {
-                        old_parent_id: filenode_track.0.parent_id,
-                    },
-                )
-            } else if let Some(&existing_id) = self.identity_map.get(&identity) {
-                let filenode_track = &self.entries[&existing_id];
+        let is_dir = meta.is_dir();
 
-                println!(
-                    "Detected Move (Cross Library) (Inode): {:#?} -> {:#?}",
-                    entry.path(),
-                    tree.node(parent_idx).data().path
-                );
-                (
-                    existing_id,
-                    SyncOp::Move {
-                        old_parent_id: filenode_track.0.parent_id,
-                    },
-                )
-            } else {
-                (Uuid::new_v4(), SyncOp::Insert)
+        self.control.check()?;
+        if is_dir {
+            self.progress.dirs_visited += 1;
+        } else {
+            self.progress.files_examined += 1;
+        }
+        if (self.progress.dirs_visited + self.progress.files_examined)
+            .is_multiple_of(PROGRESS_INTERVAL)
+        {
+            self.report();
+        }
+
+        let identity = get_identity(&meta);
+        let mtime = DateTime::<Utc>::from(mtime);
+        let size = meta.len();
+        let file_type = if entry.file_type().is_dir() {
+            FsFileType::Directory
+        } else {
+            // Filled in by `read_tags` once the walk is done, if needed.
+            FsFileType::AudioFile(TrackTags::default())
+        };
+
+        let (node_id, op) = if let Some(id) = path_map.remove(&(Some(parent_id), name)) {
+            let filenode_track = self.entries.get(&id).unwrap();
+
+            let filenode_identity = FileIdentity {
+                inode: filenode_track.0.inode,
+                device: filenode_track.0.device,
             };
+            identity_map.remove(&filenode_identity);
+
+            if filenode_identity != identity
+                || filenode_track.0.mtime != mtime
+                || filenode_track.0.size != size
+                // Not hashable so far, or read before scans extracted everything
+                // they do now.
+                || (!is_dir && filenode_track.0.audio_hash.is_none())
+                || (!is_dir && filenode_track.0.read_version < READ_VERSION)
+            {
+                (filenode_track.0.id, SyncOp::UpdateMeta)
+            } else {
+                (filenode_track.0.id, SyncOp::Synced)
+            }
+        } else if let Some(existing_id) = identity_map.remove(&identity) {
+            let filenode_track = &self.entries[&existing_id];
+            // The old location is gone, it mustn't be reported as deleted.
+            path_map.remove(&(filenode_track.0.parent_id, filenode_track.0.name.clone()));
+
+            log::debug!(
+                "Detected Move (Inode): {:#?} -> {:#?}",
+                entry.path(),
+                tree.node(parent_idx).data().path
+            );
+            (
+                existing_id,
+                SyncOp::Move {
+                    old_parent_id: filenode_track.0.parent_id,
+                },
+            )
+        } else if let Some(&existing_id) = self.identity_map.get(&identity) {
+            let filenode_track = &self.entries[&existing_id];
 
-            let child_idx = tree.node_mut(parent_idx).push_child(FsNode {
-                path: entry.path().into(),
-                file_type,
-                identity,
-                size,
-                mtime,
-                db_id: node_id,
-                parent_id: Some(parent_id),
-                op,
-            });
+            log::debug!(
+                "Detected Move (Outside Scan Root) (Inode): {:#?} -> {:#?}",
+                entry.path(),
+                tree.node(parent_idx).data().path
+            );
+            (
+                existing_id,
+                SyncOp::Move {
+                    old_parent_id: filenode_track.0.parent_id,
+                },
+            )
+        } else {
+            (Uuid::new_v4(), SyncOp::Insert)
+        };
 
-            if is_dir {
-                if stack.len() >= depth {
-                    stack.push(child_idx);
-                } else {
-                    stack[depth] = child_idx;
+        if !matches!(op, SyncOp::Insert) {
+            self.claimed.insert(node_id);
+        }
+
+        let child_idx = tree.node_mut(parent_idx).push_child(FsNode {
+            path: entry.path().into(),
+            file_type,
+            identity,
+            size,
+            mtime,
+            db_id: node_id,
+            parent_id: Some(parent_id),
+            hashes: None,
+            format: None,
+            op,
+            relinked_track: None,
+        });
+
+        Ok(Some(child_idx))
+    }
+
+    /// Probes the content of `entries`, files whose extension doesn't tell whether
+    /// they're audio, on the worker pool of [`Self::read_tags`] and in the same batches.
+    fn sniff_audio(&mut self, entries: &[DirEntry]) -> anyhow::Result<Vec<bool>> {
+        let mut is_audio = Vec::with_capacity(entries.len());
+
+        for entries in entries.chunks(TAG_READ_BATCH) {
+            self.control.check()?;
+
+            is_audio.extend(
+                entries
+                    .par()
+                    .map(|entry| {
+                        sniff(entry.path()).unwrap_or_else(|e| {
+                            log::warn!("Unable to sniff {:#?}: {:#}", entry.path(), e);
+                            false
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        Ok(is_audio)
+    }
+
+    /// Reads the tags of `tag_reads` on a worker pool, a batch at a time, and stores
+    /// them in their nodes.
+    ///
+    /// The results of a batch come back in input order, so the tree ends up the same
+    /// no matter how the reads were scheduled.
+    fn read_tags(
+        &mut self,
+        tree: &mut DynTree<FsNode>,
+        tag_reads: Vec<(NodeIdx<Dyn<FsNode>>, PathBuf)>,
+    ) -> anyhow::Result<()> {
+        self.progress.tags_total += tag_reads.len();
+        let (idxs, paths): (Vec<_>, Vec<_>) = tag_reads.into_iter().unzip();
+
+        for (idxs, paths) in idxs
+            .chunks(TAG_READ_BATCH)
+            .zip(paths.chunks(TAG_READ_BATCH))
+        {
+            self.control.check()?;
+
+            let infos = paths
+                .par()
+                .map(|path| read_audio_file(path, &self.artwork_cache))
+                .collect::<Vec<_>>();
+
+            for (&idx, info) in idxs.iter().zip(infos) {
+                let mut node = tree.node_mut(idx);
+                let data = node.data_mut();
+                (data.file_type, data.hashes, data.format) = match info {
+                    Ok(info) => (info.file_type, info.hashes, Some(info.format)),
+                    Err(e) => {
+                        self.errors.push(e);
+                        (FsFileType::Unreadable, None, None)
+                    }
+                };
+            }
+
+            self.progress.tags_read += idxs.len();
+            self.report();
+        }
+
+        Ok(())
+    }
+
+    fn report(&self) {
+        self.control.send(ScanEvent::Progress(self.progress));
+    }
+
+    /// Attaches inserted files to the tracks of deleted files, or of `detached_tracks`,
+    /// with the same audio, so that a file copied to another disk or downloaded again
+    /// keeps its track. Run after [`Self::retain_unclaimed`].
+    ///
+    /// When several tracks share the audio, the one whose tags match too is preferred.
+    pub fn relink_by_hash(
+        &self,
+        library_states: &mut [LibraryState],
+        detached_tracks: Vec<(Uuid, Blake3Hash)>,
+    ) {
+        let mut candidates: HashMap<Blake3Hash, Vec<(Uuid, Option<Blake3Hash>)>> = HashMap::new();
+
+        for library_state in library_states.iter() {
+            for id in &library_state.deleted {
+                if let Some((filenode, Some(track))) = self.entries.get(id)
+                    && let Some(audio_hash) = filenode.audio_hash
+                {
+                    candidates
+                        .entry(audio_hash)
+                        .or_default()
+                        .push((track.id, filenode.meta_hash));
                 }
             }
         }
+        for (track_id, audio_hash) in detached_tracks {
+            candidates
+                .entry(audio_hash)
+                .or_default()
+                .push((track_id, None));
+        }
+
+        // `deleted` isn't in any particular order.
+        for tracks in candidates.values_mut() {
+            tracks.sort_by_key(|(id, _)| *id);
+        }
 
-        Ok(LibraryState { fs_tree: tree })
+        for library_state in library_states {
+            for &idx in &library_state.inserted_files {
+                let mut node = library_state.fs_tree.node_mut(idx);
+                let data = node.data_mut();
+                let Some(hashes) = data.hashes else {
+                    continue;
+                };
+                let Some(tracks) = candidates
+                    .get_mut(&hashes.audio)
+                    .filter(|tracks| !tracks.is_empty())
+                else {
+                    continue;
+                };
+
+                let i = tracks
+                    .iter()
+                    .position(|(_, meta_hash)| *meta_hash == Some(hashes.meta))
+                    .unwrap_or(0);
+                data.relinked_track = Some(tracks.remove(i).0);
+            }
+        }
+    }
+
+    /// Drops deletions of nodes that another scan root found moved into its tree.
+    ///
+    /// Roots are scanned one after the other, so the one a node was moved out of may
+    /// have already listed it as deleted before the move was detected.
+    pub fn retain_unclaimed(&self, library_states: &mut [LibraryState]) {
+        for library_state in library_states {
+            library_state
+                .deleted
+                .retain(|id| !self.claimed.contains(id));
+        }
     }
 }
 
+/// Files are sniffed and their tags read on a worker pool this many at a time, which
+/// bounds how many parsed files are held in memory at once.
+const TAG_READ_BATCH: usize = 256;
+
+/// Keeps `path` as the artwork of the directory `dir_id` if it ranks better than what
+/// was found there so far.
+fn offer_folder_artwork(
+    folder_artwork: &mut HashMap<Uuid, (usize, PathBuf)>,
+    dir_id: Uuid,
+    path: PathBuf,
+) {
+    let Some(rank) = path
+        .file_name()
+        .and_then(|name| folder_artwork_rank(&name.to_string_lossy()))
+    else {
+        return;
+    };
+
+    if folder_artwork
+        .get(&dir_id)
+        .is_none_or(|(best, _)| rank < *best)
+    {
+        folder_artwork.insert(dir_id, (rank, path));
+    }
+}
+
+/// Walked entries between two progress events.
+const PROGRESS_INTERVAL: usize = 1024;
+
+/// A directory to diff against the `filenodes` subtree below `id`.
+#[derive(Debug, Clone)]
+pub struct ScanRoot {
+    pub id: Uuid,
+    pub path: PathBuf,
+    pub parent_id: Option<Uuid>,
+    /// How the directory itself is synced.
+    pub op: SyncOp,
+    /// Of the library the directory is in.
+    pub library_path: PathBuf,
+    pub extensions: ExtensionFilter,
+    pub ignore: IgnoreRules,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    /// Keep the `tracks` rows of deleted files, detached from `filenodes`, instead of
+    /// deleting them, so play counts and playlists survive a drive that's only
+    /// unplugged for a while.
+    pub keep_missing_tracks: bool,
+}
+
 pub struct LibraryState {
     pub fs_tree: DynTree<FsNode>,
+    /// `filenodes` ids of files and directories that are gone from disk.
+    pub deleted: Vec<Uuid>,
+    /// Audio files that are new to `filenodes`.
+    pub inserted_files: Vec<NodeIdx<Dyn<FsNode>>>,
+    /// Files below the root that were skipped or couldn't be fully read.
+    pub errors: Vec<ScanError>,
+    /// The best picture to take as the artwork of each walked directory that has one,
+    /// see [`folder_artwork_rank`].
+    pub folder_artwork: HashMap<Uuid, PathBuf>,
 }
 
 impl LibraryState {
+    /// The nodes that need a database write, parents before their children.
+    ///
+    /// Copied out up front in a plain fn so that nothing borrowed from an `orx_tree`
+    /// traversal is held across an `.await` in `sync_with_db`. Doing so made the
+    /// future fail the `Send` bound of `rt.spawn(io_thread(..))` with an HRTB
+    /// lifetime error.
+    pub fn pending(&self) -> Vec<FsNode> {
+        self.fs_tree
+            .root()
+            .walk_with(&mut Traversal.dfs())
+            .filter(|data| !matches!(data.op, SyncOp::Synced))
+            .cloned()
+            .collect()
+    }
+
+    /// How many nodes this state inserts, updates, moves or deletes.
+    pub fn changes(&self) -> usize {
+        self.fs_tree
+            .root()
+            .walk_with(&mut Traversal.dfs())
+            .filter(|data| !matches!(data.op, SyncOp::Synced))
+            .count()
+            + self.deleted.len()
+    }
+
     pub async fn sync_with_db(
         &self,
         connection: &mut sqlx::SqliteConnection,
     ) -> anyhow::Result<()> {
-        for node in self
-            .fs_tree
-            .root()
-            .walk_with(&mut Traversal.dfs().over_nodes())
-        {
-            let data = node.data();
+        for data in &self.pending() {
             match data.op {
                 SyncOp::Synced => {}
                 SyncOp::UpdateMeta => {
-                    println!("update meta: {:#?}, {:#?}", data, data.mtime);
+                    log::trace!("update meta: {:#?}, {:#?}", data, data.mtime);
 
-                    sqlx::query!(
-                        r#"
-                        UPDATE filenodes
-                        SET
-                            mtime = ?,
-                            size = ?
-                        WHERE id = ?;
-                        "#,
-                        data.mtime,
-                        data.size as i64,
-                        data.db_id,
-                    )
-                    .execute(&mut *connection)
-                    .await?;
+                    data.update_in_db(&mut *connection).await?;
                 }
                 SyncOp::Insert => {
-                    println!(
+                    log::debug!(
                         "insert {:#?}, {:#?}, {:#?}",
-                        data.db_id, data.path, data.parent_id
+                        data.db_id,
+                        data.path,
+                        data.parent_id
                     );
 
                     data.insert_into_db(&mut *connection).await?;
                 }
                 SyncOp::Move { old_parent_id } => {
-                    println!("moved: {:#?}, {:#?}", data, old_parent_id);
+                    log::debug!("moved: {:#?}, {:#?}", data, old_parent_id);
 
                     sqlx::query!(
                         r#"
                         UPDATE filenodes
-                        SET parent_id = ?
+                        SET
+                            parent_id = ?,
+                            name = ?
                         WHERE id = ?;
                         "#,
                         data.parent_id,
+                        data.name(),
                         data.db_id,
                     )
                     .execute(&mut *connection)
@@ -682,39 +2085,309 @@ impl LibraryState {
 
         Ok(())
     }
+
+    /// Replaces the `scan_errors` below the root with this scan's `errors`.
+    pub async fn sync_errors_with_db(
+        &self,
+        connection: &mut sqlx::SqliteConnection,
+    ) -> anyhow::Result<()> {
+        let root = self
+            .fs_tree
+            .root()
+            .data()
+            .path
+            .to_string_lossy()
+            .to_string();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM scan_errors
+            WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/';
+            "#,
+            root
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        for error in &self.errors {
+            let path = error.path.to_string_lossy();
+
+            sqlx::query!(
+                r#"
+                INSERT OR REPLACE INTO scan_errors (path, kind, message)
+                VALUES (?, ?, ?);
+                "#,
+                path,
+                error.kind,
+                error.message,
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes `deleted` from `filenodes`. Run after every library's `sync_with_db`,
+    /// so that nodes moved out of a deleted directory aren't cascaded along with it.
+    pub async fn delete_from_db(
+        &self,
+        connection: &mut sqlx::SqliteConnection,
+        options: SyncOptions,
+    ) -> anyhow::Result<()> {
+        for id in &self.deleted {
+            log::debug!("delete: {:#?}", id);
+
+            if !options.keep_missing_tracks {
+                sqlx::query!(
+                    r#"
+                    WITH RECURSIVE subtree AS (
+                        SELECT f.id
+                        FROM filenodes f
+                        WHERE f.id = ?
+
+                        UNION ALL
+
+                        SELECT f.id
+                        FROM filenodes f
+                        JOIN subtree s ON f.parent_id = s.id
+                    )
+                    DELETE FROM tracks
+                    WHERE filenode_id IN (SELECT id FROM subtree);
+                    "#,
+                    id,
+                )
+                .execute(&mut *connection)
+                .await?;
+            }
+
+            // Cascades to the subtree, `tracks` of files in it are detached
+            // (`ON DELETE SET NULL`) if they're still around.
+            sqlx::query!(
+                r#"
+                DELETE FROM filenodes
+                WHERE id = ?;
+                "#,
+                id,
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+
+        Ok(())
+    }
 }
 
 pub enum FetchLibraryRes {
     Snapshot(FxIndexMap<Uuid, Arc<Track>>),
 }
 
+/// Tracks that were added, retagged or moved, and the ids of those that are gone.
+#[derive(Debug, Default)]
+pub struct LibraryDelta {
+    pub upserted: Vec<Arc<Track>>,
+    pub removed: Vec<Uuid>,
+}
+
+impl LibraryDelta {
+    pub fn is_empty(&self) -> bool {
+        self.upserted.is_empty() && self.removed.is_empty()
+    }
+}
+
+pub enum IoMainMsg {
+    LibraryDelta(LibraryDelta),
+}
+
 pub enum MainIoMsg {
     FetchLibrary {
         reply: flume::Sender<FetchLibraryRes>,
     },
+    /// Debounced filesystem events below a library root.
+    FsChanged {
+        paths: Vec<PathBuf>,
+    },
+    /// Rejected with `ScanEvent::Failed` while another one is running.
+    Scan {
+        target: ScanTarget,
+        control: ScanControl,
+    },
+    /// Sent by the task running a `Scan` once it's done.
+    ScanFinished {
+        committed: bool,
+    },
+    ScanErrors {
+        reply: flume::Sender<anyhow::Result<Vec<ScanError>>>,
+    },
+    Library(LibraryMsg),
+    Browse(BrowseMsg),
+    TagEdit(TagEditMsg),
+    /// Sent periodically, to notice roots that were mounted or unmounted.
+    CheckLibraryRoots,
+    Duplicates {
+        reply: flume::Sender<anyhow::Result<Vec<DuplicateGroup>>>,
+    },
+    /// Hides the other copies of `track_id`, or shows them again if `preferred` is
+    /// false.
+    PreferDuplicate {
+        track_id: Uuid,
+        preferred: bool,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+    ResolveContext {
+        context: PlayContext,
+        reply: flume::Sender<anyhow::Result<Vec<Uuid>>>,
+    },
 }
 
-static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+impl MainIoMsg {
+    /// Whether handling this can write to the database. These wait for a running
+    /// `Scan`, which would otherwise commit a diff against what they changed.
+    fn writes(&self) -> bool {
+        matches!(
+            self,
+            MainIoMsg::FsChanged { .. }
+                | MainIoMsg::Library(_)
+                | MainIoMsg::TagEdit(_)
+                | MainIoMsg::CheckLibraryRoots
+                | MainIoMsg::PreferDuplicate { .. }
+        )
+    }
+}
 
-pub async fn io_thread(main_io_rx: flume::Receiver<MainIoMsg>) -> anyhow::Result<()> {
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+pub async fn io_thread(
+    main_io_rx: flume::Receiver<MainIoMsg>,
+    main_io_tx: flume::Sender<MainIoMsg>,
+    io_main_tx: Sender<IoMainMsg>,
+) -> anyhow::Result<()> {
     let database_url =
         env::var("DATABASE_URL").expect("DATABASE_URL is not set in the environment");
 
-    let mut state = PersistenceState {
-        db: sqlx::Pool::connect_with(
+    let mut state = PersistenceState::new(
+        sqlx::Pool::connect_with(
             sqlx::sqlite::SqliteConnectOptions::from_str(&database_url)?.create_if_missing(true),
         )
         .await?,
-    };
+    );
 
     MIGRATOR.run(&state.db).await?;
 
+    // Events are held back while the scan started by `FetchLibrary` runs, anything
+    // they describe is then already synced and the rescans are no-ops.
+    std::thread::spawn({
+        let main_io_tx = main_io_tx.clone();
+        move || poll_library_roots(main_io_tx)
+    });
+
+    let mut watcher = LibraryWatcher::new(main_io_tx.clone())?;
+    for path in state.library_paths().await? {
+        if let Err(e) = watcher.watch(&path) {
+            log::error!("Unable to watch {:#?}: {:#?}", path, e);
+        }
+    }
+
+    // Reads are still served while a scan runs, writes are held back until it's done.
+    let mut scanning = false;
+    let mut deferred = VecDeque::new();
+
     loop {
-        let msg = main_io_rx.recv_async().await?;
+        let msg = if !scanning && let Some(msg) = deferred.pop_front() {
+            msg
+        } else {
+            main_io_rx.recv_async().await?
+        };
+
+        if scanning && msg.writes() {
+            deferred.push_back(msg);
+            continue;
+        }
 
         match msg {
             MainIoMsg::FetchLibrary { reply } => {
-                state.handle_fetch_library(reply).await?;
+                match state.handle_fetch_library(reply).await {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_fetch_library error: {:#?}", e),
+                }
+
+                if !scanning {
+                    scanning = true;
+                    state.handle_scan(ScanTarget::Libraries, ScanControl::default(), &main_io_tx);
+                }
+            }
+            MainIoMsg::FsChanged { paths } => {
+                match state.handle_fs_changed(paths, &io_main_tx).await {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_fs_changed error: {:#?}", e),
+                }
+            }
+            MainIoMsg::Scan { control, .. } if scanning => {
+                control.finish(&Err(anyhow::anyhow!("Another scan is already running")));
+            }
+            MainIoMsg::Scan { target, control } => {
+                scanning = true;
+                state.handle_scan(target, control, &main_io_tx);
+            }
+            MainIoMsg::ScanFinished { committed } => {
+                scanning = false;
+                match state.handle_scan_finished(committed, &io_main_tx).await {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_scan_finished error: {:#?}", e),
+                }
+            }
+            MainIoMsg::ScanErrors { reply } => match state.handle_scan_errors(reply).await {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_scan_errors error: {:#?}", e),
+            },
+            MainIoMsg::CheckLibraryRoots => {
+                match state
+                    .handle_check_library_roots(&mut watcher, &io_main_tx)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_check_library_roots error: {:#?}", e),
+                }
+            }
+            MainIoMsg::Library(msg) => {
+                match state
+                    .handle_library_msg(msg, &mut watcher, &io_main_tx)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_library_msg error: {:#?}", e),
+                }
+            }
+            MainIoMsg::Browse(msg) => match state.handle_browse_msg(msg).await {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_browse_msg error: {:#?}", e),
+            },
+            MainIoMsg::TagEdit(msg) => match state.handle_tag_edit_msg(msg, &io_main_tx).await {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_tag_edit_msg error: {:#?}", e),
+            },
+            MainIoMsg::Duplicates { reply } => match state.handle_duplicates(reply).await {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_duplicates error: {:#?}", e),
+            },
+            MainIoMsg::PreferDuplicate {
+                track_id,
+                preferred,
+                reply,
+            } => {
+                match state
+                    .handle_prefer_duplicate(track_id, preferred, reply, &io_main_tx)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_prefer_duplicate error: {:#?}", e),
+                }
+            }
+            MainIoMsg::ResolveContext { context, reply } => {
+                match state.handle_resolve_context(context, reply).await {
+                    Ok(_) => {}
+                    Err(e) => log::error!("handle_resolve_context error: {:#?}", e),
+                }
             }
         }
     }
@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use uuid::Uuid;
+
+use super::PersistenceState;
+use super::TrackTags;
+
+/// An album, grouped from the tags of its tracks by [`album_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Album {
+    pub id: Uuid,
+    /// The most common spelling among its tracks.
+    pub title: String,
+    /// The album artist, or the artist of every track if they don't have one.
+    pub artist: Option<String>,
+    /// Flagged as a compilation, or tracks by several artists without an album artist.
+    pub compilation: bool,
+    /// The earliest year of its tracks.
+    pub year: Option<i32>,
+    pub discs: u32,
+    pub tracks: u32,
+    pub duration_ms: u64,
+    /// The first track with an embedded picture.
+    pub artwork_track: Option<Uuid>,
+}
+
+/// Which album a track is grouped into, if it has one.
+///
+/// The MusicBrainz album id if it's tagged, otherwise the album title with the
+/// album artist. Without an album artist the title is only grouped within a folder,
+/// so that compilations aren't split up by their track artists, and two albums with
+/// the same title aren't merged. Discs of a set are merged whether they're told apart
+/// by the title, like `Album (Disc 2)`, or by a folder, like `Album/CD2`.
+pub fn album_key(tags: &TrackTags, path: &Path) -> Option<String> {
+    if let Some(id) = &tags.musicbrainz_album_id {
+        return Some(format!("mbid:{}", id.to_lowercase()));
+    }
+
+    let title = strip_disc_suffix(tags.album.as_deref()?).to_lowercase();
+    let key = match &tags.album_artist {
+        Some(artist) => format!("artist:{}\n{}", artist.to_lowercase(), title),
+        None => format!("folder:{}\n{}", album_dir(path).to_string_lossy(), title),
+    };
+
+    Some(key)
+}
+
+/// The folder of the album the file at `path` is in, above any disc folder.
+fn album_dir(path: &Path) -> &Path {
+    let Some(parent) = path.parent() else {
+        return path;
+    };
+
+    match parent.file_name() {
+        Some(name) if is_disc_name(&name.to_string_lossy()) => parent.parent().unwrap_or(parent),
+        _ => parent,
+    }
+}
+
+/// `Album (Disc 2)`, `Album [CD2]` and `Album - Disc 2` without the disc.
+fn strip_disc_suffix(title: &str) -> &str {
+    let title = title.trim_end();
+
+    if let Some(rest) = title.strip_suffix([')', ']'])
+        && let Some(open) = rest.rfind(['(', '['])
+        && is_disc_name(&rest[open + 1..])
+    {
+        return rest[..open].trim_end();
+    }
+
+    if let Some(dash) = title.rfind(" - ")
+        && is_disc_name(&title[dash + 3..])
+    {
+        return title[..dash].trim_end();
+    }
+
+    title
+}
+
+/// `CD2`, `Disc 2`, `disk_02` and the like.
+fn is_disc_name(name: &str) -> bool {
+    let name = name.trim().to_ascii_lowercase();
+    let Some(number) = ["cd", "disc", "disk"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+    else {
+        return false;
+    };
+    let number = number.trim_start_matches([' ', '-', '_']);
+
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Groups tracks into albums by their `album_key`, creating and deleting albums as
+/// needed, and refreshes what the albums show from their tracks.
+pub async fn sync_albums(connection: &mut sqlx::SqliteConnection) -> anyhow::Result<()> {
+    let new_keys = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT album_key AS "album_key!"
+        FROM tracks
+        WHERE album_key IS NOT NULL
+            AND album_key NOT IN (SELECT grouping_key FROM albums)
+        "#
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    for key in new_keys {
+        sqlx::query!(
+            "INSERT INTO albums (id, grouping_key, title) VALUES (?, ?, '')",
+            Uuid::new_v4(),
+            key,
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE tracks
+        SET album_id = (SELECT a.id FROM albums a WHERE a.grouping_key = tracks.album_key)
+        WHERE album_id IS NOT (SELECT a.id FROM albums a WHERE a.grouping_key = tracks.album_key)
+        "#
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM albums WHERE id NOT IN (SELECT album_id FROM tracks WHERE album_id IS NOT NULL)"
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE albums
+        SET
+            title = coalesce((
+                SELECT t.album
+                FROM tracks t
+                WHERE t.album_id = albums.id AND t.album IS NOT NULL
+                GROUP BY t.album
+                ORDER BY COUNT(*) DESC, length(t.album), t.album
+                LIMIT 1
+            ), ''),
+            artist = coalesce(
+                (
+                    SELECT t.album_artist
+                    FROM tracks t
+                    WHERE t.album_id = albums.id AND t.album_artist IS NOT NULL
+                    GROUP BY t.album_artist
+                    ORDER BY COUNT(*) DESC, t.album_artist
+                    LIMIT 1
+                ),
+                (
+                    SELECT CASE WHEN COUNT(DISTINCT t.artist) = 1 THEN min(t.artist) END
+                    FROM tracks t
+                    WHERE t.album_id = albums.id
+                )
+            ),
+            compilation = EXISTS (
+                SELECT 1
+                FROM tracks t
+                WHERE t.album_id = albums.id AND t.compilation
+            ) OR (
+                NOT EXISTS (
+                    SELECT 1
+                    FROM tracks t
+                    WHERE t.album_id = albums.id AND t.album_artist IS NOT NULL
+                ) AND (
+                    SELECT COUNT(DISTINCT t.artist)
+                    FROM tracks t
+                    WHERE t.album_id = albums.id
+                ) > 1
+            ),
+            year = (SELECT min(t.year) FROM tracks t WHERE t.album_id = albums.id)
+        "#
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+impl PersistenceState {
+    /// Albums with a track in the libraries, by artist, year and title.
+    pub async fn albums(&self) -> anyhow::Result<Vec<Album>> {
+        self.fetch_albums(None).await
+    }
+
+    pub async fn album(&self, album_id: Uuid) -> anyhow::Result<Album> {
+        self.fetch_albums(Some(album_id))
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Album {} isn't in the library", album_id))
+    }
+
+    /// The tracks of `album_id` in disc/track order.
+    pub async fn album_tracks(&self, album_id: Uuid) -> anyhow::Result<Vec<Uuid>> {
+        let ids = sqlx::query_scalar!(
+            r#"
+            SELECT t.id AS "id: Uuid"
+            FROM visible_tracks t
+            WHERE t.album_id = ?
+            ORDER BY
+                coalesce(t.disc_number, 1),
+                t.track_number IS NULL,
+                t.track_number,
+                t.title
+            "#,
+            album_id,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn fetch_albums(&self, album_id: Option<Uuid>) -> anyhow::Result<Vec<Album>> {
+        let albums = sqlx::query_as!(
+            Album,
+            r#"
+            SELECT
+                a.id AS "id: Uuid",
+                a.title,
+                a.artist AS "artist?",
+                a.compilation AS "compilation: bool",
+                a.year AS "year: i32",
+                max(coalesce(t.disc_number, 1)) AS "discs!: u32",
+                COUNT(t.id) AS "tracks: u32",
+                coalesce(sum(t.duration_ms), 0) AS "duration_ms!: u64",
+                (
+                    SELECT p.id
+                    FROM visible_tracks p
+                    WHERE p.album_id = a.id AND p.has_picture
+                    ORDER BY coalesce(p.disc_number, 1), p.track_number
+                    LIMIT 1
+                ) AS "artwork_track: Uuid"
+            FROM albums a
+            INNER JOIN visible_tracks t
+                ON t.album_id = a.id
+            WHERE (?1 IS NULL OR a.id = ?1)
+            GROUP BY a.id
+            ORDER BY a.artist IS NULL, a.artist, a.year, a.title
+            "#,
+            album_id,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(albums)
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use chrono::DateTime;
+use chrono::Utc;
+use image::GenericImageView as _;
+use image::ImageFormat;
+use image::ImageReader;
+use lofty::picture::PictureType;
+use lofty::tag::Tag;
+use orx_tree::NodeRef as _;
+use orx_tree::Traversal;
+use uuid::Uuid;
+
+use super::FsFileType;
+use super::LibraryState;
+use super::PersistenceState;
+use crate::db::types::Blake3Hash;
+
+/// Longest edge of the JPEG thumbnails generated for every picture.
+pub const THUMBNAIL_SIZES: &[u32] = &[96, 256, 512];
+
+/// Pictures next to audio files that are taken as the artwork of their folder, best
+/// first, with any of [`FOLDER_ARTWORK_EXTENSIONS`].
+const FOLDER_ARTWORK_STEMS: &[&str] = &["cover", "folder", "front", "album"];
+const FOLDER_ARTWORK_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// A picture in the [`ArtworkCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artwork {
+    pub hash: Blake3Hash,
+    pub mime: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Whose artwork to look up with [`PersistenceState::artwork`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtworkOwner {
+    /// Its embedded picture, or else the artwork of its folder.
+    Track(Uuid),
+    /// The artwork of its first track that has one.
+    Album(Uuid),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtworkImage {
+    pub artwork: Artwork,
+    /// Of the picture at the requested size.
+    pub path: PathBuf,
+}
+
+/// Pictures stored by their BLAKE3 hash, so that the cover embedded in every track of
+/// an album is only kept once, each with a thumbnail of every [`THUMBNAIL_SIZES`].
+#[derive(Debug, Clone)]
+pub struct ArtworkCache {
+    dir: PathBuf,
+}
+
+impl Default for ArtworkCache {
+    fn default() -> Self {
+        Self::new(
+            dirs::cache_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("nxm-music")
+                .join("artwork"),
+        )
+    }
+}
+
+impl ArtworkCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The picture `hash`, or its smallest thumbnail that is at least `size` pixels.
+    /// The picture itself if there's no such thumbnail.
+    pub fn path(&self, hash: &Blake3Hash, size: Option<u32>) -> PathBuf {
+        let hex = hash.to_hex();
+        match size.and_then(|size| THUMBNAIL_SIZES.iter().find(|s| **s >= size)) {
+            Some(size) => self.dir.join(format!("{hex}-{size}.jpg")),
+            None => self.dir.join(hex),
+        }
+    }
+
+    /// Adds the picture `bytes` and its thumbnails, unless they're cached already.
+    pub fn store(&self, bytes: &[u8]) -> anyhow::Result<Artwork> {
+        let hash = Blake3Hash::from(blake3::hash(bytes));
+        let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+        let format = reader.format().context("Unknown image format")?;
+
+        let cached = std::iter::once(None)
+            .chain(THUMBNAIL_SIZES.iter().copied().map(Some))
+            .all(|size| self.path(&hash, size).exists());
+
+        let (width, height) = if cached {
+            reader.into_dimensions()?
+        } else {
+            let image = reader.decode()?;
+            let (width, height) = image.dimensions();
+            std::fs::create_dir_all(&self.dir)?;
+
+            for &size in THUMBNAIL_SIZES {
+                let thumbnail = if width.max(height) > size {
+                    image.thumbnail(size, size).to_rgb8()
+                } else {
+                    image.to_rgb8()
+                };
+
+                let mut jpeg = Cursor::new(vec![]);
+                thumbnail.write_to(&mut jpeg, ImageFormat::Jpeg)?;
+                write_atomically(&self.path(&hash, Some(size)), jpeg.get_ref())?;
+            }
+            write_atomically(&self.path(&hash, None), bytes)?;
+
+            (width, height)
+        };
+
+        Ok(Artwork {
+            hash,
+            mime: format.to_mime_type().to_string(),
+            width,
+            height,
+        })
+    }
+
+    /// Deletes the picture `hash` and its thumbnails.
+    pub fn remove(&self, hash: &Blake3Hash) {
+        for size in std::iter::once(None).chain(THUMBNAIL_SIZES.iter().copied().map(Some)) {
+            let path = self.path(hash, size);
+            if let Err(e) = std::fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                log::warn!("Unable to remove {:#?}: {:#}", path, e);
+            }
+        }
+    }
+}
+
+/// Writes to a temporary file first, so that a picture being read by the UI, or
+/// stored by another scan thread, is never seen half written.
+fn write_atomically(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+/// Caches the front cover of `tag`, or else its first picture.
+pub fn store_embedded_artwork(cache: &ArtworkCache, tag: &Tag, path: &Path) -> Option<Artwork> {
+    let picture = tag
+        .pictures()
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| tag.pictures().first())?;
+
+    cache
+        .store(picture.data())
+        .inspect_err(|e| log::warn!("Unable to store the artwork of {:#?}: {:#}", path, e))
+        .ok()
+}
+
+/// How good a file named `name` is as the artwork of its folder, lower is better.
+pub fn folder_artwork_rank(name: &str) -> Option<usize> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if !FOLDER_ARTWORK_EXTENSIONS
+        .iter()
+        .any(|e| e.eq_ignore_ascii_case(ext))
+    {
+        return None;
+    }
+
+    FOLDER_ARTWORK_STEMS
+        .iter()
+        .position(|s| s.eq_ignore_ascii_case(stem))
+}
+
+/// The artwork of a directory, as stored in `folder_artworks`.
+#[derive(Debug, Clone)]
+pub struct FolderArtwork {
+    pub name: String,
+    pub mtime: DateTime<Utc>,
+    pub size: u64,
+    pub artwork: Artwork,
+}
+
+/// Caches the folder artwork found by the walks of `library_states`. Returns, for every
+/// walked directory whose artwork changed, its new artwork or `None` if it has none
+/// anymore. Pictures that are unchanged since `known` aren't read again.
+pub fn read_folder_artwork(
+    cache: &ArtworkCache,
+    library_states: &[LibraryState],
+    known: &HashMap<Uuid, FolderArtwork>,
+) -> Vec<(Uuid, Option<FolderArtwork>)> {
+    let mut changes = vec![];
+
+    for library_state in library_states {
+        let dirs = library_state
+            .fs_tree
+            .root()
+            .walk_with(&mut Traversal.dfs())
+            .filter(|data| matches!(data.file_type, FsFileType::Directory))
+            .map(|data| data.db_id)
+            .collect::<Vec<_>>();
+
+        for dir_id in dirs {
+            let known = known.get(&dir_id);
+            let Some(path) = library_state.folder_artwork.get(&dir_id) else {
+                if known.is_some() {
+                    changes.push((dir_id, None));
+                }
+                continue;
+            };
+
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let read = std::fs::metadata(path)
+                .and_then(|meta| Ok((DateTime::<Utc>::from(meta.modified()?), meta.len())))
+                .map_err(anyhow::Error::from)
+                .and_then(|(mtime, size)| {
+                    if let Some(known) = known
+                        && known.name == name
+                        && known.mtime == mtime
+                        && known.size == size
+                    {
+                        return Ok(None);
+                    }
+
+                    let artwork = cache.store(&std::fs::read(path)?)?;
+                    Ok(Some(FolderArtwork {
+                        name,
+                        mtime,
+                        size,
+                        artwork,
+                    }))
+                });
+
+            match read {
+                Ok(Some(folder_artwork)) => changes.push((dir_id, Some(folder_artwork))),
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("Unable to store the artwork {:#?}: {:#}", path, e);
+                    if known.is_some() {
+                        changes.push((dir_id, None));
+                    }
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+/// Adds `artwork` to `artworks`, which tracks and folders refer to.
+pub async fn insert_artwork(
+    connection: &mut sqlx::SqliteConnection,
+    artwork: &Artwork,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT OR IGNORE INTO artworks (hash, mime, width, height) VALUES (?, ?, ?, ?)",
+        artwork.hash,
+        artwork.mime,
+        artwork.width,
+        artwork.height,
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes the folder artwork changes of [`read_folder_artwork`].
+pub async fn sync_folder_artwork(
+    connection: &mut sqlx::SqliteConnection,
+    changes: &[(Uuid, Option<FolderArtwork>)],
+) -> anyhow::Result<()> {
+    for (dir_id, folder_artwork) in changes {
+        let Some(folder_artwork) = folder_artwork else {
+            sqlx::query!("DELETE FROM folder_artworks WHERE filenode_id = ?", dir_id)
+                .execute(&mut *connection)
+                .await?;
+            continue;
+        };
+
+        insert_artwork(&mut *connection, &folder_artwork.artwork).await?;
+        sqlx::query!(
+            r#"
+            INSERT OR REPLACE INTO folder_artworks (filenode_id, name, mtime, size, artwork_hash)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            dir_id,
+            folder_artwork.name,
+            folder_artwork.mtime,
+            folder_artwork.size as i64,
+            folder_artwork.artwork.hash,
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the pictures nothing refers to anymore, returning them so that they can be
+/// removed from the cache once the transaction is committed.
+pub async fn delete_unused_artwork(
+    connection: &mut sqlx::SqliteConnection,
+) -> anyhow::Result<Vec<Blake3Hash>> {
+    let hashes = sqlx::query_scalar!(
+        r#"
+        DELETE FROM artworks
+        WHERE hash NOT IN (SELECT artwork_hash FROM tracks WHERE artwork_hash IS NOT NULL)
+            AND hash NOT IN (SELECT artwork_hash FROM folder_artworks)
+        RETURNING hash AS "hash: Blake3Hash"
+        "#
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    Ok(hashes)
+}
+
+impl PersistenceState {
+    pub fn with_artwork_cache(mut self, artwork_cache: ArtworkCache) -> Self {
+        self.artwork_cache = artwork_cache;
+        self
+    }
+
+    pub(super) async fn load_folder_artwork(&self) -> anyhow::Result<HashMap<Uuid, FolderArtwork>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT
+                fa.filenode_id AS "filenode_id: Uuid",
+                fa.name,
+                fa.mtime AS "mtime: DateTime<Utc>",
+                fa.size AS "size: u64",
+                a.hash AS "hash: Blake3Hash",
+                a.mime,
+                a.width AS "width: u32",
+                a.height AS "height: u32"
+            FROM folder_artworks fa
+            INNER JOIN artworks a
+                ON a.hash = fa.artwork_hash
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|rec| {
+                (
+                    rec.filenode_id,
+                    FolderArtwork {
+                        name: rec.name,
+                        mtime: rec.mtime,
+                        size: rec.size,
+                        artwork: Artwork {
+                            hash: rec.hash,
+                            mime: rec.mime,
+                            width: rec.width,
+                            height: rec.height,
+                        },
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// The artwork of a track or album, at the thumbnail size for `size`, see
+    /// [`ArtworkCache::path`].
+    ///
+    /// A track without an embedded picture gets the artwork of its folder, or of the
+    /// folder above for albums split into disc folders.
+    pub async fn artwork(
+        &self,
+        owner: ArtworkOwner,
+        size: Option<u32>,
+    ) -> anyhow::Result<Option<ArtworkImage>> {
+        let (track_id, album_id) = match owner {
+            ArtworkOwner::Track(id) => (Some(id), None),
+            ArtworkOwner::Album(id) => (None, Some(id)),
+        };
+
+        let rec = sqlx::query!(
+            r#"
+            SELECT
+                a.hash AS "hash: Blake3Hash",
+                a.mime,
+                a.width AS "width: u32",
+                a.height AS "height: u32"
+            FROM tracks t
+            INNER JOIN filenodes f
+                ON f.id = t.filenode_id
+            LEFT JOIN filenodes d
+                ON d.id = f.parent_id
+            INNER JOIN artworks a
+                ON a.hash = coalesce(
+                    t.artwork_hash,
+                    (SELECT fa.artwork_hash FROM folder_artworks fa WHERE fa.filenode_id = d.id),
+                    (SELECT fa.artwork_hash FROM folder_artworks fa WHERE fa.filenode_id = d.parent_id)
+                )
+            WHERE t.id = ? OR t.album_id = ?
+            ORDER BY coalesce(t.disc_number, 1), t.track_number IS NULL, t.track_number
+            LIMIT 1
+            "#,
+            track_id,
+            album_id,
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(rec.map(|rec| {
+            let artwork = Artwork {
+                hash: rec.hash,
+                mime: rec.mime,
+                width: rec.width,
+                height: rec.height,
+            };
+
+            ArtworkImage {
+                path: self.artwork_cache.path(&artwork.hash, size),
+                artwork,
+            }
+        }))
+    }
+}
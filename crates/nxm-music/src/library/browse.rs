@@ -0,0 +1,135 @@
+use uuid::Uuid;
+
+use super::Album;
+use super::Artist;
+use super::ArtistCredit;
+use super::ArtworkImage;
+use super::ArtworkOwner;
+use super::Genre;
+use super::PersistenceState;
+use super::SearchResults;
+use super::TrackCursor;
+use super::TrackPage;
+use super::TrackQuery;
+
+/// Read-only queries for browsing the libraries.
+pub enum BrowseMsg {
+    Albums {
+        reply: flume::Sender<anyhow::Result<Vec<Album>>>,
+    },
+    Album {
+        album_id: Uuid,
+        reply: flume::Sender<anyhow::Result<Album>>,
+    },
+    AlbumTracks {
+        album_id: Uuid,
+        reply: flume::Sender<anyhow::Result<Vec<Uuid>>>,
+    },
+    Artists {
+        reply: flume::Sender<anyhow::Result<Vec<Artist>>>,
+    },
+    ArtistCredits {
+        artist_id: Uuid,
+        reply: flume::Sender<anyhow::Result<Vec<ArtistCredit>>>,
+    },
+    Artwork {
+        owner: ArtworkOwner,
+        size: Option<u32>,
+        reply: flume::Sender<anyhow::Result<Option<ArtworkImage>>>,
+    },
+    Genres {
+        reply: flume::Sender<anyhow::Result<Vec<Genre>>>,
+    },
+    GenreTracks {
+        genre_id: i64,
+        reply: flume::Sender<anyhow::Result<Vec<Uuid>>>,
+    },
+    Search {
+        query: String,
+        limit: usize,
+        reply: flume::Sender<anyhow::Result<SearchResults>>,
+    },
+    Query {
+        query: TrackQuery,
+        reply: flume::Sender<anyhow::Result<Vec<Uuid>>>,
+    },
+    TrackPage {
+        query: TrackQuery,
+        after: Option<TrackCursor>,
+        limit: usize,
+        reply: flume::Sender<anyhow::Result<TrackPage>>,
+    },
+}
+
+impl PersistenceState {
+    pub(super) async fn handle_browse_msg(&self, msg: BrowseMsg) -> anyhow::Result<()> {
+        match msg {
+            BrowseMsg::Albums { reply } => {
+                reply
+                    .try_send(self.albums().await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Albums"))?;
+            }
+            BrowseMsg::Album { album_id, reply } => {
+                reply
+                    .try_send(self.album(album_id).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Album"))?;
+            }
+            BrowseMsg::AlbumTracks { album_id, reply } => {
+                reply
+                    .try_send(self.album_tracks(album_id).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::AlbumTracks"))?;
+            }
+            BrowseMsg::Artists { reply } => {
+                reply
+                    .try_send(self.artists().await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Artists"))?;
+            }
+            BrowseMsg::ArtistCredits { artist_id, reply } => {
+                reply
+                    .try_send(self.artist_credits(artist_id).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::ArtistCredits"))?;
+            }
+            BrowseMsg::Artwork { owner, size, reply } => {
+                reply
+                    .try_send(self.artwork(owner, size).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Artwork"))?;
+            }
+            BrowseMsg::Genres { reply } => {
+                reply
+                    .try_send(self.genres().await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Genres"))?;
+            }
+            BrowseMsg::GenreTracks { genre_id, reply } => {
+                reply
+                    .try_send(self.genre_tracks(genre_id).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::GenreTracks"))?;
+            }
+            BrowseMsg::Search {
+                query,
+                limit,
+                reply,
+            } => {
+                reply
+                    .try_send(self.search(&query, limit).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Search"))?;
+            }
+            BrowseMsg::Query { query, reply } => {
+                reply
+                    .try_send(self.query_tracks(&query).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::Query"))?;
+            }
+            BrowseMsg::TrackPage {
+                query,
+                after,
+                limit,
+                reply,
+            } => {
+                reply
+                    .try_send(self.track_page(&query, after.as_ref(), limit).await)
+                    .map_err(|_| anyhow::anyhow!("BrowseMsg::TrackPage"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use lofty::tag::ItemKey;
+use lofty::tag::Tag;
+use uuid::Uuid;
+
+use super::PersistenceState;
+use crate::db::types::ArtistRole;
+
+/// Separate the values of a multi-value tag that was stored as a single string.
+const VALUE_SEPARATORS: &[char] = &['\0', ';'];
+
+/// `/` as well, since `Rock/Pop` is far more common in genres than in artist names.
+const GENRE_SEPARATORS: &[char] = &['\0', ';', '/'];
+
+/// Separate the artists that are featured on a track.
+const FEATURE_SEPARATORS: &[char] = &['\0', ';', ',', '&'];
+
+/// Introduce the featured artists in `A feat. B` and `Title (feat. B)`, matched ASCII
+/// case-insensitively.
+const FEATURE_MARKERS: &[&str] = &[
+    "(feat. ",
+    "[feat. ",
+    "(ft. ",
+    "[ft. ",
+    "(featuring ",
+    "[featuring ",
+    " feat. ",
+    " ft. ",
+    " featuring ",
+];
+
+/// An artist as credited on a track.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Credit {
+    pub name: String,
+    pub role: ArtistRole,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artist {
+    pub id: Uuid,
+    pub name: String,
+    /// Tracks in the libraries crediting the artist, in any role.
+    pub tracks: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArtistCredit {
+    pub track_id: Uuid,
+    pub role: ArtistRole,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Genre {
+    pub id: i64,
+    pub name: String,
+    pub tracks: u32,
+}
+
+/// The artists credited by the tags of a track.
+///
+/// `ARTISTS` is preferred over splitting `ARTIST`, but either way the featured
+/// artists are taken from `ARTIST` and the title, and the remixers from `MIXARTIST`
+/// and titles like `Title (B Remix)`.
+pub fn read_credits(tag: &Tag, title: Option<&str>) -> Vec<Credit> {
+    let mut credits = vec![];
+    let mut push = |name: &str, role| {
+        let name = name.trim();
+        if !name.is_empty()
+            && !credits
+                .iter()
+                .any(|c: &Credit| c.role == role && c.name.eq_ignore_ascii_case(name))
+        {
+            credits.push(Credit {
+                name: name.to_string(),
+                role,
+            });
+        }
+    };
+
+    let mut creators = vec![];
+    let mut featured = vec![];
+    for value in tag.get_strings(&ItemKey::TrackArtist) {
+        for artist in value.split(VALUE_SEPARATORS) {
+            let (main, features) = split_featuring(artist);
+            creators.push(main);
+            featured.extend(features);
+        }
+    }
+    if let Some(title) = title {
+        featured.extend(split_featuring(title).1);
+    }
+
+    let artists = tag
+        .get_strings(&ItemKey::TrackArtists)
+        .flat_map(|value| value.split(VALUE_SEPARATORS))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !artists.is_empty() {
+        // `ARTISTS` lists the featured artists too.
+        creators = artists
+            .into_iter()
+            .filter(|artist| {
+                !featured
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case(artist.trim()))
+            })
+            .collect();
+    }
+
+    for artist in &creators {
+        push(artist, ArtistRole::Creator);
+    }
+    for artist in &featured {
+        push(artist, ArtistRole::Feature);
+    }
+
+    for value in tag.get_strings(&ItemKey::Remixer) {
+        for remixer in value.split(VALUE_SEPARATORS) {
+            push(remixer, ArtistRole::Remixer);
+        }
+    }
+    if let Some(title) = title {
+        for remixer in title_remixers(title) {
+            push(remixer, ArtistRole::Remixer);
+        }
+    }
+
+    for value in tag.get_strings(&ItemKey::Composer) {
+        for composer in value.split(VALUE_SEPARATORS) {
+            push(composer, ArtistRole::Composer);
+        }
+    }
+
+    credits
+}
+
+/// Every `GENRE` of the tag, split.
+pub fn read_genres(tag: &Tag) -> Vec<String> {
+    let mut genres = Vec::<String>::new();
+    for genre in tag
+        .get_strings(&ItemKey::Genre)
+        .flat_map(|value| value.split(GENRE_SEPARATORS))
+        .map(str::trim)
+        .filter(|genre| !genre.is_empty())
+    {
+        if !genres.iter().any(|g| g.eq_ignore_ascii_case(genre)) {
+            genres.push(genre.to_string());
+        }
+    }
+
+    genres
+}
+
+/// Splits `A feat. B & C` and `A (feat. B, C)` into `A` and `[B, C]`.
+fn split_featuring(value: &str) -> (String, Vec<String>) {
+    // ASCII lowercasing keeps the byte offsets of `value`.
+    let lower = value.to_ascii_lowercase();
+    let Some((start, marker)) = FEATURE_MARKERS
+        .iter()
+        .filter_map(|marker| lower.find(marker).map(|start| (start, *marker)))
+        .min_by_key(|(start, _)| *start)
+    else {
+        return (value.trim().to_string(), vec![]);
+    };
+
+    let rest = &value[start + marker.len()..];
+    let (featured, after) = match marker.chars().next() {
+        Some(open @ ('(' | '[')) => {
+            let close = if open == '(' { ')' } else { ']' };
+            match rest.find(close) {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => (rest, ""),
+            }
+        }
+        _ => (rest, ""),
+    };
+
+    let main = format!("{} {}", value[..start].trim(), after.trim());
+    let featured = featured
+        .split(FEATURE_SEPARATORS)
+        .flat_map(|artist| artist.split(" and "))
+        .map(str::trim)
+        .filter(|artist| !artist.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    (main.trim().to_string(), featured)
+}
+
+/// `B` of every `(B Remix)` or `[B Remix]` in a title.
+fn title_remixers(title: &str) -> Vec<&str> {
+    const SUFFIX: &str = " remix";
+
+    title
+        .split(['(', '['])
+        .skip(1)
+        .filter_map(|group| group.split([')', ']']).next())
+        .filter_map(|group| {
+            let split = group.len().checked_sub(SUFFIX.len())?;
+            let (remixer, suffix) = (group.get(..split)?, group.get(split..)?);
+            suffix.eq_ignore_ascii_case(SUFFIX).then_some(remixer)
+        })
+        .map(str::trim)
+        .filter(|remixer| !remixer.is_empty())
+        .collect()
+}
+
+/// Replaces the credits and genres of the track of the `filenodes` row `filenode_id`.
+pub async fn write_credits(
+    connection: &mut sqlx::SqliteConnection,
+    filenode_id: Uuid,
+    credits: &[Credit],
+    genres: &[String],
+) -> anyhow::Result<()> {
+    let Some(track_id) = sqlx::query_scalar!(
+        r#"SELECT id AS "id: Uuid" FROM tracks WHERE filenode_id = ?"#,
+        filenode_id
+    )
+    .fetch_optional(&mut *connection)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    sqlx::query!("DELETE FROM artist_tracks WHERE track_id = ?", track_id)
+        .execute(&mut *connection)
+        .await?;
+    sqlx::query!("DELETE FROM track_genres WHERE track_id = ?", track_id)
+        .execute(&mut *connection)
+        .await?;
+
+    for credit in credits {
+        sqlx::query!(
+            "INSERT INTO artists (id, name) VALUES (?, ?) ON CONFLICT (name) DO NOTHING",
+            Uuid::new_v4(),
+            credit.name,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO artist_tracks (artist_id, track_id, role)
+            SELECT id, ?, ?
+            FROM artists
+            WHERE name = ?
+            "#,
+            track_id,
+            credit.role,
+            credit.name,
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for genre in genres {
+        sqlx::query!(
+            "INSERT INTO genres (name) VALUES (?) ON CONFLICT (name) DO NOTHING",
+            genre,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO track_genres (track_id, genre_id)
+            SELECT ?, id
+            FROM genres
+            WHERE name = ?
+            "#,
+            track_id,
+            genre,
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the artists and genres no track refers to anymore.
+pub async fn delete_unused_credits(connection: &mut sqlx::SqliteConnection) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM artists WHERE id NOT IN (SELECT artist_id FROM artist_tracks)")
+        .execute(&mut *connection)
+        .await?;
+    sqlx::query!("DELETE FROM genres WHERE id NOT IN (SELECT genre_id FROM track_genres)")
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(())
+}
+
+impl PersistenceState {
+    /// Artists credited on a track in the libraries, by name.
+    pub async fn artists(&self) -> anyhow::Result<Vec<Artist>> {
+        let artists = sqlx::query_as!(
+            Artist,
+            r#"
+            SELECT
+                a.id AS "id: Uuid",
+                a.name,
+                COUNT(DISTINCT at.track_id) AS "tracks: u32"
+            FROM artists a
+            INNER JOIN artist_tracks at
+                ON at.artist_id = a.id
+            INNER JOIN visible_tracks t
+                ON t.id = at.track_id
+            GROUP BY a.id
+            ORDER BY a.name
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(artists)
+    }
+
+    /// The tracks crediting `artist_id`, by role and then in album order. A track is
+    /// listed once for every role the artist has on it.
+    pub async fn artist_credits(&self, artist_id: Uuid) -> anyhow::Result<Vec<ArtistCredit>> {
+        let credits = sqlx::query_as!(
+            ArtistCredit,
+            r#"
+            SELECT
+                at.track_id AS "track_id: Uuid",
+                at.role AS "role: ArtistRole"
+            FROM artist_tracks at
+            INNER JOIN visible_tracks t
+                ON t.id = at.track_id
+            WHERE at.artist_id = ?
+            ORDER BY
+                at.role,
+                t.album,
+                coalesce(t.disc_number, 1),
+                t.track_number,
+                t.title
+            "#,
+            artist_id,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(credits)
+    }
+
+    /// Genres of the tracks in the libraries, by name.
+    pub async fn genres(&self) -> anyhow::Result<Vec<Genre>> {
+        let genres = sqlx::query_as!(
+            Genre,
+            r#"
+            SELECT
+                g.id AS "id!",
+                g.name,
+                COUNT(tg.track_id) AS "tracks: u32"
+            FROM genres g
+            INNER JOIN track_genres tg
+                ON tg.genre_id = g.id
+            INNER JOIN visible_tracks t
+                ON t.id = tg.track_id
+            GROUP BY g.id
+            ORDER BY g.name
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(genres)
+    }
+
+    pub async fn genre_tracks(&self, genre_id: i64) -> anyhow::Result<Vec<Uuid>> {
+        let ids = sqlx::query_scalar!(
+            r#"
+            SELECT t.id AS "id: Uuid"
+            FROM track_genres tg
+            INNER JOIN visible_tracks t
+                ON t.id = tg.track_id
+            WHERE tg.genre_id = ?
+            ORDER BY coalesce(t.artist, ''), t.title
+            "#,
+            genre_id,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+}
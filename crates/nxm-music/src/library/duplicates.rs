@@ -0,0 +1,228 @@
+use collections::FxIndexMap;
+use collections::FxIndexSet;
+use uuid::Uuid;
+
+use super::PersistenceState;
+use crate::db::types::Blake3Hash;
+
+/// How the tracks of a [`DuplicateGroup`] are alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicateKind {
+    /// The same audio and the same tags.
+    Exact,
+    /// The same audio, tagged differently.
+    SameAudio,
+    /// The same artist and title, with different audio. Usually another format or
+    /// bitrate of the recording, but there's no acoustic fingerprint to confirm it.
+    SameRecording,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub tracks: Vec<Uuid>,
+    /// The copy picked with [`PersistenceState::prefer_duplicate`], the others are
+    /// hidden from the library. Never set for [`DuplicateKind::SameRecording`], whose
+    /// tracks may be different versions.
+    pub preferred: Option<Uuid>,
+}
+
+struct Member {
+    id: Uuid,
+    duplicate_of: Option<Uuid>,
+}
+
+impl DuplicateGroup {
+    fn new(kind: DuplicateKind, members: Vec<Member>) -> Self {
+        let tracks = members.iter().map(|m| m.id).collect::<Vec<_>>();
+        let preferred = members
+            .iter()
+            .filter_map(|m| m.duplicate_of)
+            .find(|id| tracks.contains(id));
+
+        Self {
+            kind,
+            tracks,
+            preferred,
+        }
+    }
+}
+
+impl PersistenceState {
+    /// Tracks that are in a library more than once, across all libraries. A track
+    /// can be in a [`DuplicateKind::SameRecording`] group as well as in one of the
+    /// others.
+    pub async fn duplicates(&self) -> anyhow::Result<Vec<DuplicateGroup>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT
+                t.id AS "id!: Uuid",
+                t.duplicate_of AS "duplicate_of: Uuid",
+                f.audio_hash AS "audio_hash!: Blake3Hash",
+                f.meta_hash AS "meta_hash: Blake3Hash"
+            FROM tracks t
+            INNER JOIN filenodes f
+                ON f.id = t.filenode_id
+            WHERE f.audio_hash IN (
+                SELECT audio_hash
+                FROM filenodes
+                WHERE audio_hash IS NOT NULL
+                GROUP BY audio_hash
+                HAVING COUNT(*) > 1
+            )
+            ORDER BY f.audio_hash, t.id
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut by_audio = FxIndexMap::<Blake3Hash, Vec<_>>::default();
+        for rec in recs {
+            by_audio.entry(rec.audio_hash).or_default().push((
+                rec.meta_hash,
+                Member {
+                    id: rec.id,
+                    duplicate_of: rec.duplicate_of,
+                },
+            ));
+        }
+
+        let mut groups = by_audio
+            .into_values()
+            .map(|members| {
+                let kind = if members.iter().all(|(meta, _)| *meta == members[0].0) {
+                    DuplicateKind::Exact
+                } else {
+                    DuplicateKind::SameAudio
+                };
+
+                DuplicateGroup::new(kind, members.into_iter().map(|(_, m)| m).collect())
+            })
+            .collect::<Vec<_>>();
+
+        let recs = sqlx::query!(
+            r#"
+            WITH recordings AS (
+                SELECT
+                    t.id,
+                    f.audio_hash,
+                    lower(trim(coalesce(t.artist, ''))) AS artist,
+                    lower(trim(t.title)) AS title
+                FROM tracks t
+                INNER JOIN filenodes f
+                    ON f.id = t.filenode_id
+                WHERE trim(coalesce(t.title, '')) != ''
+            ),
+            recording_groups AS (
+                SELECT artist, title
+                FROM recordings
+                GROUP BY artist, title
+                HAVING COUNT(DISTINCT audio_hash) > 1
+            )
+            SELECT
+                r.id AS "id!: Uuid",
+                r.artist AS "artist!: String",
+                r.title AS "title!: String"
+            FROM recordings r
+            INNER JOIN recording_groups g
+                ON g.artist = r.artist AND g.title = r.title
+            ORDER BY r.artist, r.title, r.id
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut by_recording = FxIndexMap::<(String, String), Vec<_>>::default();
+        for rec in recs {
+            by_recording
+                .entry((rec.artist, rec.title))
+                .or_default()
+                .push(Member {
+                    id: rec.id,
+                    duplicate_of: None,
+                });
+        }
+
+        groups.extend(
+            by_recording
+                .into_values()
+                .map(|members| DuplicateGroup::new(DuplicateKind::SameRecording, members)),
+        );
+
+        Ok(groups)
+    }
+
+    /// Keeps `track_id` and hides the other copies of its audio, the tracks of its
+    /// [`DuplicateKind::Exact`] or [`DuplicateKind::SameAudio`] group. Hidden tracks
+    /// come back once the preferred one is removed from the library.
+    pub async fn prefer_duplicate(&self, track_id: Uuid) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let hidden = sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET duplicate_of = ?1
+            WHERE id != ?1
+                AND filenode_id IN (
+                    SELECT f.id
+                    FROM filenodes f
+                    WHERE f.audio_hash = (
+                        SELECT p.audio_hash
+                        FROM tracks t
+                        INNER JOIN filenodes p
+                            ON p.id = t.filenode_id
+                        WHERE t.id = ?1
+                    )
+                )
+            "#,
+            track_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if hidden == 0 {
+            anyhow::bail!("Track {} has no duplicates", track_id);
+        }
+
+        sqlx::query!(
+            "UPDATE tracks SET duplicate_of = NULL WHERE id = ?",
+            track_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Shows the tracks hidden in favour of `track_id` again.
+    pub async fn unhide_duplicates(&self, track_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE tracks SET duplicate_of = NULL WHERE duplicate_of = ?",
+            track_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Tracks hidden in favour of a copy that is still in a library.
+    pub(super) async fn hidden_duplicates(&self) -> anyhow::Result<FxIndexSet<Uuid>> {
+        let ids = sqlx::query_scalar!(
+            r#"
+            SELECT t.id AS "id: Uuid"
+            FROM tracks t
+            INNER JOIN tracks p
+                ON p.id = t.duplicate_of
+            WHERE p.filenode_id IS NOT NULL
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+}
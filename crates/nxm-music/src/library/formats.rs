@@ -0,0 +1,169 @@
+use std::io::BufReader;
+use std::io::Read as _;
+use std::path::Path;
+use std::time::Duration;
+
+use lofty::file::FileType;
+use lofty::probe::Probe;
+
+/// Extensions that are scanned when a library doesn't list its own.
+pub const DEFAULT_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "ape", "flac", "m4a", "m4b", "mka", "mp3", "mpc", "oga", "ogg", "opus",
+    "spx", "wav", "wv",
+];
+
+/// Never sniffed, so that the covers, playlists and rip logs next to an album aren't
+/// opened on every scan.
+const NON_AUDIO_EXTENSIONS: &[&str] = &[
+    "bmp", "cue", "db", "gif", "ini", "jpeg", "jpg", "log", "lrc", "m3u", "m3u8", "md", "md5",
+    "nfo", "pdf", "pls", "png", "sfv", "txt", "webp",
+];
+
+/// Matroska isn't known to lofty, symphonia reads it fine.
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+/// Which files of a library are scanned, by extension.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionFilter {
+    /// Replaces [`DEFAULT_EXTENSIONS`] if set.
+    pub include: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    Audio,
+    /// The extension says nothing, the content has to be probed.
+    Sniff,
+    Skip,
+}
+
+impl ExtensionFilter {
+    /// From the comma separated `libraries.include_extensions`/`exclude_extensions`.
+    pub fn from_db(include: Option<&str>, exclude: Option<&str>) -> Self {
+        Self {
+            include: include.map(split_extensions),
+            exclude: exclude.map(split_extensions).unwrap_or_default(),
+        }
+    }
+
+    pub fn include_to_db(&self) -> Option<String> {
+        self.include.as_ref().map(|include| include.join(","))
+    }
+
+    pub fn exclude_to_db(&self) -> Option<String> {
+        (!self.exclude.is_empty()).then(|| self.exclude.join(","))
+    }
+
+    pub fn detect(&self, path: &Path) -> Detection {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if self.exclude.contains(&ext) {
+            return Detection::Skip;
+        }
+
+        let included = match &self.include {
+            Some(include) => include.contains(&ext),
+            None => DEFAULT_EXTENSIONS.contains(&ext.as_str()),
+        };
+        if included {
+            return Detection::Audio;
+        }
+
+        // A known extension that just isn't wanted in this library.
+        if DEFAULT_EXTENSIONS.contains(&ext.as_str())
+            || NON_AUDIO_EXTENSIONS.contains(&ext.as_str())
+        {
+            return Detection::Skip;
+        }
+
+        Detection::Sniff
+    }
+}
+
+fn split_extensions(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+/// Whether the content of the file at `path` looks like audio, regardless of its
+/// extension.
+pub fn sniff(path: &Path) -> anyhow::Result<bool> {
+    if is_matroska(path)? {
+        return Ok(true);
+    }
+
+    // Not `Probe::open`, which would go by the extension.
+    let probe = Probe::new(BufReader::new(std::fs::File::open(path)?)).guess_file_type()?;
+
+    Ok(probe.file_type().is_some())
+}
+
+fn is_matroska(path: &Path) -> anyhow::Result<bool> {
+    let mut magic = [0; 4];
+    match std::fs::File::open(path)?.read_exact(&mut magic) {
+        Ok(_) => Ok(magic == EBML_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The container, codec and length of an audio file, as stored on its track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub container: String,
+    pub codec: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+impl AudioFormat {
+    /// `codec` is what symphonia found in the file, if it has a decoder for it.
+    /// `duration` is left out if it's zero, which is what lofty reports for files it
+    /// couldn't work out the length of.
+    pub fn detect(
+        path: &Path,
+        file_type: Option<&FileType>,
+        codec: Option<&str>,
+        duration: Option<Duration>,
+    ) -> Self {
+        let (container, lofty_codec) = match file_type {
+            Some(FileType::Aac) => ("aac", Some("aac")),
+            Some(FileType::Aiff) => ("aiff", None),
+            Some(FileType::Ape) => ("ape", Some("ape")),
+            Some(FileType::Flac) => ("flac", Some("flac")),
+            Some(FileType::Mpeg) => ("mpeg", None),
+            Some(FileType::Mp4) => ("mp4", None),
+            Some(FileType::Mpc) => ("mpc", Some("mpc")),
+            Some(FileType::Opus) => ("ogg", Some("opus")),
+            Some(FileType::Vorbis) => ("ogg", Some("vorbis")),
+            Some(FileType::Speex) => ("ogg", Some("speex")),
+            Some(FileType::Wav) => ("wav", None),
+            Some(FileType::WavPack) => ("wavpack", Some("wavpack")),
+            _ => ("", None),
+        };
+
+        let container = if !container.is_empty() {
+            container.to_string()
+        } else if is_matroska(path).unwrap_or(false) {
+            "matroska".to_string()
+        } else {
+            // Only known to symphonia, which doesn't name its formats.
+            path.extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default()
+        };
+
+        Self {
+            container,
+            codec: codec.or(lofty_codec).map(str::to_string),
+            duration_ms: duration
+                .filter(|duration| !duration.is_zero())
+                .map(|duration| duration.as_millis() as u64),
+        }
+    }
+}
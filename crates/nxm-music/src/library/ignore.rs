@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use globset::GlobBuilder;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+
+/// A directory containing one of these is skipped along with everything below it.
+pub const IGNORE_MARKERS: &[&str] = &[".nomedia", ".ignore"];
+
+/// Glob patterns for paths of a library that aren't scanned, relative to its root.
+///
+/// `*` doesn't match `/`, so `Samples` only skips the directory at the root of the
+/// library, while `**/*.als` skips project files at any depth.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl Default for IgnoreRules {
+    fn default() -> Self {
+        Self {
+            patterns: vec![],
+            set: GlobSet::empty(),
+        }
+    }
+}
+
+impl PartialEq for IgnoreRules {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Eq for IgnoreRules {}
+
+impl IgnoreRules {
+    pub fn new(patterns: Vec<String>) -> anyhow::Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+        }
+
+        Ok(Self {
+            set: builder.build()?,
+            patterns,
+        })
+    }
+
+    /// From the newline separated `libraries.ignore_patterns`.
+    pub fn from_db(patterns: Option<&str>) -> Self {
+        let patterns = patterns
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        // Patterns are checked before they're stored, this is only for a hand edited
+        // database.
+        Self::new(patterns).unwrap_or_else(|e| {
+            log::error!("Invalid ignore patterns: {:#}", e);
+            Self::default()
+        })
+    }
+
+    pub fn to_db(&self) -> Option<String> {
+        (!self.patterns.is_empty()).then(|| self.patterns.join("\n"))
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Whether `path`, below `library_path`, is left out of the library.
+    pub fn is_ignored(&self, library_path: &Path, path: &Path, is_dir: bool) -> bool {
+        if let Ok(relative) = path.strip_prefix(library_path)
+            && self.set.is_match(relative)
+        {
+            return true;
+        }
+
+        is_dir
+            && IGNORE_MARKERS
+                .iter()
+                .any(|marker| path.join(marker).exists())
+    }
+}
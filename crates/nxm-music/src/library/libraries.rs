@@ -0,0 +1,585 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use crossbeam_channel::Sender;
+use uuid::Uuid;
+
+use super::ExtensionFilter;
+use super::IgnoreRules;
+use super::IoMainMsg;
+use super::PersistenceState;
+use super::ScanControl;
+use super::ScanTarget;
+use super::delete_unused_artwork;
+use super::delete_unused_credits;
+use super::get_identity;
+use super::sync_albums;
+use super::watcher::LibraryWatcher;
+
+/// A library root as shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    pub id: Uuid,
+    pub path: PathBuf,
+    pub label: Option<String>,
+    pub extensions: ExtensionFilter,
+    pub ignore_patterns: Vec<String>,
+    pub online: bool,
+}
+
+/// Whether the root of a library is mounted.
+///
+/// An empty root that has indexed files is taken to be offline as well, since that's
+/// what an unmounted mount point looks like. Scanning it would delete every track.
+pub fn root_is_online(path: &Path, has_children: bool) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(mut entries) => !has_children || entries.next().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Why a directory can't be a library root.
+#[derive(Debug, thiserror::Error)]
+pub enum LibraryPathError {
+    #[error("{0:?} isn't an absolute path")]
+    NotAbsolute(PathBuf),
+    #[error("{0:?} isn't a directory")]
+    NotADirectory(PathBuf),
+    #[error("{0:?} isn't valid UTF-8")]
+    NotUtf8(PathBuf),
+    /// One of the two contains the other, so their files would be indexed twice.
+    #[error("{path:?} overlaps library {library_id} at {library_path:?}")]
+    Overlaps {
+        path: PathBuf,
+        library_id: Uuid,
+        library_path: PathBuf,
+    },
+}
+
+pub enum LibraryMsg {
+    List {
+        reply: flume::Sender<anyhow::Result<Vec<Library>>>,
+    },
+    Add {
+        path: PathBuf,
+        label: Option<String>,
+        reply: flume::Sender<anyhow::Result<Uuid>>,
+    },
+    Remove {
+        id: Uuid,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+    Relabel {
+        id: Uuid,
+        label: Option<String>,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+    /// Points a library at the same files in another place, keeping its tracks.
+    Relocate {
+        id: Uuid,
+        path: PathBuf,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+    SetExtensionFilter {
+        id: Uuid,
+        extensions: ExtensionFilter,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+    SetIgnorePatterns {
+        id: Uuid,
+        patterns: Vec<String>,
+        reply: flume::Sender<anyhow::Result<()>>,
+    },
+}
+
+impl PersistenceState {
+    pub async fn libraries(&self) -> anyhow::Result<Vec<Library>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT
+                id as "id: Uuid",
+                path,
+                label,
+                include_extensions,
+                exclude_extensions,
+                ignore_patterns,
+                online as "online: bool"
+            FROM libraries
+            ORDER BY path
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|rec| Library {
+                id: rec.id,
+                path: rec.path.into(),
+                label: rec.label,
+                extensions: ExtensionFilter::from_db(
+                    rec.include_extensions.as_deref(),
+                    rec.exclude_extensions.as_deref(),
+                ),
+                ignore_patterns: IgnoreRules::from_db(rec.ignore_patterns.as_deref())
+                    .patterns()
+                    .to_vec(),
+                online: rec.online,
+            })
+            .collect())
+    }
+
+    /// Registers the directory at `path` as a library and scans it. The library is
+    /// forgotten again if the scan fails.
+    pub async fn add_library(&self, path: &Path, label: Option<String>) -> anyhow::Result<Uuid> {
+        let path = self.validate_library_path(path, None).await?;
+        let path = path.to_str().expect("Checked by validate_library_path");
+
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO libraries (id, path, label) VALUES (?, ?, ?)",
+            id,
+            path,
+            label,
+        )
+        .execute(&self.db)
+        .await?;
+
+        // The scan looks the library up by itself, so the row can't be part of its
+        // transaction. Nothing else refers to it while the scan hasn't committed.
+        if let Err(e) = self
+            .scan(ScanTarget::Library(id), &ScanControl::default())
+            .await
+        {
+            sqlx::query!("DELETE FROM libraries WHERE id = ?", id)
+                .execute(&self.db)
+                .await?;
+
+            return Err(e);
+        }
+
+        Ok(id)
+    }
+
+    /// Forgets a library along with its `filenodes`, tracks and scan errors. The files
+    /// themselves are left alone.
+    pub async fn remove_library(&self, id: Uuid) -> anyhow::Result<()> {
+        let library = self.library(id).await?;
+
+        let mut tx = self.db.begin().await?;
+
+        // Detached rather than deleted by the `ON DELETE SET NULL` otherwise.
+        sqlx::query!(
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT node AS id
+                FROM libraries
+                WHERE id = ?
+
+                UNION ALL
+
+                SELECT f.id
+                FROM filenodes f
+                JOIN subtree s ON f.parent_id = s.id
+            )
+            DELETE FROM tracks
+            WHERE filenode_id IN (SELECT id FROM subtree)
+            "#,
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Children go with their parent through `ON DELETE CASCADE`.
+        sqlx::query!(
+            "DELETE FROM filenodes WHERE id = (SELECT node FROM libraries WHERE id = ?)",
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM libraries WHERE id = ?", id)
+            .execute(&mut *tx)
+            .await?;
+
+        delete_scan_errors(&mut tx, &library.path).await?;
+        delete_unused_credits(&mut tx).await?;
+        sync_albums(&mut tx).await?;
+        let unused_artwork = delete_unused_artwork(&mut tx).await?;
+
+        tx.commit().await?;
+
+        for hash in &unused_artwork {
+            self.artwork_cache.remove(hash);
+        }
+
+        Ok(())
+    }
+
+    pub async fn relabel_library(&self, id: Uuid, label: Option<String>) -> anyhow::Result<()> {
+        let res = sqlx::query!("UPDATE libraries SET label = ? WHERE id = ?", label, id)
+            .execute(&self.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            anyhow::bail!("Library {} doesn't exist", id);
+        }
+
+        Ok(())
+    }
+
+    /// Moves a library to `path`, where its files are expected under the same relative
+    /// paths, e.g. because the drive is mounted somewhere else now. Files are matched
+    /// by path, so their tracks are kept even though their inodes changed.
+    pub async fn relocate_library(&self, id: Uuid, path: &Path) -> anyhow::Result<()> {
+        let library = self.library(id).await?;
+        let path = self.validate_library_path(path, Some(id)).await?;
+
+        let meta = path.metadata()?;
+        let identity = get_identity(&meta);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path_str = path.to_str().expect("Checked by validate_library_path");
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!("UPDATE libraries SET path = ? WHERE id = ?", path_str, id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE filenodes
+            SET
+                name = ?,
+                inode = ?,
+                device = ?
+            WHERE id = (SELECT node FROM libraries WHERE id = ?)
+            "#,
+            name,
+            identity.inode as i64,
+            identity.device as i64,
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Found again under the new path by the scan, if they're still unreadable.
+        delete_scan_errors(&mut tx, &library.path).await?;
+
+        tx.commit().await?;
+
+        self.scan(ScanTarget::Library(id), &ScanControl::default())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Changes which files of a library are scanned and rescans it, so that files the
+    /// new filter leaves out are removed.
+    pub async fn set_extension_filter(
+        &self,
+        library_id: Uuid,
+        extensions: &ExtensionFilter,
+    ) -> anyhow::Result<()> {
+        let include = extensions.include_to_db();
+        let exclude = extensions.exclude_to_db();
+
+        let res = sqlx::query!(
+            r#"
+            UPDATE libraries
+            SET
+                include_extensions = ?,
+                exclude_extensions = ?
+            WHERE id = ?
+            "#,
+            include,
+            exclude,
+            library_id,
+        )
+        .execute(&self.db)
+        .await?;
+        if res.rows_affected() == 0 {
+            anyhow::bail!("Library {} doesn't exist", library_id);
+        }
+
+        self.scan(ScanTarget::Library(library_id), &ScanControl::default())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces the ignore patterns of a library and rescans it, so that files they
+    /// match are removed.
+    pub async fn set_ignore_patterns(
+        &self,
+        library_id: Uuid,
+        patterns: Vec<String>,
+    ) -> anyhow::Result<()> {
+        let patterns = IgnoreRules::new(patterns)?.to_db();
+
+        let res = sqlx::query!(
+            "UPDATE libraries SET ignore_patterns = ? WHERE id = ?",
+            patterns,
+            library_id,
+        )
+        .execute(&self.db)
+        .await?;
+        if res.rows_affected() == 0 {
+            anyhow::bail!("Library {} doesn't exist", library_id);
+        }
+
+        self.scan(ScanTarget::Library(library_id), &ScanControl::default())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks libraries whose root went away as offline, and rescans the ones that are
+    /// back. Returns the libraries whose status changed.
+    pub async fn check_library_roots(&self) -> anyhow::Result<Vec<Library>> {
+        let mut changed = vec![];
+
+        for l in self.libraries().await? {
+            let has_children = sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM filenodes f
+                    JOIN libraries l ON f.parent_id = l.node
+                    WHERE l.id = ?
+                ) AS "has_children: bool"
+                "#,
+                l.id,
+            )
+            .fetch_one(&self.db)
+            .await?;
+
+            let online = root_is_online(&l.path, has_children);
+            if online == l.online {
+                continue;
+            }
+
+            if online {
+                log::info!("Library {} at {:#?} is back online", l.id, l.path);
+                // Marks it online as well.
+                self.scan(ScanTarget::Library(l.id), &ScanControl::default())
+                    .await?;
+            } else {
+                log::warn!("Library {} at {:#?} went offline", l.id, l.path);
+                sqlx::query!("UPDATE libraries SET online = FALSE WHERE id = ?", l.id)
+                    .execute(&self.db)
+                    .await?;
+            }
+
+            changed.push(Library { online, ..l });
+        }
+
+        Ok(changed)
+    }
+
+    pub(super) async fn handle_check_library_roots(
+        &mut self,
+        watcher: &mut LibraryWatcher,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        let changed = self.check_library_roots().await?;
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        for l in &changed {
+            // The watch on the old mount is gone along with it.
+            unwatch(watcher, &l.path);
+            if l.online {
+                watch(watcher, &l.path);
+            }
+        }
+
+        self.send_delta(io_main_tx).await
+    }
+
+    async fn library(&self, id: Uuid) -> anyhow::Result<Library> {
+        self.libraries()
+            .await?
+            .into_iter()
+            .find(|l| l.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Library {} doesn't exist", id))
+    }
+
+    /// The canonical form of `path`, if it can be the root of a library other than
+    /// `library_id`.
+    async fn validate_library_path(
+        &self,
+        path: &Path,
+        library_id: Option<Uuid>,
+    ) -> anyhow::Result<PathBuf> {
+        if !path.is_absolute() {
+            return Err(LibraryPathError::NotAbsolute(path.into()).into());
+        }
+        if !path.is_dir() {
+            return Err(LibraryPathError::NotADirectory(path.into()).into());
+        }
+
+        let path = path.canonicalize()?;
+        if path.to_str().is_none() {
+            return Err(LibraryPathError::NotUtf8(path).into());
+        }
+
+        for l in self.libraries().await? {
+            if Some(l.id) == library_id {
+                continue;
+            }
+
+            // The root may be offline, in which case it's taken as it was stored.
+            let other = l.path.canonicalize().unwrap_or_else(|_| l.path.clone());
+            if path.starts_with(&other) || other.starts_with(&path) {
+                return Err(LibraryPathError::Overlaps {
+                    path,
+                    library_id: l.id,
+                    library_path: l.path,
+                }
+                .into());
+            }
+        }
+
+        Ok(path)
+    }
+
+    pub(super) async fn handle_library_msg(
+        &mut self,
+        msg: LibraryMsg,
+        watcher: &mut LibraryWatcher,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        match msg {
+            LibraryMsg::List { reply } => {
+                reply
+                    .try_send(self.libraries().await)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::List"))?;
+            }
+            LibraryMsg::Add { path, label, reply } => {
+                let res = match self.add_library(&path, label).await {
+                    Ok(id) => {
+                        if let Ok(library) = self.library(id).await {
+                            watch(watcher, &library.path);
+                        }
+                        self.send_delta(io_main_tx).await.map(|_| id)
+                    }
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::Add"))?;
+            }
+            LibraryMsg::Remove { id, reply } => {
+                let old = self.library(id).await;
+
+                let res = match self.remove_library(id).await {
+                    Ok(_) => {
+                        if let Ok(old) = &old {
+                            unwatch(watcher, &old.path);
+                        }
+                        self.send_delta(io_main_tx).await
+                    }
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::Remove"))?;
+            }
+            LibraryMsg::Relabel { id, label, reply } => {
+                reply
+                    .try_send(self.relabel_library(id, label).await)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::Relabel"))?;
+            }
+            LibraryMsg::Relocate { id, path, reply } => {
+                let old = self.library(id).await;
+
+                let res = match self.relocate_library(id, &path).await {
+                    Ok(_) => {
+                        if let Ok(old) = &old {
+                            unwatch(watcher, &old.path);
+                        }
+                        if let Ok(new) = self.library(id).await {
+                            watch(watcher, &new.path);
+                        }
+                        self.send_delta(io_main_tx).await
+                    }
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::Relocate"))?;
+            }
+            LibraryMsg::SetExtensionFilter {
+                id,
+                extensions,
+                reply,
+            } => {
+                let res = match self.set_extension_filter(id, &extensions).await {
+                    Ok(_) => self.send_delta(io_main_tx).await,
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::SetExtensionFilter"))?;
+            }
+            LibraryMsg::SetIgnorePatterns {
+                id,
+                patterns,
+                reply,
+            } => {
+                let res = match self.set_ignore_patterns(id, patterns).await {
+                    Ok(_) => self.send_delta(io_main_tx).await,
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("LibraryMsg::SetIgnorePatterns"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The library is usable without live updates, so a root that can't be watched is
+/// only logged.
+fn watch(watcher: &mut LibraryWatcher, path: &Path) {
+    if let Err(e) = watcher.watch(path) {
+        log::error!("Unable to watch {:#?}: {:#?}", path, e);
+    }
+}
+
+fn unwatch(watcher: &mut LibraryWatcher, path: &Path) {
+    if let Err(e) = watcher.unwatch(path) {
+        log::error!("Unable to unwatch {:#?}: {:#?}", path, e);
+    }
+}
+
+/// Deletes the errors of files below `path`.
+async fn delete_scan_errors(
+    connection: &mut sqlx::SqliteConnection,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let path = path.to_string_lossy();
+
+    sqlx::query!(
+        r#"
+        DELETE FROM scan_errors
+        WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'
+        "#,
+        path,
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
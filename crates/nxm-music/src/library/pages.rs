@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use sqlx::QueryBuilder;
+use sqlx::Row as _;
+use sqlx::Sqlite;
+use uuid::Uuid;
+
+use super::OrderColumn;
+use super::PersistenceState;
+use super::SortKey;
+use super::Track;
+use super::TrackQuery;
+use super::push_order;
+
+/// Where a page of tracks ends, to fetch the one after it.
+///
+/// The cursor holds the values the last track was sorted by rather than its position,
+/// so pages don't skip or repeat tracks when the library changes in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackCursor {
+    sort: Vec<SortKey>,
+    values: Vec<CursorValue>,
+    id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CursorValue {
+    Integer(i64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrackPage {
+    pub tracks: Vec<Arc<Track>>,
+    /// `None` on the last page.
+    pub next: Option<TrackCursor>,
+}
+
+/// Pushes a condition matching the tracks ordered after `cursor`.
+fn push_after(
+    builder: &mut QueryBuilder<Sqlite>,
+    order: &[(OrderColumn, bool)],
+    cursor: &TrackCursor,
+) {
+    let columns = order
+        .iter()
+        .zip(&cursor.values)
+        .map(|((column, descending), value)| {
+            (column.expr(), column.is_descending(*descending), value)
+        })
+        .collect::<Vec<_>>();
+
+    // The tracks tied with the cursor on the first `i` columns, and after it on the
+    // next one, or on `t.id` once every column is tied.
+    builder.push("(");
+    for i in 0..=columns.len() {
+        if i > 0 {
+            builder.push(" OR ");
+        }
+
+        builder.push("(");
+        for (expr, _, value) in &columns[..i] {
+            builder.push(format_args!("({expr}) = "));
+            push_value(builder, value);
+            builder.push(" AND ");
+        }
+        match columns.get(i) {
+            Some((expr, descending, value)) => {
+                let comparison = if *descending { "<" } else { ">" };
+                builder.push(format_args!("({expr}) {comparison} "));
+                push_value(builder, value);
+            }
+            None => {
+                builder.push("t.id > ").push_bind(cursor.id);
+            }
+        }
+        builder.push(")");
+    }
+    builder.push(")");
+}
+
+fn push_value(builder: &mut QueryBuilder<Sqlite>, value: &CursorValue) {
+    match value {
+        CursorValue::Integer(value) => builder.push_bind(*value),
+        CursorValue::Text(value) => builder.push_bind(value.as_str()),
+    };
+}
+
+impl PersistenceState {
+    /// Up to `limit` tracks matching `query`, in its order, starting after `after`, a
+    /// cursor returned with a previous page of the same query.
+    pub async fn track_page(
+        &self,
+        query: &TrackQuery,
+        after: Option<&TrackCursor>,
+        limit: usize,
+    ) -> anyhow::Result<TrackPage> {
+        anyhow::ensure!(limit > 0, "A page has to hold at least one track");
+
+        let order = query.order();
+        if let Some(cursor) = after
+            && (cursor.sort != query.sort || cursor.values.len() != order.len())
+        {
+            anyhow::bail!("The cursor is from a differently sorted query");
+        }
+
+        // The page is selected first, then the paths of its files are built from the
+        // `filenodes` tree, up to the roots of their libraries.
+        let mut builder = QueryBuilder::new("WITH RECURSIVE page AS (SELECT t.id, t.filenode_id");
+        for (i, (column, _)) in order.iter().enumerate() {
+            builder.push(format_args!(", {} AS k{i}", column.expr()));
+        }
+        builder.push(", row_number() OVER (ORDER BY ");
+        push_order(&mut builder, &order);
+        builder.push(") AS position");
+
+        query.push_from(&mut builder);
+        if let Some(cursor) = after {
+            builder.push(" AND ");
+            push_after(&mut builder, &order, cursor);
+        }
+
+        builder.push(" ORDER BY ");
+        push_order(&mut builder, &order);
+        // One more than asked for, to know whether there's a next page.
+        builder
+            .push(" LIMIT ")
+            .push_bind(i64::try_from(limit)?.saturating_add(1));
+
+        builder.push(
+            r#"
+            ),
+            paths (track_id, node_id, path) AS (
+                SELECT p.id, f.parent_id, f.name
+                FROM page p
+                INNER JOIN filenodes f
+                    ON f.id = p.filenode_id
+
+                UNION ALL
+
+                SELECT s.track_id, f.parent_id, (f.name || '/' || s.path)
+                FROM paths s
+                INNER JOIN filenodes f
+                    ON f.id = s.node_id
+                WHERE f.id NOT IN (
+                    SELECT l.node
+                    FROM libraries l
+                    WHERE l.node IS NOT NULL
+                )
+            )
+            SELECT
+                p.*,
+                t.artist,
+                t.title,
+                t.album,
+                t.album_artist,
+                t.track_number,
+                t.disc_number,
+                t.year,
+                t.genre,
+                t.composer,
+                t.album_id,
+                t.duration_ms,
+                (l.path || '/' || s.path) AS filepath,
+                l.online
+            FROM page p
+            INNER JOIN tracks t
+                ON t.id = p.id
+            INNER JOIN paths s
+                ON s.track_id = p.id
+            INNER JOIN libraries l
+                ON l.node = s.node_id
+            ORDER BY p.position
+            "#,
+        );
+
+        let rows = builder.build().fetch_all(&self.db).await?;
+
+        let mut page = TrackPage::default();
+        for row in rows.iter().take(limit) {
+            page.tracks.push(Arc::new(Track {
+                id: row.try_get("id")?,
+                artist: row
+                    .try_get::<Option<String>, _>("artist")?
+                    .unwrap_or_default(),
+                title: row
+                    .try_get::<Option<String>, _>("title")?
+                    .unwrap_or_default(),
+                album: row.try_get("album")?,
+                album_artist: row.try_get("album_artist")?,
+                track_number: row.try_get("track_number")?,
+                disc_number: row.try_get("disc_number")?,
+                year: row.try_get("year")?,
+                genre: row.try_get("genre")?,
+                composer: row.try_get("composer")?,
+                album_id: row.try_get("album_id")?,
+                duration_ms: row.try_get("duration_ms")?,
+                filepath: row.try_get("filepath")?,
+                available: row.try_get("online")?,
+            }));
+        }
+
+        if rows.len() > limit {
+            let last = &rows[limit - 1];
+            let mut values = vec![];
+            for (i, (column, _)) in order.iter().enumerate() {
+                let key = format!("k{i}");
+                values.push(match column {
+                    OrderColumn::Missing(_) | OrderColumn::Integer(_) => {
+                        CursorValue::Integer(last.try_get(key.as_str())?)
+                    }
+                    OrderColumn::Text(_) => CursorValue::Text(last.try_get(key.as_str())?),
+                });
+            }
+
+            page.next = Some(TrackCursor {
+                sort: query.sort.clone(),
+                values,
+                id: last.try_get("id")?,
+            });
+        }
+
+        Ok(page)
+    }
+}
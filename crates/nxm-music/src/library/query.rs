@@ -0,0 +1,670 @@
+use sqlx::QueryBuilder;
+use sqlx::Sqlite;
+use uuid::Uuid;
+
+use super::PersistenceState;
+
+/// A filter and ordering of tracks, written like
+/// `artist:"Boards of Canada" year:>1998 genre:ambient -live sort:year`.
+///
+/// Terms are matched case-insensitively and all have to match, unless they're joined
+/// by `OR`. A term is negated by a leading `-`, and terms can be grouped with
+/// parentheses. A term without a field matches the title, artist, album, genre or
+/// file name of a track.
+///
+/// Text fields (`artist`, `albumartist`, `album`, `title`, `genre`, `composer` and
+/// `file`) match values containing the term, or equal to it with a leading `=`, like
+/// `genre:=ambient`. `artist` and `genre` match any of the credits of a track as well.
+///
+/// Number fields (`year`, `track`, `disc` and `duration`, in seconds or as `m:ss`)
+/// take a number, a comparison like `>1998` or `<=3:00`, or a range like
+/// `1990..1999`.
+///
+/// `sort:field` or `sort:-field`, for descending order, can be given more than once,
+/// by `artist`, `albumartist`, `album`, `title`, `year`, `track`, `duration`, `genre`
+/// or `added`. Tracks are otherwise in library order: by album artist, year, album,
+/// disc and track.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackQuery {
+    pub filter: Option<QueryExpr>,
+    pub sort: Vec<SortKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryExpr {
+    And(Vec<QueryExpr>),
+    Or(Vec<QueryExpr>),
+    Not(Box<QueryExpr>),
+    Text {
+        /// Every field without one.
+        field: Option<TextField>,
+        value: String,
+        /// Whether the field has to equal `value`, rather than contain it.
+        exact: bool,
+    },
+    Number {
+        field: NumberField,
+        comparison: Comparison,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Genre,
+    Composer,
+    /// The name of the file.
+    File,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    Year,
+    Track,
+    Disc,
+    /// In seconds.
+    Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq(i64),
+    Lt(i64),
+    Le(i64),
+    Gt(i64),
+    Ge(i64),
+    /// Both ends included.
+    Between(i64, i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Year,
+    /// Disc and track number.
+    Track,
+    Duration,
+    Genre,
+    /// When the track was added to the library.
+    Added,
+}
+
+/// Why a [`TrackQuery`] couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueryError {
+    #[error("Unknown field {0:?}")]
+    UnknownField(String),
+    #[error("{value:?} isn't a valid value for {field:?}")]
+    InvalidValue { field: String, value: String },
+    #[error("Missing closing quote")]
+    UnclosedQuote,
+    #[error("Unbalanced parentheses")]
+    UnbalancedParentheses,
+    #[error("Missing term after {0:?}")]
+    MissingTerm(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Term {
+        field: Option<String>,
+        value: String,
+    },
+    Not,
+    Or,
+    Open,
+    Close,
+}
+
+/// Splits `query` into terms, which may be quoted in part, like `artist:"A B"`.
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            _ => {
+                let mut field = None;
+                let mut value = String::new();
+                let mut quoted = false;
+
+                while let Some(&c) = chars.peek() {
+                    if c == '"' {
+                        quoted = !quoted;
+                    } else if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    } else if !quoted && c == ':' && field.is_none() {
+                        field = Some(std::mem::take(&mut value));
+                    } else {
+                        value.push(c);
+                    }
+                    chars.next();
+                }
+                if quoted {
+                    return Err(QueryError::UnclosedQuote);
+                }
+
+                if field.is_none() && value == "OR" {
+                    tokens.push(Token::Or);
+                } else {
+                    tokens.push(Token::Term { field, value });
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    sort: Vec<SortKey>,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<Option<QueryExpr>, QueryError> {
+        let mut exprs = vec![];
+        exprs.extend(self.parse_and()?);
+
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            exprs.push(self.parse_and()?.ok_or(QueryError::MissingTerm("OR"))?);
+        }
+
+        Ok(match exprs.len() {
+            0 => None,
+            1 => exprs.pop(),
+            _ => Some(QueryExpr::Or(exprs)),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Option<QueryExpr>, QueryError> {
+        let mut exprs = vec![];
+        while let Some(token) = self.tokens.peek() {
+            if matches!(token, Token::Or | Token::Close) {
+                break;
+            }
+            exprs.extend(self.parse_unary()?);
+        }
+
+        Ok(match exprs.len() {
+            0 => None,
+            1 => exprs.pop(),
+            _ => Some(QueryExpr::And(exprs)),
+        })
+    }
+
+    /// `None` for a `sort:` term, which is taken out of the filter.
+    fn parse_unary(&mut self) -> Result<Option<QueryExpr>, QueryError> {
+        match self.tokens.next() {
+            Some(Token::Not) => {
+                let expr = self.parse_unary()?.ok_or(QueryError::MissingTerm("-"))?;
+                Ok(Some(QueryExpr::Not(Box::new(expr))))
+            }
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                if self.tokens.next() != Some(Token::Close) {
+                    return Err(QueryError::UnbalancedParentheses);
+                }
+                Ok(expr)
+            }
+            Some(Token::Term { field, value }) => self.parse_term(field, value),
+            Some(Token::Close) => Err(QueryError::UnbalancedParentheses),
+            Some(Token::Or) | None => Err(QueryError::MissingTerm("-")),
+        }
+    }
+
+    fn parse_term(
+        &mut self,
+        field: Option<String>,
+        value: String,
+    ) -> Result<Option<QueryExpr>, QueryError> {
+        let Some(field) = field else {
+            return Ok(Some(QueryExpr::Text {
+                field: None,
+                value,
+                exact: false,
+            }));
+        };
+        let invalid = || QueryError::InvalidValue {
+            field: field.clone(),
+            value: value.clone(),
+        };
+
+        let text_field = match field.to_lowercase().as_str() {
+            "artist" => Some(TextField::Artist),
+            "albumartist" => Some(TextField::AlbumArtist),
+            "album" => Some(TextField::Album),
+            "title" => Some(TextField::Title),
+            "genre" => Some(TextField::Genre),
+            "composer" => Some(TextField::Composer),
+            "file" => Some(TextField::File),
+            _ => None,
+        };
+        if let Some(text_field) = text_field {
+            let (text, exact) = match value.strip_prefix('=') {
+                Some(text) => (text, true),
+                None => (value.as_str(), false),
+            };
+            if text.is_empty() {
+                return Err(invalid());
+            }
+
+            return Ok(Some(QueryExpr::Text {
+                field: Some(text_field),
+                value: text.to_string(),
+                exact,
+            }));
+        }
+
+        let number_field = match field.to_lowercase().as_str() {
+            "year" => NumberField::Year,
+            "track" => NumberField::Track,
+            "disc" => NumberField::Disc,
+            "duration" => NumberField::Duration,
+            "sort" => {
+                let (name, descending) = match value.strip_prefix('-') {
+                    Some(name) => (name, true),
+                    None => (value.as_str(), false),
+                };
+                let field = match name.to_lowercase().as_str() {
+                    "artist" => SortField::Artist,
+                    "albumartist" => SortField::AlbumArtist,
+                    "album" => SortField::Album,
+                    "title" => SortField::Title,
+                    "year" => SortField::Year,
+                    "track" => SortField::Track,
+                    "duration" => SortField::Duration,
+                    "genre" => SortField::Genre,
+                    "added" => SortField::Added,
+                    _ => return Err(invalid()),
+                };
+
+                self.sort.push(SortKey { field, descending });
+                return Ok(None);
+            }
+            _ => return Err(QueryError::UnknownField(field)),
+        };
+
+        let comparison = parse_comparison(number_field, &value).ok_or_else(invalid)?;
+        Ok(Some(QueryExpr::Number {
+            field: number_field,
+            comparison,
+        }))
+    }
+}
+
+fn parse_comparison(field: NumberField, value: &str) -> Option<Comparison> {
+    let number = |value: &str| match field {
+        NumberField::Duration => parse_duration(value),
+        _ => value.parse::<i64>().ok(),
+    };
+
+    if let Some((from, to)) = value.split_once("..") {
+        return Some(Comparison::Between(number(from)?, number(to)?));
+    }
+
+    let comparison = if let Some(value) = value.strip_prefix(">=") {
+        Comparison::Ge(number(value)?)
+    } else if let Some(value) = value.strip_prefix("<=") {
+        Comparison::Le(number(value)?)
+    } else if let Some(value) = value.strip_prefix('>') {
+        Comparison::Gt(number(value)?)
+    } else if let Some(value) = value.strip_prefix('<') {
+        Comparison::Lt(number(value)?)
+    } else {
+        Comparison::Eq(number(value.strip_prefix('=').unwrap_or(value))?)
+    };
+
+    Some(comparison)
+}
+
+/// Seconds, like `90`, or minutes and seconds, like `1:30`.
+fn parse_duration(value: &str) -> Option<i64> {
+    match value.split_once(':') {
+        Some((minutes, seconds)) if seconds.len() == 2 => {
+            let seconds = seconds.parse::<i64>().ok().filter(|s| *s < 60)?;
+            Some(minutes.parse::<i64>().ok()? * 60 + seconds)
+        }
+        Some(_) => None,
+        None => value.parse().ok(),
+    }
+}
+
+impl TrackQuery {
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?.into_iter().peekable(),
+            sort: vec![],
+        };
+
+        let filter = parser.parse_or()?;
+        if parser.tokens.next().is_some() {
+            return Err(QueryError::UnbalancedParentheses);
+        }
+
+        Ok(Self {
+            filter,
+            sort: parser.sort,
+        })
+    }
+
+    /// Selects the ids of the tracks that match, in order.
+    fn build(&self) -> QueryBuilder<Sqlite> {
+        let mut builder = QueryBuilder::new("SELECT t.id");
+        self.push_from(&mut builder);
+
+        builder.push(" ORDER BY ");
+        push_order(&mut builder, &self.order());
+
+        builder
+    }
+
+    /// Pushes the tracks that match, from `visible_tracks t` joined with their
+    /// `filenodes f`. Ends in a `WHERE` clause that callers may extend with `AND`.
+    pub(super) fn push_from(&self, builder: &mut QueryBuilder<Sqlite>) {
+        builder.push(
+            r#"
+            FROM visible_tracks t
+            INNER JOIN filenodes f
+                ON f.id = t.filenode_id
+            WHERE
+            "#,
+        );
+
+        match &self.filter {
+            Some(filter) => push_expr(builder, filter),
+            None => {
+                builder.push("TRUE");
+            }
+        }
+    }
+
+    /// The columns tracks are ordered by, before `t.id`: the sort keys, then library
+    /// order.
+    pub(super) fn order(&self) -> Vec<(OrderColumn, bool)> {
+        let library_order = [
+            SortField::AlbumArtist,
+            SortField::Year,
+            SortField::Album,
+            SortField::Track,
+            SortField::Title,
+        ]
+        .map(|field| SortKey {
+            field,
+            descending: false,
+        });
+
+        self.sort
+            .iter()
+            .chain(&library_order)
+            .flat_map(|key| {
+                sort_columns(key.field)
+                    .iter()
+                    .map(|column| (*column, key.descending))
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for TrackQuery {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// An expression tracks are ordered by, which is never NULL, so that it can be
+/// compared with a [`TrackCursor`](super::TrackCursor).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum OrderColumn {
+    /// Whether a value is missing, always ascending so that missing values go last.
+    Missing(&'static str),
+    Integer(&'static str),
+    Text(&'static str),
+}
+
+impl OrderColumn {
+    pub(super) fn expr(self) -> &'static str {
+        match self {
+            OrderColumn::Missing(expr) | OrderColumn::Integer(expr) | OrderColumn::Text(expr) => {
+                expr
+            }
+        }
+    }
+
+    pub(super) fn is_descending(self, descending: bool) -> bool {
+        descending && !matches!(self, OrderColumn::Missing(_))
+    }
+}
+
+fn sort_columns(field: SortField) -> &'static [OrderColumn] {
+    use OrderColumn::*;
+
+    match field {
+        SortField::Artist => &[
+            Missing("t.artist IS NULL"),
+            Text("coalesce(t.artist, '') COLLATE NOCASE"),
+        ],
+        SortField::AlbumArtist => &[
+            Missing("coalesce(t.album_artist, t.artist) IS NULL"),
+            Text("coalesce(t.album_artist, t.artist, '') COLLATE NOCASE"),
+        ],
+        SortField::Album => &[
+            Missing("t.album IS NULL"),
+            Text("coalesce(t.album, '') COLLATE NOCASE"),
+        ],
+        SortField::Title => &[
+            Missing("t.title IS NULL"),
+            Text("coalesce(t.title, '') COLLATE NOCASE"),
+        ],
+        SortField::Year => &[Missing("t.year IS NULL"), Integer("coalesce(t.year, 0)")],
+        SortField::Track => &[
+            Integer("coalesce(t.disc_number, 1)"),
+            Missing("t.track_number IS NULL"),
+            Integer("coalesce(t.track_number, 0)"),
+        ],
+        SortField::Duration => &[
+            Missing("t.duration_ms IS NULL"),
+            Integer("coalesce(t.duration_ms, 0)"),
+        ],
+        SortField::Genre => &[
+            Missing("t.genre IS NULL"),
+            Text("coalesce(t.genre, '') COLLATE NOCASE"),
+        ],
+        SortField::Added => &[
+            Missing("t.added_at IS NULL"),
+            Text("coalesce(t.added_at, '')"),
+        ],
+    }
+}
+
+/// Pushes `order`, then `t.id` to break ties.
+pub(super) fn push_order(builder: &mut QueryBuilder<Sqlite>, order: &[(OrderColumn, bool)]) {
+    for (column, descending) in order {
+        let direction = if column.is_descending(*descending) {
+            " DESC"
+        } else {
+            ""
+        };
+        builder.push(format_args!("{}{direction}, ", column.expr()));
+    }
+    builder.push("t.id");
+}
+
+/// A `LIKE` pattern matching values that contain `value`.
+fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Pushes a condition that is never NULL, so that negating it matches the tracks
+/// without the field as well.
+fn push_expr(builder: &mut QueryBuilder<Sqlite>, expr: &QueryExpr) {
+    match expr {
+        QueryExpr::And(exprs) | QueryExpr::Or(exprs) => {
+            let separator = if matches!(expr, QueryExpr::And(_)) {
+                " AND "
+            } else {
+                " OR "
+            };
+
+            builder.push("(");
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    builder.push(separator);
+                }
+                push_expr(builder, expr);
+            }
+            builder.push(")");
+        }
+        QueryExpr::Not(expr) => {
+            builder.push("NOT ");
+            push_expr(builder, expr);
+        }
+        QueryExpr::Text {
+            field: None,
+            value,
+            exact: _,
+        } => {
+            let columns = ["t.title", "t.artist", "t.album", "t.genre", "f.name"];
+
+            builder.push("(");
+            for (i, column) in columns.iter().enumerate() {
+                if i > 0 {
+                    builder.push(" OR ");
+                }
+                push_text_match(builder, column, value, false);
+            }
+            builder.push(")");
+        }
+        QueryExpr::Text {
+            field: Some(field),
+            value,
+            exact,
+        } => {
+            let column = match field {
+                TextField::Artist => "t.artist",
+                TextField::AlbumArtist => "t.album_artist",
+                TextField::Album => "t.album",
+                TextField::Title => "t.title",
+                TextField::Genre => "t.genre",
+                TextField::Composer => "t.composer",
+                TextField::File => "f.name",
+            };
+
+            builder.push("(");
+            push_text_match(builder, column, value, *exact);
+            match field {
+                TextField::Artist => {
+                    builder.push(
+                        r#"
+                        OR t.id IN (
+                            SELECT at.track_id
+                            FROM artist_tracks at
+                            INNER JOIN artists a
+                                ON a.id = at.artist_id
+                            WHERE "#,
+                    );
+                    push_text_match(builder, "a.name", value, *exact);
+                    builder.push(")");
+                }
+                TextField::Genre => {
+                    builder.push(
+                        r#"
+                        OR t.id IN (
+                            SELECT tg.track_id
+                            FROM track_genres tg
+                            INNER JOIN genres g
+                                ON g.id = tg.genre_id
+                            WHERE "#,
+                    );
+                    push_text_match(builder, "g.name", value, *exact);
+                    builder.push(")");
+                }
+                _ => {}
+            }
+            builder.push(")");
+        }
+        QueryExpr::Number { field, comparison } => {
+            let column = match field {
+                NumberField::Year => "t.year",
+                NumberField::Track => "t.track_number",
+                NumberField::Disc => "coalesce(t.disc_number, 1)",
+                NumberField::Duration => "t.duration_ms / 1000",
+            };
+
+            builder.push(format_args!("coalesce({column} "));
+            match *comparison {
+                Comparison::Eq(n) => builder.push("= ").push_bind(n),
+                Comparison::Lt(n) => builder.push("< ").push_bind(n),
+                Comparison::Le(n) => builder.push("<= ").push_bind(n),
+                Comparison::Gt(n) => builder.push("> ").push_bind(n),
+                Comparison::Ge(n) => builder.push(">= ").push_bind(n),
+                Comparison::Between(from, to) => builder
+                    .push("BETWEEN ")
+                    .push_bind(from)
+                    .push(" AND ")
+                    .push_bind(to),
+            };
+            builder.push(", FALSE)");
+        }
+    }
+}
+
+fn push_text_match(builder: &mut QueryBuilder<Sqlite>, column: &str, value: &str, exact: bool) {
+    if exact {
+        builder
+            .push(format_args!("coalesce({column}, '') = "))
+            .push_bind(value)
+            .push(" COLLATE NOCASE");
+    } else {
+        builder
+            .push(format_args!("coalesce({column}, '') LIKE "))
+            .push_bind(contains_pattern(value))
+            .push(" ESCAPE '\\'");
+    }
+}
+
+impl PersistenceState {
+    /// The tracks matching `query`, in its order.
+    pub async fn query_tracks(&self, query: &TrackQuery) -> anyhow::Result<Vec<Uuid>> {
+        let ids = query
+            .build()
+            .build_query_scalar::<Uuid>()
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(ids)
+    }
+}
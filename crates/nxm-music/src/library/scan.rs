@@ -0,0 +1,146 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use uuid::Uuid;
+
+use crate::db::types::ScanErrorKind;
+
+/// What a scan walks and diffs against the database.
+#[derive(Debug, Clone)]
+pub enum ScanTarget {
+    /// Every library.
+    Libraries,
+    Library(Uuid),
+    /// A directory inside a library, along with everything below it.
+    Subtree(PathBuf),
+    /// The directories containing these paths, as reported by the watcher.
+    Changed(Vec<PathBuf>),
+}
+
+/// Running totals over every root of a scan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanProgress {
+    pub dirs_visited: usize,
+    pub files_examined: usize,
+    pub tags_read: usize,
+    pub tags_total: usize,
+    /// Nodes that are inserted, updated, moved or deleted.
+    pub changes: usize,
+    /// Files that were skipped or couldn't be fully read.
+    pub errors: usize,
+}
+
+/// A file a scan couldn't read. Unreadable audio files are still listed as tracks,
+/// anything that couldn't be examined at all is left as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub kind: ScanErrorKind,
+    pub message: String,
+}
+
+impl ScanError {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        kind: ScanErrorKind,
+        error: impl std::fmt::Display,
+    ) -> Self {
+        let path = path.into();
+        let message = format!("{:#}", error);
+        log::warn!("{:?} reading {:#?}: {}", kind, path, message);
+
+        Self {
+            path,
+            kind,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ScanEvent {
+    Progress(ScanProgress),
+    /// The changes were committed.
+    Finished(ScanProgress),
+    /// Nothing was written.
+    Cancelled,
+    /// Nothing was written.
+    Failed(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Scan was cancelled")]
+pub struct ScanCancelled;
+
+/// The IO thread's side of a [`ScanJob`].
+#[derive(Debug, Clone, Default)]
+pub struct ScanControl {
+    cancel: Arc<AtomicBool>,
+    events: Option<flume::Sender<ScanEvent>>,
+}
+
+impl ScanControl {
+    /// Fails with [`ScanCancelled`] once the job has been cancelled.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(ScanCancelled.into());
+        }
+
+        Ok(())
+    }
+
+    pub fn send(&self, event: ScanEvent) {
+        log::info!("scan event: {:?}", event);
+
+        if let Some(events) = &self.events {
+            // The job may have been dropped, the scan still runs to completion.
+            let _ = events.send(event);
+        }
+    }
+
+    /// Reports how `res` ended the scan.
+    pub fn finish(&self, res: &anyhow::Result<ScanProgress>) {
+        self.send(match res {
+            Ok(progress) => ScanEvent::Finished(*progress),
+            Err(e) if e.is::<ScanCancelled>() => ScanEvent::Cancelled,
+            Err(e) => ScanEvent::Failed(format!("{:#}", e)),
+        });
+    }
+}
+
+/// A scan running on the IO thread.
+pub struct ScanJob {
+    cancel: Arc<AtomicBool>,
+    events: flume::Receiver<ScanEvent>,
+}
+
+impl ScanJob {
+    pub fn new() -> (Self, ScanControl) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = flume::unbounded();
+
+        (
+            Self {
+                cancel: cancel.clone(),
+                events: rx,
+            },
+            ScanControl {
+                cancel,
+                events: Some(tx),
+            },
+        )
+    }
+
+    /// Stops the scan at the next file or batch of tags. Whatever it found so far is
+    /// discarded.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Progress events, ending with `Finished`, `Cancelled` or `Failed`.
+    pub fn events(&self) -> &flume::Receiver<ScanEvent> {
+        &self.events
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashSet;
+
+use unicode_normalization::UnicodeNormalization as _;
+use unicode_normalization::char::is_combining_mark;
+use uuid::Uuid;
+
+use super::PersistenceState;
+
+/// Rows fetched by relevance for every kind of result, before they're ranked by how
+/// well they match.
+const CANDIDATES: i64 = 200;
+
+/// How alike a query word and an indexed text have to be, see [`word_score`].
+const MIN_WORD_SCORE: f32 = 0.5;
+
+/// What [`PersistenceState::search`] found, best match first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchResults {
+    pub tracks: Vec<Uuid>,
+    pub albums: Vec<Uuid>,
+    pub artists: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SearchIndex {
+    Tracks,
+    Albums,
+    Artists,
+}
+
+/// A row of a search index, with its values joined.
+struct Candidate {
+    id: Uuid,
+    text: String,
+}
+
+/// `text` lowercased and without diacritics, the way the search indexes see it.
+fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The words of a search query, folded.
+fn query_words(query: &str) -> Vec<String> {
+    fold(query)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The trigrams of `word` padded with spaces, so that the start and end of a word
+/// count as well.
+fn trigrams(word: &str) -> Vec<String> {
+    let chars = format!(" {word} ").chars().collect::<Vec<_>>();
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+/// Matches rows containing every word of at least 3 characters, which shorter words
+/// can't be looked up by.
+fn exact_expression(words: &[String]) -> Option<String> {
+    let exprs = words
+        .iter()
+        .filter(|word| word.chars().count() >= 3)
+        .map(|word| format!("\"{word}\""))
+        .collect::<Vec<_>>();
+
+    (!exprs.is_empty()).then(|| exprs.join(" AND "))
+}
+
+/// Matches rows sharing a trigram with every word of at least 3 characters, which
+/// includes those with a typo.
+fn fuzzy_expression(words: &[String]) -> Option<String> {
+    let exprs = words
+        .iter()
+        .filter(|word| word.chars().count() >= 3)
+        .map(|word| {
+            let trigrams = trigrams(word)
+                .into_iter()
+                .map(|trigram| format!("\"{trigram}\""))
+                .collect::<Vec<_>>();
+            format!("({})", trigrams.join(" OR "))
+        })
+        .collect::<Vec<_>>();
+
+    (!exprs.is_empty()).then(|| exprs.join(" AND "))
+}
+
+/// How well the text of a row matches `words`, from 0 to 1, or `None` if a word
+/// doesn't match at all.
+fn score(words: &[String], text: &str) -> Option<f32> {
+    let text = fold(text);
+    let text_words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut total = 0.0;
+    for word in words {
+        let score = word_score(word, &text, &text_words);
+        if score < MIN_WORD_SCORE {
+            return None;
+        }
+        total += score;
+    }
+
+    Some(total / words.len() as f32)
+}
+
+/// 1 if `word` is in `text`, otherwise the best of how many of its trigrams are in
+/// `text` and how close it is to a word of `text`, or to the start of one, since the
+/// last word of a query is usually still being typed. Words shorter than 3
+/// characters have to start a word of `text`.
+fn word_score(word: &str, text: &str, text_words: &[&str]) -> f32 {
+    let len = word.chars().count();
+    if len < 3 {
+        return if text_words.iter().any(|w| w.starts_with(word)) {
+            1.0
+        } else {
+            0.0
+        };
+    }
+    if text.contains(word) {
+        return 1.0;
+    }
+
+    let padded = format!(" {} ", text_words.join(" "));
+    let trigrams = trigrams(word);
+    let shared = trigrams
+        .iter()
+        .filter(|t| padded.contains(t.as_str()))
+        .count();
+    let trigram_score = shared as f32 / trigrams.len() as f32;
+
+    let word = word.chars().collect::<Vec<_>>();
+    let max_distance = if len >= 8 {
+        2
+    } else if len >= 4 {
+        1
+    } else {
+        0
+    };
+    let edit_score = text_words
+        .iter()
+        .map(|text_word| {
+            let text_word = text_word.chars().collect::<Vec<_>>();
+            let prefix = &text_word[..text_word.len().min(len)];
+            edit_distance(&word, &text_word).min(edit_distance(&word, prefix))
+        })
+        .filter(|distance| *distance <= max_distance)
+        .map(|distance| 1.0 - distance as f32 / len as f32)
+        .fold(0.0, f32::max);
+
+    trigram_score.max(edit_score)
+}
+
+/// The optimal string alignment distance, where swapping two neighbouring characters
+/// is one edit.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+impl PersistenceState {
+    /// Tracks, albums and artists matching every word of `query`, at most `limit` of
+    /// each. Case and diacritics are ignored, words may be partial, and a typo or two
+    /// is tolerated, depending on the length of the word.
+    pub async fn search(&self, query: &str, limit: usize) -> anyhow::Result<SearchResults> {
+        let words = query_words(query);
+        if words.is_empty() {
+            return Ok(SearchResults::default());
+        }
+
+        Ok(SearchResults {
+            tracks: self
+                .search_index(SearchIndex::Tracks, &words, limit)
+                .await?,
+            albums: self
+                .search_index(SearchIndex::Albums, &words, limit)
+                .await?,
+            artists: self
+                .search_index(SearchIndex::Artists, &words, limit)
+                .await?,
+        })
+    }
+
+    /// Exact matches are looked up first, rows with a typo only if there aren't enough
+    /// of them, since matching by trigram is what's slow on a large library.
+    async fn search_index(
+        &self,
+        index: SearchIndex,
+        words: &[String],
+        limit: usize,
+    ) -> anyhow::Result<Vec<Uuid>> {
+        let expressions = match (exact_expression(words), fuzzy_expression(words)) {
+            (Some(exact), Some(fuzzy)) => vec![Some(exact), Some(fuzzy)],
+            _ => vec![None],
+        };
+        // Only used without an expression, when every word is shorter than a trigram.
+        let pattern = format!("%{}%", words[0]);
+
+        let mut seen = HashSet::new();
+        let mut hits = vec![];
+        for expression in expressions {
+            let candidates = self
+                .search_candidates(index, expression.as_deref(), &pattern)
+                .await?;
+            for candidate in candidates {
+                if seen.insert(candidate.id)
+                    && let Some(score) = score(words, &candidate.text)
+                {
+                    hits.push((score, candidate.id));
+                }
+            }
+
+            if hits.len() >= limit {
+                break;
+            }
+        }
+
+        // Stable, so that equal scores stay in the order of relevance.
+        hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+        hits.truncate(limit);
+
+        Ok(hits.into_iter().map(|(_, id)| id).collect())
+    }
+
+    /// Rows of `index` that `expression` matches by relevance, or that contain
+    /// `pattern` without one.
+    async fn search_candidates(
+        &self,
+        index: SearchIndex,
+        expression: Option<&str>,
+        pattern: &str,
+    ) -> anyhow::Result<Vec<Candidate>> {
+        let candidates = match (index, expression) {
+            (SearchIndex::Tracks, Some(expression)) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        t.id AS "id: Uuid",
+                        coalesce(s.title, '')
+                            || coalesce(s.artist, '')
+                            || coalesce(s.album, '')
+                            || coalesce(s.genre, '')
+                            || coalesce(s.filename, '') AS "text!: String"
+                    FROM tracks_fts s
+                    INNER JOIN tracks_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN visible_tracks t
+                        ON t.id = k.track_id
+                    WHERE tracks_fts MATCH ?1
+                    ORDER BY bm25(tracks_fts, 10.0, 5.0, 5.0, 2.0, 1.0)
+                    LIMIT ?2
+                    "#,
+                    expression,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            (SearchIndex::Tracks, None) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        t.id AS "id: Uuid",
+                        coalesce(s.title, '')
+                            || coalesce(s.artist, '')
+                            || coalesce(s.album, '')
+                            || coalesce(s.genre, '')
+                            || coalesce(s.filename, '') AS "text!: String"
+                    FROM tracks_fts s
+                    INNER JOIN tracks_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN visible_tracks t
+                        ON t.id = k.track_id
+                    WHERE (
+                            s.title LIKE ?1
+                            OR s.artist LIKE ?1
+                            OR s.album LIKE ?1
+                            OR s.genre LIKE ?1
+                            OR s.filename LIKE ?1
+                        )
+                    ORDER BY length(s.title)
+                    LIMIT ?2
+                    "#,
+                    pattern,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            (SearchIndex::Albums, Some(expression)) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        a.id AS "id: Uuid",
+                        coalesce(s.title, '') || coalesce(s.artist, '') AS "text!: String"
+                    FROM albums_fts s
+                    INNER JOIN albums_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN albums a
+                        ON a.id = k.album_id
+                    WHERE albums_fts MATCH ?1
+                        AND EXISTS (
+                            SELECT 1
+                            FROM visible_tracks t
+                            WHERE t.album_id = a.id
+                        )
+                    ORDER BY bm25(albums_fts, 2.0, 1.0)
+                    LIMIT ?2
+                    "#,
+                    expression,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            (SearchIndex::Albums, None) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        a.id AS "id: Uuid",
+                        coalesce(s.title, '') || coalesce(s.artist, '') AS "text!: String"
+                    FROM albums_fts s
+                    INNER JOIN albums_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN albums a
+                        ON a.id = k.album_id
+                    WHERE (s.title LIKE ?1 OR s.artist LIKE ?1)
+                        AND EXISTS (
+                            SELECT 1
+                            FROM visible_tracks t
+                            WHERE t.album_id = a.id
+                        )
+                    ORDER BY length(s.title)
+                    LIMIT ?2
+                    "#,
+                    pattern,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            (SearchIndex::Artists, Some(expression)) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        a.id AS "id: Uuid",
+                        s.name AS "text!: String"
+                    FROM artists_fts s
+                    INNER JOIN artists_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN artists a
+                        ON a.id = k.artist_id
+                    WHERE artists_fts MATCH ?1
+                        AND EXISTS (
+                            SELECT 1
+                            FROM artist_tracks at
+                            INNER JOIN visible_tracks t
+                                ON t.id = at.track_id
+                            WHERE at.artist_id = a.id
+                        )
+                    ORDER BY bm25(artists_fts)
+                    LIMIT ?2
+                    "#,
+                    expression,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+            (SearchIndex::Artists, None) => {
+                sqlx::query_as!(
+                    Candidate,
+                    r#"
+                    SELECT
+                        a.id AS "id: Uuid",
+                        s.name AS "text!: String"
+                    FROM artists_fts s
+                    INNER JOIN artists_fts_keys k
+                        ON k.key = s.rowid
+                    INNER JOIN artists a
+                        ON a.id = k.artist_id
+                    WHERE s.name LIKE ?1
+                        AND EXISTS (
+                            SELECT 1
+                            FROM artist_tracks at
+                            INNER JOIN visible_tracks t
+                                ON t.id = at.track_id
+                            WHERE at.artist_id = a.id
+                        )
+                    ORDER BY length(s.name)
+                    LIMIT ?2
+                    "#,
+                    pattern,
+                    CANDIDATES,
+                )
+                .fetch_all(&self.db)
+                .await?
+            }
+        };
+
+        Ok(candidates)
+    }
+}
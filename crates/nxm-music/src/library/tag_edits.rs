@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use chrono::Utc;
+use collections::FxIndexMap;
+use crossbeam_channel::Sender;
+use lofty::config::WriteOptions;
+use lofty::file::TaggedFileExt as _;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use lofty::tag::Tag;
+use lofty::tag::TagExt as _;
+use uuid::Uuid;
+
+use super::ArtworkCache;
+use super::FsNode;
+use super::IoMainMsg;
+use super::PersistenceState;
+use super::SyncOp;
+use super::delete_unused_artwork;
+use super::delete_unused_credits;
+use super::get_identity;
+use super::parse_year;
+use super::read_audio_file;
+use super::sync_albums;
+use crate::db::types::TagField;
+
+/// Sets `field` to `value` in the tags of a file, or removes it if `value` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagEdit {
+    pub field: TagField,
+    pub value: Option<String>,
+}
+
+/// A field of a track before and after an edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagChange {
+    pub track_id: Uuid,
+    pub field: TagField,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A track whose file couldn't be edited, which is left as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagWriteError {
+    pub track_id: Uuid,
+    pub path: PathBuf,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagEditOutcome {
+    /// The entry of the undo log that was written or undone. `None` for a dry run, or
+    /// if no file was changed.
+    pub id: Option<Uuid>,
+    /// What was written, or would be by a dry run. Fields that have the new value
+    /// already are left out.
+    pub changes: Vec<TagChange>,
+    pub errors: Vec<TagWriteError>,
+}
+
+/// An edit in the undo log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagEditEntry {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub undone: bool,
+    pub changes: Vec<TagChange>,
+}
+
+pub enum TagEditMsg {
+    Edit {
+        track_ids: Vec<Uuid>,
+        edits: Vec<TagEdit>,
+        dry_run: bool,
+        reply: flume::Sender<anyhow::Result<TagEditOutcome>>,
+    },
+    Undo {
+        id: Uuid,
+        reply: flume::Sender<anyhow::Result<TagEditOutcome>>,
+    },
+    History {
+        reply: flume::Sender<anyhow::Result<Vec<TagEditEntry>>>,
+    },
+}
+
+fn item_key(field: TagField) -> ItemKey {
+    match field {
+        TagField::Artist => ItemKey::TrackArtist,
+        TagField::Title => ItemKey::TrackTitle,
+        TagField::Album => ItemKey::AlbumTitle,
+        TagField::AlbumArtist => ItemKey::AlbumArtist,
+        TagField::TrackNumber => ItemKey::TrackNumber,
+        TagField::TrackTotal => ItemKey::TrackTotal,
+        TagField::DiscNumber => ItemKey::DiscNumber,
+        TagField::DiscTotal => ItemKey::DiscTotal,
+        TagField::Year => ItemKey::RecordingDate,
+        TagField::Genre => ItemKey::Genre,
+        TagField::Composer => ItemKey::Composer,
+    }
+}
+
+/// `value` as it's written to files: trimmed, with an empty value removing the field.
+fn normalize_value(field: TagField, value: Option<&str>) -> anyhow::Result<Option<String>> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+
+    match field {
+        TagField::TrackNumber
+        | TagField::TrackTotal
+        | TagField::DiscNumber
+        | TagField::DiscTotal => {
+            let number = value
+                .parse::<u32>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or_else(|| anyhow::anyhow!("{:?} isn't a valid {:?}", value, field))?;
+
+            Ok(Some(number.to_string()))
+        }
+        TagField::Year => {
+            anyhow::ensure!(
+                parse_year(value).is_some(),
+                "{:?} isn't a valid {:?}",
+                value,
+                field
+            );
+
+            Ok(Some(value.to_string()))
+        }
+        _ => Ok(Some(value.to_string())),
+    }
+}
+
+/// The tag a scan reads the file at `path` from, or a new tag of the type the file
+/// would have.
+fn read_tag(path: &Path) -> anyhow::Result<Tag> {
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+
+    Ok(tagged_file
+        .primary_tag()
+        .or(tagged_file.first_tag())
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tagged_file.primary_tag_type())))
+}
+
+fn field_value(tag: &Tag, field: TagField) -> Option<String> {
+    tag.get_string(&item_key(field))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn set_field(tag: &mut Tag, field: TagField, value: Option<&str>) -> anyhow::Result<()> {
+    let key = item_key(field);
+    tag.remove_key(&key);
+    if field == TagField::Artist {
+        // Lists the artists of the old value, see `read_credits`.
+        tag.remove_key(&ItemKey::TrackArtists);
+    }
+
+    if let Some(value) = value {
+        anyhow::ensure!(
+            tag.insert_text(key, value.to_string()),
+            "{:?} can't be stored in a {:?} tag",
+            field,
+            tag.tag_type()
+        );
+    }
+
+    Ok(())
+}
+
+/// Applies `edits` to the file of `track_id` at `path`, unless it's a dry run.
+/// Returns the fields that change.
+fn edit_file(
+    track_id: Uuid,
+    path: &Path,
+    edits: &[TagEdit],
+    dry_run: bool,
+) -> anyhow::Result<Vec<TagChange>> {
+    let mut tag = read_tag(path)?;
+
+    let changes = edits
+        .iter()
+        .filter_map(|edit| {
+            let old = field_value(&tag, edit.field);
+            (old != edit.value).then(|| TagChange {
+                track_id,
+                field: edit.field,
+                old,
+                new: edit.value.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+    if dry_run || changes.is_empty() {
+        return Ok(changes);
+    }
+
+    for change in &changes {
+        set_field(&mut tag, change.field, change.new.as_deref())?;
+    }
+    tag.save_to_path(path, WriteOptions::default())?;
+
+    Ok(changes)
+}
+
+/// The edited file as a scan would find it, so that the next scan sees it unchanged.
+fn read_edited_file(
+    filenode_id: Uuid,
+    parent_id: Option<Uuid>,
+    path: &Path,
+    artwork_cache: &ArtworkCache,
+) -> anyhow::Result<FsNode> {
+    let info = read_audio_file(path, artwork_cache).map_err(|e| anyhow::anyhow!(e.message))?;
+    let meta = path.metadata()?;
+
+    Ok(FsNode {
+        path: path.into(),
+        file_type: info.file_type,
+        identity: get_identity(&meta),
+        size: meta.len(),
+        mtime: DateTime::<Utc>::from(meta.modified()?),
+        parent_id,
+        hashes: info.hashes,
+        format: Some(info.format),
+        db_id: filenode_id,
+        op: SyncOp::UpdateMeta,
+        relinked_track: None,
+    })
+}
+
+impl PersistenceState {
+    /// Sets the fields of `edits` on every track of `track_ids`. The files are written
+    /// along with their `filenodes` and `tracks` rows, so that the next scan finds them
+    /// unchanged, and the edit is added to the undo log. A dry run only reports what
+    /// would change.
+    ///
+    /// Files that can't be written are reported and left as they were.
+    pub async fn edit_tags(
+        &self,
+        track_ids: &[Uuid],
+        edits: &[TagEdit],
+        dry_run: bool,
+    ) -> anyhow::Result<TagEditOutcome> {
+        let edits = edits
+            .iter()
+            .map(|edit| {
+                Ok(TagEdit {
+                    field: edit.field,
+                    value: normalize_value(edit.field, edit.value.as_deref())?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let targets = track_ids.iter().map(|id| (*id, edits.clone())).collect();
+        self.write_tag_edits(targets, dry_run, None).await
+    }
+
+    /// Sets the fields changed by the edit `id` back to what they were, even if they
+    /// were changed again since.
+    pub async fn undo_tag_edit(&self, id: Uuid) -> anyhow::Result<TagEditOutcome> {
+        let undone = sqlx::query_scalar!(
+            r#"SELECT undone AS "undone: bool" FROM tag_edits WHERE id = ?"#,
+            id,
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Tag edit {} doesn't exist", id))?;
+        anyhow::ensure!(!undone, "Tag edit {} is undone already", id);
+
+        let mut targets = FxIndexMap::<Uuid, Vec<TagEdit>>::default();
+        for change in self
+            .tag_edit_changes(Some(id))
+            .await?
+            .into_values()
+            .flatten()
+        {
+            targets.entry(change.track_id).or_default().push(TagEdit {
+                field: change.field,
+                value: change.old,
+            });
+        }
+
+        self.write_tag_edits(targets.into_iter().collect(), false, Some(id))
+            .await
+    }
+
+    /// The undo log, latest first.
+    pub async fn tag_edits(&self) -> anyhow::Result<Vec<TagEditEntry>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT
+                id AS "id: Uuid",
+                created_at AS "created_at: DateTime<Utc>",
+                undone AS "undone: bool"
+            FROM tag_edits
+            ORDER BY created_at DESC, rowid DESC
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let mut changes = self.tag_edit_changes(None).await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|rec| TagEditEntry {
+                id: rec.id,
+                created_at: rec.created_at,
+                undone: rec.undone,
+                changes: changes.remove(&rec.id).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// The changes of the edit `id`, or of every edit, keyed by their edit.
+    async fn tag_edit_changes(
+        &self,
+        id: Option<Uuid>,
+    ) -> anyhow::Result<HashMap<Uuid, Vec<TagChange>>> {
+        let recs = sqlx::query!(
+            r#"
+            SELECT
+                edit_id AS "edit_id: Uuid",
+                track_id AS "track_id: Uuid",
+                field AS "field: TagField",
+                old_value,
+                new_value
+            FROM tag_edit_changes
+            WHERE ?1 IS NULL OR edit_id = ?1
+            ORDER BY rowid
+            "#,
+            id,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut changes = HashMap::<Uuid, Vec<TagChange>>::new();
+        for rec in recs {
+            changes.entry(rec.edit_id).or_default().push(TagChange {
+                track_id: rec.track_id,
+                field: rec.field,
+                old: rec.old_value,
+                new: rec.new_value,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    /// Writes `targets` to the files of their tracks and the database. Marks the edit
+    /// `undoing` as undone once all of its files are written, or logs a new edit.
+    async fn write_tag_edits(
+        &self,
+        targets: Vec<(Uuid, Vec<TagEdit>)>,
+        dry_run: bool,
+        undoing: Option<Uuid>,
+    ) -> anyhow::Result<TagEditOutcome> {
+        let mut files = HashMap::new();
+        for (track_id, _) in &targets {
+            // The path of the file is built from the `filenodes` tree, up to the root of
+            // its library.
+            let Some(file) = sqlx::query!(
+                r#"
+                WITH RECURSIVE paths (filenode_id, parent_id, node_id, path) AS (
+                    SELECT f.id, f.parent_id, f.parent_id, f.name
+                    FROM tracks t
+                    INNER JOIN filenodes f
+                        ON f.id = t.filenode_id
+                    WHERE t.id = ?
+
+                    UNION ALL
+
+                    SELECT s.filenode_id, s.parent_id, f.parent_id, (f.name || '/' || s.path)
+                    FROM paths s
+                    INNER JOIN filenodes f
+                        ON f.id = s.node_id
+                    WHERE f.id NOT IN (
+                        SELECT l.node
+                        FROM libraries l
+                        WHERE l.node IS NOT NULL
+                    )
+                )
+                SELECT
+                    s.filenode_id AS "filenode_id!: Uuid",
+                    s.parent_id AS "parent_id?: Uuid",
+                    (l.path || '/' || s.path) AS "path!: String",
+                    l.online AS "online!: bool"
+                FROM paths s
+                INNER JOIN libraries l
+                    ON l.node = s.node_id
+                "#,
+                track_id,
+            )
+            .fetch_optional(&self.db)
+            .await?
+            else {
+                anyhow::bail!("Track {} isn't in the library", track_id);
+            };
+
+            anyhow::ensure!(file.online, "Track {} is in an offline library", track_id);
+            files.insert(
+                *track_id,
+                (file.filenode_id, file.parent_id, PathBuf::from(file.path)),
+            );
+        }
+
+        // Writing and reading the files back is blocking IO.
+        let (changes, nodes, errors) = tokio::task::spawn_blocking({
+            let artwork_cache = self.artwork_cache.clone();
+            move || {
+                let mut changes = vec![];
+                let mut nodes = vec![];
+                let mut errors = vec![];
+
+                for (track_id, edits) in targets {
+                    let (filenode_id, parent_id, path) = &files[&track_id];
+                    let res = edit_file(track_id, path, &edits, dry_run).and_then(|edited| {
+                        if !dry_run && !edited.is_empty() {
+                            nodes.push(read_edited_file(
+                                *filenode_id,
+                                *parent_id,
+                                path,
+                                &artwork_cache,
+                            )?);
+                        }
+                        Ok(edited)
+                    });
+
+                    match res {
+                        Ok(edited) => changes.extend(edited),
+                        Err(e) => {
+                            log::warn!("Unable to edit the tags of {:#?}: {:#}", path, e);
+                            errors.push(TagWriteError {
+                                track_id,
+                                path: path.clone(),
+                                message: format!("{:#}", e),
+                            });
+                        }
+                    }
+                }
+
+                (changes, nodes, errors)
+            }
+        })
+        .await?;
+
+        if dry_run || (nodes.is_empty() && undoing.is_none()) {
+            return Ok(TagEditOutcome {
+                id: None,
+                changes,
+                errors,
+            });
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        for node in &nodes {
+            node.update_in_db(&mut tx).await?;
+        }
+
+        let id = match undoing {
+            Some(id) => {
+                // Left to be undone again if some of its files couldn't be written.
+                if errors.is_empty() {
+                    sqlx::query!("UPDATE tag_edits SET undone = TRUE WHERE id = ?", id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                id
+            }
+            None => {
+                let id = Uuid::new_v4();
+                sqlx::query!(
+                    "INSERT INTO tag_edits (id, created_at) VALUES (?, ?)",
+                    id,
+                    Utc::now(),
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                for change in &changes {
+                    sqlx::query!(
+                        r#"
+                        INSERT INTO tag_edit_changes (edit_id, track_id, field, old_value, new_value)
+                        VALUES (?, ?, ?, ?, ?)
+                        "#,
+                        id,
+                        change.track_id,
+                        change.field,
+                        change.old,
+                        change.new,
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                id
+            }
+        };
+
+        delete_unused_credits(&mut tx).await?;
+        sync_albums(&mut tx).await?;
+        let unused_artwork = delete_unused_artwork(&mut tx).await?;
+
+        tx.commit().await?;
+
+        for hash in &unused_artwork {
+            self.artwork_cache.remove(hash);
+        }
+
+        Ok(TagEditOutcome {
+            id: Some(id),
+            changes,
+            errors,
+        })
+    }
+
+    pub(super) async fn handle_tag_edit_msg(
+        &mut self,
+        msg: TagEditMsg,
+        io_main_tx: &Sender<IoMainMsg>,
+    ) -> anyhow::Result<()> {
+        match msg {
+            TagEditMsg::Edit {
+                track_ids,
+                edits,
+                dry_run,
+                reply,
+            } => {
+                let res = match self.edit_tags(&track_ids, &edits, dry_run).await {
+                    Ok(outcome) if !dry_run => self.send_delta(io_main_tx).await.map(|_| outcome),
+                    res => res,
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("TagEditMsg::Edit"))?;
+            }
+            TagEditMsg::Undo { id, reply } => {
+                let res = match self.undo_tag_edit(id).await {
+                    Ok(outcome) => self.send_delta(io_main_tx).await.map(|_| outcome),
+                    Err(e) => Err(e),
+                };
+
+                reply
+                    .try_send(res)
+                    .map_err(|_| anyhow::anyhow!("TagEditMsg::Undo"))?;
+            }
+            TagEditMsg::History { reply } => {
+                reply
+                    .try_send(self.tag_edits().await)
+                    .map_err(|_| anyhow::anyhow!("TagEditMsg::History"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
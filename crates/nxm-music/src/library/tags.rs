@@ -0,0 +1,152 @@
+use lofty::tag::Accessor as _;
+use lofty::tag::ItemKey;
+use lofty::tag::Tag;
+use std::path::Path;
+
+use uuid::Uuid;
+
+use super::Artwork;
+use super::Credit;
+use super::album_key;
+use super::insert_artwork;
+use super::read_credits;
+use super::read_genres;
+use super::write_credits;
+
+/// The common tags of an audio file, as stored on its track.
+///
+/// Text is trimmed and empty values are left out, so that a column is either NULL or
+/// something to show.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackTags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub musicbrainz_album_id: Option<String>,
+    /// Set by the compilation flag of iTunes and others.
+    pub compilation: bool,
+    /// Whether the tag has an embedded picture.
+    pub has_picture: bool,
+    /// The embedded picture, once it's stored by [`store_embedded_artwork`](super::store_embedded_artwork).
+    pub artwork: Option<Artwork>,
+    /// Every artist of the track, including the ones in `artist` and `composer`.
+    pub credits: Vec<Credit>,
+    /// Every genre of the track, including `genre`.
+    pub genres: Vec<String>,
+}
+
+impl TrackTags {
+    pub fn from_tag(tag: &Tag) -> Self {
+        let title = normalize(tag.title().as_deref());
+
+        Self {
+            artist: normalize(tag.artist().as_deref()),
+            credits: read_credits(tag, title.as_deref()),
+            genres: read_genres(tag),
+            title,
+            album: normalize(tag.album().as_deref()),
+            album_artist: normalize(tag.get_string(&ItemKey::AlbumArtist)),
+            track_number: tag.track().filter(|n| *n > 0),
+            track_total: tag.track_total().filter(|n| *n > 0),
+            disc_number: tag.disk().filter(|n| *n > 0),
+            disc_total: tag.disk_total().filter(|n| *n > 0),
+            year: [ItemKey::RecordingDate, ItemKey::OriginalReleaseDate]
+                .iter()
+                .find_map(|key| tag.get_string(key).and_then(parse_year)),
+            genre: normalize(tag.genre().as_deref()),
+            composer: normalize(tag.get_string(&ItemKey::Composer)),
+            musicbrainz_album_id: normalize(tag.get_string(&ItemKey::MusicBrainzReleaseId)),
+            compilation: tag
+                .get_string(&ItemKey::FlagCompilation)
+                .is_some_and(|flag| flag.trim() == "1"),
+            has_picture: !tag.pictures().is_empty(),
+            artwork: None,
+        }
+    }
+
+    /// Writes the tags, credits and genres to the track of the `filenodes` row
+    /// `filenode_id`, the file at `path`. The track is grouped into its album by
+    /// [`sync_albums`](super::sync_albums).
+    pub async fn write_to_track(
+        &self,
+        connection: &mut sqlx::SqliteConnection,
+        filenode_id: Uuid,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let album_key = album_key(self, path);
+        if let Some(artwork) = &self.artwork {
+            insert_artwork(&mut *connection, artwork).await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET
+                artist = ?,
+                title = ?,
+                album = ?,
+                album_artist = ?,
+                track_number = ?,
+                track_total = ?,
+                disc_number = ?,
+                disc_total = ?,
+                year = ?,
+                genre = ?,
+                composer = ?,
+                musicbrainz_album_id = ?,
+                compilation = ?,
+                has_picture = ?,
+                artwork_hash = ?,
+                album_key = ?
+            WHERE filenode_id = ?;
+            "#,
+            self.artist,
+            self.title,
+            self.album,
+            self.album_artist,
+            self.track_number,
+            self.track_total,
+            self.disc_number,
+            self.disc_total,
+            self.year,
+            self.genre,
+            self.composer,
+            self.musicbrainz_album_id,
+            self.compilation,
+            self.has_picture,
+            self.artwork.as_ref().map(|artwork| artwork.hash),
+            album_key,
+            filenode_id,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        write_credits(&mut *connection, filenode_id, &self.credits, &self.genres).await
+    }
+}
+
+fn normalize(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// The year of a date like `2004`, `2004-05-01` or `2004-05-01T12:00:00`.
+pub(super) fn parse_year(date: &str) -> Option<i32> {
+    let date = date.trim();
+    let digits = date
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_digit())
+        .map_or(date.len(), |(i, _)| i);
+
+    (digits == 4).then(|| date[..4].parse().ok()).flatten()
+}
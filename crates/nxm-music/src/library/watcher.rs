@@ -0,0 +1,90 @@
+use std::path::Path;
+use std::time::Duration;
+
+use collections::FxIndexSet;
+use notify_debouncer_full::DebounceEventResult;
+use notify_debouncer_full::Debouncer;
+use notify_debouncer_full::RecommendedCache;
+use notify_debouncer_full::new_debouncer;
+use notify_debouncer_full::notify::RecommendedWatcher;
+use notify_debouncer_full::notify::RecursiveMode;
+
+use super::MainIoMsg;
+
+/// Copying an album in produces a burst of events per file, waiting this long after
+/// the last one turns it into a single rescan.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often library roots are checked for having been mounted or unmounted, which
+/// the watcher doesn't notice.
+const ROOT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Watches library roots and forwards debounced changes as [`MainIoMsg::FsChanged`].
+pub struct LibraryWatcher {
+    debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+}
+
+impl LibraryWatcher {
+    pub fn new(main_io_tx: flume::Sender<MainIoMsg>) -> anyhow::Result<Self> {
+        let debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, move |res| {
+            forward_events(&main_io_tx, res)
+        })?;
+
+        Ok(Self { debouncer })
+    }
+
+    pub fn watch(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.debouncer.watch(path, RecursiveMode::Recursive)?;
+
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.debouncer.unwatch(path)?;
+
+        Ok(())
+    }
+}
+
+fn forward_events(main_io_tx: &flume::Sender<MainIoMsg>, res: DebounceEventResult) {
+    let events = match res {
+        Ok(events) => events,
+        Err(errors) => {
+            for e in errors {
+                log::error!("LibraryWatcher error: {:#?}", e);
+            }
+            return;
+        }
+    };
+
+    let paths = events
+        .into_iter()
+        .filter(|event| !event.kind.is_access())
+        .flat_map(|event| event.event.paths)
+        .collect::<FxIndexSet<_>>();
+
+    if paths.is_empty() {
+        return;
+    }
+
+    if main_io_tx
+        .send(MainIoMsg::FsChanged {
+            paths: paths.into_iter().collect(),
+        })
+        .is_err()
+    {
+        log::error!("Unable to send MainIoMsg::FsChanged");
+    }
+}
+
+/// Sends [`MainIoMsg::CheckLibraryRoots`] every [`ROOT_POLL_INTERVAL`], until the IO
+/// thread is gone.
+pub fn poll_library_roots(main_io_tx: flume::Sender<MainIoMsg>) {
+    loop {
+        std::thread::sleep(ROOT_POLL_INTERVAL);
+
+        if main_io_tx.send(MainIoMsg::CheckLibraryRoots).is_err() {
+            break;
+        }
+    }
+}
@@ -1,47 +1,259 @@
-use std::{collections::HashMap, sync::Arc};
-
-use collections::FxIndexMap;
-use orx_linked_list::{
-    DoublyEnds as _, DoublyEndsMut as _, DoublyIdx, DoublyIterable as _, DoublyListLazy,
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::{Bound, Range, RangeBounds},
+    sync::Arc,
 };
+
+use collections::{FxIndexMap, RadixLinkTree};
+use orx_linked_list::{DoublyEnds as _, DoublyEndsMut as _, DoublyIdx, DoublyListLazy};
 use rand::Rng as _;
+use slotmap::SlotMap;
 use uuid::Uuid;
 
 use crate::library::Track;
 
+slotmap::new_key_type! {
+    pub struct QueueId;
+}
+
+/// How many destructive edits a `Queue` can undo by default.
+pub const DEFAULT_HISTORY_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub idx: DoublyIdx<Uuid>,
+    pub id: Uuid,
+}
+
+/// The order view plus cursor position of a `Queue` at some point in time.
+///
+/// `order` shares its nodes with the queue it was taken from until either changes
+/// them, so taking one copies a pointer per 64 entries. The `idx`s inside are only
+/// meaningful for that queue's current `list`, `Queue::restore` rebuilds the list
+/// from the `id`s.
+#[derive(Clone)]
+pub struct QueueSnapshot {
+    order: RadixLinkTree<QueueEntry>,
+    curr: Option<usize>,
+}
+
+impl QueueSnapshot {
+    /// Drops every entry of `uuids`, keeping the cursor on the same track, or the
+    /// closest one left before it.
+    fn remove_all(&mut self, uuids: &HashSet<Uuid>) {
+        if !self.order.iter().any(|entry| uuids.contains(&entry.id)) {
+            return;
+        }
+
+        self.curr = self.curr.map(|curr| {
+            let removed_before = self
+                .order
+                .range(..curr.min(self.order.len()))
+                .filter(|entry| uuids.contains(&entry.id))
+                .count();
+            curr - removed_before
+        });
+        self.order.retain(|entry| !uuids.contains(&entry.id));
+        self.curr = self
+            .curr
+            .map(|curr| curr.min(self.order.len().saturating_sub(1)));
+    }
+}
+
+pub struct QueueHistory {
+    undo: VecDeque<QueueSnapshot>,
+    redo: Vec<QueueSnapshot>,
+    depth: usize,
+}
+
+impl QueueHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::with_capacity(depth),
+            redo: Vec::new(),
+            depth,
+        }
+    }
+
+    fn record(&mut self, snapshot: QueueSnapshot) {
+        self.redo.clear();
+
+        if self.depth == 0 {
+            return;
+        }
+        if self.undo.len() == self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(snapshot);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// A play queue.
+///
+/// `list` and `order` always describe the same sequence: `order[i].idx` is the `i`th
+/// node of `list`, and `position` is the index of `curr` in `order`. The fields are
+/// private so that every mutation goes through a method keeping the two in step.
 pub struct Queue {
-    pub list: DoublyListLazy<Uuid>, // main queue traversal/mutation. O(1)
-    pub curr: Option<DoublyIdx<Uuid>>, //
-    pub tracks: HashMap<Uuid, Vec<DoublyIdx<Uuid>>>, // Reverse Map. Vec<...> for tracking duplicates in a queue
-    pub order: imbl::Vector<DoublyIdx<Uuid>>,        // indices order view
+    pub name: String,
+    list: DoublyListLazy<Uuid>,    // main queue traversal/mutation. O(1)
+    curr: Option<DoublyIdx<Uuid>>, //
+    position: Option<usize>,       // index of `curr` in `order`
+    tracks: HashMap<Uuid, Vec<DoublyIdx<Uuid>>>, // Reverse Map. Vec<...> for tracking duplicates in a queue
+    order: RadixLinkTree<QueueEntry>,            // indices order view. O(log n) random access
+    history: QueueHistory,
 }
 
 impl Queue {
-    #[allow(clippy::new_without_default)]
-    pub fn new(library: &FxIndexMap<Uuid, Arc<Track>>) -> Self {
+    pub fn new(name: impl Into<String>, library: &FxIndexMap<Uuid, Arc<Track>>) -> Self {
+        // println!("library = {:#?}", library);
+
+        Self::from_tracks(name, library.keys().copied())
+    }
+
+    pub fn from_tracks(
+        name: impl Into<String>,
+        track_uuids: impl IntoIterator<Item = Uuid>,
+    ) -> Self {
+        let mut queue = Self {
+            name: name.into(),
+            list: DoublyListLazy::new(),
+            curr: None,
+            position: None,
+            tracks: HashMap::new(),
+            order: RadixLinkTree::new(),
+            history: QueueHistory::new(DEFAULT_HISTORY_DEPTH),
+        };
+        queue.rebuild(track_uuids);
+
+        queue
+    }
+
+    /// Rebuilds `list`, `tracks` and `order` from scratch and puts the cursor on the
+    /// first track.
+    fn rebuild(&mut self, track_uuids: impl IntoIterator<Item = Uuid>) {
         let mut list = DoublyListLazy::new();
         let mut tracks = HashMap::new();
-        let mut order = imbl::Vector::new();
-
-        // println!("library = {:#?}", library);
+        let mut order = RadixLinkTree::new();
 
-        for track_uuid in library.keys().copied() {
+        for track_uuid in track_uuids {
             let idx = list.push_back(track_uuid);
             tracks
                 .entry(track_uuid)
                 .and_modify(|idxs: &mut Vec<_>| idxs.push(idx))
                 .or_insert_with(|| vec![idx]);
-            order.push_back(idx);
+            order.push(QueueEntry {
+                idx,
+                id: track_uuid,
+            });
         }
 
-        let curr = list.indices().next();
+        self.list = list;
+        self.tracks = tracks;
+        self.order = order;
+        self.set_position(Some(0));
+    }
 
-        Self {
-            list,
-            curr,
-            tracks,
-            order,
+    /// Moves the cursor to `position`, or clears it if that's out of bounds.
+    fn set_position(&mut self, position: Option<usize>) {
+        let entry = position.and_then(|position| self.order.get(position));
+
+        self.curr = entry.map(|entry| entry.idx);
+        self.position = entry.and(position);
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn current_position(&self) -> Option<usize> {
+        self.position
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            order: self.order.clone(),
+            curr: self.position,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: QueueSnapshot) {
+        self.rebuild(snapshot.order.iter().map(|entry| entry.id));
+        if snapshot.curr.is_some_and(|pos| pos < self.order.len()) {
+            self.set_position(snapshot.curr);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    #[cfg(test)]
+    fn set_history_depth(&mut self, depth: usize) {
+        self.history.depth = depth;
+        while self.history.undo.len() > depth {
+            self.history.undo.pop_front();
+        }
+    }
+
+    fn record(&mut self) {
+        let snapshot = self.snapshot();
+        self.history.record(snapshot);
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.history.undo.pop_back() else {
+            return false;
+        };
+
+        self.history.redo.push(self.snapshot());
+        self.restore(snapshot);
+
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some(snapshot) = self.history.redo.pop() else {
+            return false;
+        };
+
+        self.history.undo.push_back(self.snapshot());
+        self.restore(snapshot);
+
+        true
+    }
+
+    /// Replaces the contents, keeping the name. The cursor is placed on the first
+    /// occurrence of `start`, or the first track if `start` isn't part of `track_uuids`.
+    pub fn replace(&mut self, track_uuids: impl IntoIterator<Item = Uuid>, start: Option<Uuid>) {
+        self.record();
+        self.rebuild(track_uuids);
+
+        if let Some(start) = start
+            && let Some(position) = self.order.iter().position(|entry| entry.id == start)
+        {
+            self.set_position(Some(position));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.order.is_empty() {
+            return;
         }
+
+        self.record();
+        self.rebuild([]);
     }
 
     pub fn curr(&mut self) -> Option<Uuid> {
@@ -53,33 +265,158 @@ impl Queue {
             return None;
         }
 
-        self.curr = self.list.indices().next();
+        self.set_position(Some(0));
 
         Some(self.list[self.curr.expect("Track Cursor shouldn't be None")])
     }
 
     pub fn shuffle(&mut self) {
+        self.record();
+
         let mut rng = rand::rng();
         // queue.shuffle(&mut rng);
 
+        let mut position = self.position;
+
         for i in (0..self.order.len()).rev() {
             let j = rng.random_range(0..=i);
 
             if i != j {
                 self.order.swap(i, j);
-                self.list.swap(self.order[i], self.order[j]);
+                self.list.swap(self.order[i].idx, self.order[j].idx);
+
+                // The cursor stays on the same node, which just changed places.
+                if position == Some(i) {
+                    position = Some(j);
+                } else if position == Some(j) {
+                    position = Some(i);
+                }
             }
         }
+
+        self.position = position;
     }
 
     pub fn track_at(&self, index: usize) -> Option<Uuid> {
-        let &idx = self.order.get(index)?;
+        self.order.get(index).map(|entry| entry.id)
+    }
+
+    /// The tracks at the positions in `range`, clamped to the queue's bounds.
+    /// `O(log n)` to find the window, plus its length.
+    pub fn tracks_in(&self, range: impl RangeBounds<usize>) -> impl Iterator<Item = Uuid> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => usize::MAX,
+        };
+        let end = end.min(self.order.len());
+        let start = start.min(end);
 
-        self.list.get(idx).copied()
+        self.order.range(start..end).map(|entry| entry.id)
     }
 
+    /// Moves the cursor to `position` and returns the track there.
+    pub fn jump_to(&mut self, position: usize) -> Option<Uuid> {
+        let entry = *self.order.get(position)?;
+        self.set_position(Some(position));
+
+        Some(entry.id)
+    }
+
+    /// Removes every occurrence of `uuid`. If the cursor was on one of them it moves
+    /// to the following track, or the preceding one at the end of the queue.
     pub fn remove_by_uuid(&mut self, uuid: &Uuid) -> Option<Vec<DoublyIdx<Uuid>>> {
-        self.tracks.remove(uuid)
+        if !self.tracks.contains_key(uuid) {
+            return None;
+        }
+
+        self.record();
+        self.remove_entries(uuid)
+    }
+
+    /// Removes tracks that left the library. This isn't an edit that can be undone,
+    /// and the tracks are dropped from the undo and redo history as well, so undoing
+    /// an earlier edit doesn't bring them back.
+    pub fn remove_missing(&mut self, uuids: &HashSet<Uuid>) {
+        if uuids.iter().any(|uuid| self.tracks.contains_key(uuid)) {
+            let position = self.position.map(|position| {
+                let removed_before = self
+                    .order
+                    .range(..position)
+                    .filter(|entry| uuids.contains(&entry.id))
+                    .count();
+
+                let on_removed = uuids.contains(&self.order[position].id);
+                let following = self
+                    .order
+                    .range(position..)
+                    .any(|entry| !uuids.contains(&entry.id));
+
+                if on_removed && !following {
+                    (position - removed_before).checked_sub(1)
+                } else {
+                    Some(position - removed_before)
+                }
+            });
+
+            for uuid in uuids {
+                for idx in self.tracks.remove(uuid).into_iter().flatten() {
+                    self.list.remove(idx);
+                }
+            }
+            self.order.retain(|entry| !uuids.contains(&entry.id));
+
+            self.set_position(position.flatten());
+        }
+
+        for snapshot in self
+            .history
+            .undo
+            .iter_mut()
+            .chain(self.history.redo.iter_mut())
+        {
+            snapshot.remove_all(uuids);
+        }
+    }
+
+    fn remove_entries(&mut self, uuid: &Uuid) -> Option<Vec<DoublyIdx<Uuid>>> {
+        let position = self.position.map(|position| {
+            let removed_before = self
+                .order
+                .iter()
+                .take(position)
+                .filter(|entry| entry.id == *uuid)
+                .count();
+
+            let on_removed = self.order[position].id == *uuid;
+            let following = self
+                .order
+                .iter()
+                .skip(position)
+                .any(|entry| entry.id != *uuid);
+
+            if on_removed && !following {
+                // Fall back to the closest preceding track, if there's one left.
+                (position - removed_before).checked_sub(1)
+            } else {
+                Some(position - removed_before)
+            }
+        });
+
+        let idxs = self.tracks.remove(uuid)?;
+        for &idx in &idxs {
+            self.list.remove(idx);
+        }
+        self.order.retain(|entry| entry.id != *uuid);
+
+        self.set_position(position.flatten());
+
+        Some(idxs)
     }
 
     pub fn next(&mut self) -> Option<Uuid> {
@@ -91,20 +428,429 @@ impl Queue {
             && let Some(next) = self.list.next_idx_of(curr)
         {
             self.curr = Some(next);
+            self.position = self.position.map(|position| position + 1);
+            debug_assert_eq!(
+                self.position.and_then(|position| self.order.get(position)),
+                Some(&QueueEntry {
+                    idx: next,
+                    id: self.list[next],
+                })
+            );
+
             Some(self.list[next])
         } else {
-            self.curr = self.list.indices().next();
+            self.set_position(Some(0));
             Some(self.list[self.curr.expect("no no")])
         }
     }
 
-    pub fn peek_next(&self) -> Option<Uuid> {
-        if let Some(curr) = self.curr
-            && let Some(next_idx) = self.list.next_idx_of(curr)
-        {
-            Some(self.list[next_idx])
-        } else {
-            None
+    /// Like [`Self::next`], passing over the tracks `is_available` rejects. The cursor
+    /// stays where it is if none of them can be played.
+    pub fn next_available(&mut self, is_available: impl Fn(&Uuid) -> bool) -> Option<Uuid> {
+        let position = self.position;
+
+        for _ in 0..self.order.len() {
+            let id = self.next()?;
+            if is_available(&id) {
+                return Some(id);
+            }
+        }
+
+        self.set_position(position);
+        None
+    }
+
+    /// The first track after the cursor that `is_available` accepts.
+    pub fn peek_next_available(&self, is_available: impl Fn(&Uuid) -> bool) -> Option<Uuid> {
+        let position = self.position?;
+        self.tracks_in(position + 1..).find(|id| is_available(id))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueInfo {
+    pub id: QueueId,
+    pub name: String,
+    pub len: usize,
+    pub curr: Option<Uuid>,
+    pub position: Option<usize>,
+    pub active: bool,
+    pub can_undo: bool,
+    pub can_redo: bool,
+}
+
+/// A slice of the active queue, for virtualized list UIs.
+#[derive(Debug, Clone)]
+pub struct QueueWindow {
+    pub len: usize,
+    pub position: Option<usize>,
+    pub start: usize,
+    pub tracks: Vec<Uuid>,
+    /// Whether each of `tracks` can be played, see [`crate::Track::available`].
+    pub available: Vec<bool>,
+}
+
+/// Named queues, exactly one of which is driving playback.
+///
+/// Every `Queue` keeps its own cursor, so switching away and back resumes at the
+/// track it was left on.
+pub struct Queues {
+    queues: SlotMap<QueueId, Queue>,
+    active: QueueId,
+}
+
+impl Queues {
+    pub fn new(queue: Queue) -> Self {
+        let mut queues = SlotMap::with_key();
+        let active = queues.insert(queue);
+
+        Self { queues, active }
+    }
+
+    pub fn active_id(&self) -> QueueId {
+        self.active
+    }
+
+    pub fn active(&self) -> &Queue {
+        &self.queues[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Queue {
+        &mut self.queues[self.active]
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (QueueId, &mut Queue)> {
+        self.queues.iter_mut()
+    }
+
+    pub fn insert(&mut self, queue: Queue) -> QueueId {
+        self.queues.insert(queue)
+    }
+
+    pub fn switch(&mut self, id: QueueId) -> anyhow::Result<()> {
+        anyhow::ensure!(self.queues.contains_key(id), "Unknown queue: {:?}", id);
+
+        self.active = id;
+
+        Ok(())
+    }
+
+    pub fn rename(&mut self, id: QueueId, name: String) -> anyhow::Result<()> {
+        let queue = self
+            .queues
+            .get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown queue: {:?}", id))?;
+        queue.name = name;
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: QueueId) -> anyhow::Result<Queue> {
+        anyhow::ensure!(id != self.active, "Unable to remove the active queue");
+
+        self.queues
+            .remove(id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown queue: {:?}", id))
+    }
+
+    pub fn window(&self, range: Range<usize>, is_available: impl Fn(&Uuid) -> bool) -> QueueWindow {
+        let queue = self.active();
+        let tracks = queue.tracks_in(range.clone()).collect::<Vec<_>>();
+
+        QueueWindow {
+            len: queue.len(),
+            position: queue.current_position(),
+            start: range.start.min(queue.len()),
+            available: tracks.iter().map(is_available).collect(),
+            tracks,
         }
     }
+
+    pub fn infos(&self) -> Vec<QueueInfo> {
+        self.queues
+            .iter()
+            .map(|(id, queue)| QueueInfo {
+                id,
+                name: queue.name.clone(),
+                len: queue.len(),
+                curr: queue.current_position().and_then(|pos| queue.track_at(pos)),
+                position: queue.current_position(),
+                active: id == self.active,
+                can_undo: queue.can_undo(),
+                can_redo: queue.can_redo(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuids(len: usize) -> Vec<Uuid> {
+        (0..len).map(|_| Uuid::new_v4()).collect()
+    }
+
+    fn contents(queue: &Queue) -> Vec<Uuid> {
+        queue.tracks_in(..).collect()
+    }
+
+    /// `order[i].idx` is the `i`th node of `list`, and `position` is where `curr` is.
+    fn assert_in_step(queue: &Queue) {
+        let mut idx = queue.order.first().map(|entry| entry.idx);
+        for entry in &queue.order {
+            assert_eq!(idx, Some(entry.idx));
+            assert_eq!(queue.list.get(entry.idx), Some(&entry.id));
+            idx = queue.list.next_idx_of(entry.idx);
+        }
+        assert_eq!(idx, None);
+
+        assert_eq!(
+            queue.curr,
+            queue.position.map(|position| queue.order[position].idx)
+        );
+    }
+
+    #[test]
+    fn undo_history_drops_the_oldest_edits_past_its_depth() {
+        let [a, b, c, d] = [uuids(2), uuids(3), uuids(4), uuids(5)];
+        let mut queue = Queue::from_tracks("queue", a.clone());
+        queue.set_history_depth(2);
+
+        queue.replace(b.clone(), None);
+        queue.replace(c.clone(), None);
+        queue.replace(d.clone(), None);
+
+        assert!(queue.undo());
+        assert_eq!(contents(&queue), c);
+        assert!(queue.undo());
+        assert_eq!(contents(&queue), b);
+        assert!(!queue.undo());
+        assert_eq!(contents(&queue), b);
+
+        queue.set_history_depth(0);
+        queue.clear();
+        assert!(!queue.can_undo());
+    }
+
+    #[test]
+    fn lowering_the_depth_drops_the_oldest_edits() {
+        let [a, b, c] = [uuids(2), uuids(3), uuids(4)];
+        let mut queue = Queue::from_tracks("queue", a);
+
+        queue.replace(b.clone(), None);
+        queue.replace(c, None);
+        queue.set_history_depth(1);
+
+        assert!(queue.undo());
+        assert_eq!(contents(&queue), b);
+        assert!(!queue.undo());
+    }
+
+    #[test]
+    fn editing_after_undo_clears_redo() {
+        let [a, b, c] = [uuids(2), uuids(3), uuids(4)];
+        let mut queue = Queue::from_tracks("queue", a.clone());
+
+        queue.replace(b.clone(), None);
+        assert!(queue.undo());
+        assert!(queue.can_redo());
+        assert!(queue.redo());
+        assert_eq!(contents(&queue), b);
+
+        assert!(queue.undo());
+        queue.replace(c.clone(), None);
+        assert!(!queue.can_redo());
+        assert!(!queue.redo());
+        assert_eq!(contents(&queue), c);
+
+        assert!(queue.undo());
+        assert_eq!(contents(&queue), a);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_cursor() {
+        let [a, b] = [uuids(5), uuids(3)];
+        let mut queue = Queue::from_tracks("queue", a.clone());
+        queue.jump_to(3);
+
+        queue.replace(b.clone(), Some(b[1]));
+        assert_eq!(queue.current_position(), Some(1));
+
+        assert!(queue.undo());
+        assert_eq!(queue.current_position(), Some(3));
+        assert_eq!(queue.curr(), Some(a[3]));
+        assert_in_step(&queue);
+
+        assert!(queue.redo());
+        assert_eq!(queue.current_position(), Some(1));
+        assert_eq!(queue.curr(), Some(b[1]));
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn shuffle_keeps_the_cursor_on_its_track() {
+        let tracks = uuids(200);
+        let mut queue = Queue::from_tracks("queue", tracks.clone());
+        queue.jump_to(42);
+
+        for _ in 0..8 {
+            queue.shuffle();
+            assert_in_step(&queue);
+
+            let position = queue.current_position().expect("cursor was set");
+            assert_eq!(queue.curr(), Some(tracks[42]));
+            assert_eq!(queue.track_at(position), Some(tracks[42]));
+        }
+
+        let mut shuffled = contents(&queue);
+        shuffled.sort();
+        let mut sorted = tracks;
+        sorted.sort();
+        assert_eq!(shuffled, sorted);
+
+        queue.jump_to(0);
+        assert_eq!(queue.peek_next_available(|_| true), queue.track_at(1));
+        assert_eq!(queue.next(), queue.track_at(1));
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn tracks_in_clamps_to_the_queue() {
+        let tracks = uuids(10);
+        let queue = Queue::from_tracks("queue", tracks.clone());
+
+        assert_eq!(queue.tracks_in(2..5).collect::<Vec<_>>(), tracks[2..5]);
+        assert_eq!(queue.tracks_in(..=1).collect::<Vec<_>>(), tracks[..=1]);
+        assert_eq!(queue.tracks_in(8..100).collect::<Vec<_>>(), tracks[8..]);
+        assert_eq!(queue.tracks_in(20..).count(), 0);
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn tracks_in_a_reversed_range_is_empty() {
+        let queue = Queue::from_tracks("queue", uuids(10));
+
+        assert_eq!(queue.tracks_in(6..3).count(), 0);
+    }
+
+    #[test]
+    fn jump_to_moves_the_cursor() {
+        let tracks = uuids(10);
+        let mut queue = Queue::from_tracks("queue", tracks.clone());
+
+        assert_eq!(queue.jump_to(4), Some(tracks[4]));
+        assert_eq!(queue.current_position(), Some(4));
+        assert_eq!(queue.next(), Some(tracks[5]));
+        assert_eq!(queue.current_position(), Some(5));
+        assert_in_step(&queue);
+
+        assert_eq!(queue.jump_to(10), None);
+        assert_eq!(queue.current_position(), Some(5));
+
+        assert_eq!(queue.jump_to(9), Some(tracks[9]));
+        assert_eq!(queue.peek_next_available(|_| true), None);
+        assert_eq!(queue.next(), Some(tracks[0]));
+        assert_eq!(queue.current_position(), Some(0));
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn next_available_passes_over_unavailable_tracks() {
+        let tracks = uuids(5);
+        let mut queue = Queue::from_tracks("queue", tracks.clone());
+        let offline = [tracks[1], tracks[2], tracks[4]];
+        let is_available = |id: &Uuid| !offline.contains(id);
+
+        assert_eq!(queue.peek_next_available(is_available), Some(tracks[3]));
+        assert_eq!(queue.next_available(is_available), Some(tracks[3]));
+        assert_eq!(queue.current_position(), Some(3));
+        assert_eq!(queue.peek_next_available(is_available), None);
+        assert_eq!(queue.next_available(is_available), Some(tracks[0]));
+        assert_in_step(&queue);
+
+        assert_eq!(queue.next_available(|_| false), None);
+        assert_eq!(queue.current_position(), Some(0));
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn replace_starts_at_the_requested_track() {
+        let tracks = uuids(6);
+        let mut queue = Queue::from_tracks("queue", []);
+        assert_eq!(queue.current_position(), None);
+        assert_eq!(queue.curr(), None);
+
+        queue.replace(tracks.clone(), Some(tracks[4]));
+        assert_eq!(queue.current_position(), Some(4));
+        assert_in_step(&queue);
+
+        queue.replace(tracks.clone(), Some(Uuid::new_v4()));
+        assert_eq!(queue.current_position(), Some(0));
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn removing_tracks_keeps_the_cursor_in_place() {
+        let [a, b, c, d] = [0, 1, 2, 3].map(|_| Uuid::new_v4());
+
+        // Every occurrence goes, the cursor moves to the following track.
+        let mut queue = Queue::from_tracks("queue", [a, b, c, b, d]);
+        queue.jump_to(3);
+        assert_eq!(queue.remove_by_uuid(&b).map(|idxs| idxs.len()), Some(2));
+        assert_eq!(contents(&queue), [a, c, d]);
+        assert_eq!(queue.current_position(), Some(2));
+        assert_eq!(queue.curr(), Some(d));
+        assert_in_step(&queue);
+
+        // Before and after the cursor.
+        let mut queue = Queue::from_tracks("queue", [a, b, c, d]);
+        queue.jump_to(2);
+        queue.remove_by_uuid(&d);
+        assert_eq!(queue.curr(), Some(c));
+        queue.remove_by_uuid(&a);
+        assert_eq!(queue.current_position(), Some(1));
+        assert_eq!(queue.curr(), Some(c));
+        assert_in_step(&queue);
+
+        // At the end of the queue, back to the preceding track.
+        queue.remove_by_uuid(&c);
+        assert_eq!(queue.current_position(), Some(0));
+        assert_eq!(queue.curr(), Some(b));
+        assert_in_step(&queue);
+
+        queue.remove_by_uuid(&b);
+        assert_eq!(queue.current_position(), None);
+        assert_eq!(queue.curr(), None);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.remove_by_uuid(&b), None);
+        assert_in_step(&queue);
+    }
+
+    #[test]
+    fn missing_tracks_are_removed_from_the_history_too() {
+        let [a, b, c, d] = [0, 1, 2, 3].map(|_| Uuid::new_v4());
+        let mut queue = Queue::from_tracks("queue", [a, b, c]);
+        queue.jump_to(2);
+
+        queue.replace([b, c, d], Some(d));
+        queue.replace([d, a], None);
+        assert!(queue.undo());
+
+        queue.remove_missing(&HashSet::from([c]));
+        assert_eq!(contents(&queue), [b, d]);
+        assert_eq!(queue.curr(), Some(d));
+        assert!(queue.can_undo());
+        assert!(queue.can_redo());
+
+        assert!(queue.undo());
+        assert_eq!(contents(&queue), [a, b]);
+        assert_eq!(queue.curr(), Some(b));
+        assert_in_step(&queue);
+
+        assert!(!queue.undo());
+        assert!(queue.redo());
+        assert!(queue.redo());
+        assert_eq!(contents(&queue), [d, a]);
+    }
 }
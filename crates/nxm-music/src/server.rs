@@ -1,4 +1,4 @@
-use std::{num::NonZeroUsize, sync::Arc};
+use std::{collections::HashSet, num::NonZeroUsize, ops::Range, sync::Arc};
 
 use collections::FxIndexMap;
 use creek::{ReadDiskStream, SymphoniaDecoder};
@@ -6,9 +6,9 @@ use crossbeam_channel::Receiver;
 use uuid::Uuid;
 
 use crate::{
-    FetchLibraryRes, MainIoMsg, Track,
+    FetchLibraryRes, IoMainMsg, LibraryDelta, MainIoMsg, Track,
     audio_handle::{AudioOutput, AudioProcessor, StreamMainMsg},
-    queue::Queue,
+    queue::{Queue, QueueId, QueueInfo, QueueWindow, Queues},
 };
 
 pub enum MainPreloaderMsg {
@@ -29,13 +29,49 @@ pub enum TrackMsg {
 pub enum ControllerMsg {
     PlayPause,
     PlayNext,
+    #[allow(dead_code)]
     PlayPrev,
+    #[allow(dead_code)]
     Seek(u64),
 }
 
+pub enum QueueMsg {
+    Create {
+        name: String,
+        reply: flume::Sender<QueueId>,
+    },
+    Switch(QueueId),
+    Rename {
+        id: QueueId,
+        name: String,
+    },
+    Remove(QueueId),
+    /// Replaces the active queue and starts playing at `start`.
+    Replace {
+        tracks: Vec<Uuid>,
+        start: Option<Uuid>,
+    },
+    RemoveTrack(Uuid),
+    Shuffle,
+    Clear,
+    Undo,
+    Redo,
+    /// Starts playing the track at this position of the active queue.
+    PlayPosition(usize),
+    List {
+        reply: flume::Sender<Vec<QueueInfo>>,
+    },
+    Window {
+        range: Range<usize>,
+        reply: flume::Sender<QueueWindow>,
+    },
+}
+
 pub enum UserMainMsg {
+    #[allow(dead_code)]
     Track(TrackMsg),
     Controller(ControllerMsg),
+    Queue(QueueMsg),
 }
 
 pub enum MainStreamMsg {
@@ -43,6 +79,7 @@ pub enum MainStreamMsg {
     Seek(u64),
 }
 
+#[allow(dead_code)]
 pub enum Event {}
 
 #[derive(Debug, PartialEq, Eq)]
@@ -60,7 +97,7 @@ pub struct Preloader {
 
 pub struct State {
     audio_output: AudioOutput,
-    queue: Queue,
+    queues: Queues,
     library: FxIndexMap<Uuid, Arc<Track>>,
     preloader: Preloader,
 }
@@ -78,17 +115,95 @@ impl State {
                 Ok(_) => {}
                 Err(e) => log::error!("handle_controller_msg error: {:#?}", e),
             },
+            UserMainMsg::Queue(msg) => match self.handle_queue_msg(msg) {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_queue_msg error: {:#?}", e),
+            },
         }
 
         Ok(())
     }
 
+    pub fn handle_io_main_msg(&mut self, msg: IoMainMsg) -> anyhow::Result<()> {
+        match msg {
+            IoMainMsg::LibraryDelta(delta) => match self.handle_library_delta(delta) {
+                Ok(_) => {}
+                Err(e) => log::error!("handle_library_delta error: {:#?}", e),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn handle_library_delta(&mut self, delta: LibraryDelta) -> anyhow::Result<()> {
+        log::info!(
+            "library delta: {} upserted, {} removed",
+            delta.upserted.len(),
+            delta.removed.len()
+        );
+
+        for track in delta.upserted {
+            self.library.insert(track.id, track);
+        }
+
+        if delta.removed.is_empty() {
+            return Ok(());
+        }
+
+        let removed = delta.removed.into_iter().collect::<HashSet<_>>();
+        self.library.retain(|id, _| !removed.contains(id));
+
+        // Not recorded as queue edits, undoing one can't bring the tracks back.
+        let active_id = self.queues.active_id();
+        for (_, queue) in self
+            .queues
+            .iter_mut()
+            .filter(|(queue_id, _)| *queue_id != active_id)
+        {
+            queue.remove_missing(&removed);
+        }
+
+        // The active queue may lose the playing or preloaded track.
+        self.handle_edit_queue(|queue| queue.remove_missing(&removed))
+    }
+
+    fn track_src(&self, id: Uuid) -> anyhow::Result<String> {
+        let track = self
+            .library
+            .get(&id)
+            .ok_or_else(|| anyhow::anyhow!("Track {} isn't in the library", id))?;
+        anyhow::ensure!(track.available, "Track {} is in an offline library", id);
+
+        Ok(track.filepath.clone())
+    }
+
+    /// Asks the preloader for `id`. Tracks that can't be played right now, such as
+    /// those of an offline library, are logged and left alone, returning false.
+    fn preload(&self, id: Uuid) -> anyhow::Result<bool> {
+        let src = match self.track_src(id) {
+            Ok(src) => src,
+            Err(e) => {
+                log::warn!("Not preloading {}: {:#}", id, e);
+                return Ok(false);
+            }
+        };
+
+        self.preloader
+            .tx
+            .try_send(MainPreloaderMsg::PreloadTrack { id, src })
+            .map_err(|_| anyhow::anyhow!("Unable to send MainPreloaderMsg::PreloadTrack"))?;
+
+        Ok(true)
+    }
+
+    #[allow(dead_code)]
     pub fn handle_pause(&mut self) -> anyhow::Result<()> {
         self.audio_output.pause_stream()?;
 
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn handle_resume(&mut self) -> anyhow::Result<()> {
         Ok(())
     }
@@ -134,16 +249,9 @@ impl State {
     fn handle_track_msg(&mut self, msg: TrackMsg) -> anyhow::Result<()> {
         match msg {
             TrackMsg::Play(id) => {
-                if !matches!(self.preloader.curr, TrackPreloaderState::Preloaded(..)) {
-                    self.preloader
-                        .tx
-                        .try_send(MainPreloaderMsg::PreloadTrack {
-                            id,
-                            src: self.library[&id].filepath.clone(),
-                        })
-                        .map_err(|_| {
-                            anyhow::anyhow!("Unable to send MainPreloaderMsg::PreloadCurr(..)")
-                        })?;
+                if !matches!(self.preloader.curr, TrackPreloaderState::Preloaded(..))
+                    && self.preload(id)?
+                {
                     self.preloader.curr = TrackPreloaderState::Preloading(id);
                 }
             }
@@ -168,20 +276,129 @@ impl State {
         Ok(())
     }
 
+    fn handle_queue_msg(&mut self, msg: QueueMsg) -> anyhow::Result<()> {
+        match msg {
+            QueueMsg::Create { name, reply } => {
+                let id = self.queues.insert(Queue::from_tracks(name, []));
+
+                reply
+                    .try_send(id)
+                    .map_err(|_| anyhow::anyhow!("Unable to reply to QueueMsg::Create"))?;
+            }
+            QueueMsg::Switch(id) => self.handle_switch_queue(id)?,
+            QueueMsg::Rename { id, name } => self.queues.rename(id, name)?,
+            QueueMsg::Remove(id) => {
+                self.queues.remove(id)?;
+            }
+            QueueMsg::Replace { tracks, start } => self.handle_replace_queue(tracks, start)?,
+            QueueMsg::RemoveTrack(id) => self.handle_edit_queue(|queue| {
+                queue.remove_by_uuid(&id);
+            })?,
+            QueueMsg::Shuffle => self.handle_edit_queue(Queue::shuffle)?,
+            QueueMsg::Clear => self.handle_edit_queue(Queue::clear)?,
+            QueueMsg::Undo => self.handle_edit_queue(|queue| {
+                queue.undo();
+            })?,
+            QueueMsg::Redo => self.handle_edit_queue(|queue| {
+                queue.redo();
+            })?,
+            QueueMsg::PlayPosition(position) => {
+                self.queues
+                    .active_mut()
+                    .jump_to(position)
+                    .ok_or_else(|| anyhow::anyhow!("Queue position out of bounds: {}", position))?;
+                self.reset_playback(true)?;
+            }
+            QueueMsg::List { reply } => {
+                reply
+                    .try_send(self.queues.infos())
+                    .map_err(|_| anyhow::anyhow!("Unable to reply to QueueMsg::List"))?;
+            }
+            QueueMsg::Window { range, reply } => {
+                reply
+                    .try_send(self.queues.window(range, |id| {
+                        self.library.get(id).is_some_and(|track| track.available)
+                    }))
+                    .map_err(|_| anyhow::anyhow!("Unable to reply to QueueMsg::Window"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_switch_queue(&mut self, id: QueueId) -> anyhow::Result<()> {
+        if self.queues.active_id() == id {
+            return Ok(());
+        }
+
+        self.queues.switch(id)?;
+
+        let was_playing = self.audio_output.is_playing();
+        self.reset_playback(was_playing)
+    }
+
+    fn handle_replace_queue(
+        &mut self,
+        tracks: Vec<Uuid>,
+        start: Option<Uuid>,
+    ) -> anyhow::Result<()> {
+        self.queues.active_mut().replace(
+            tracks
+                .into_iter()
+                .filter(|id| self.library.contains_key(id)),
+            start,
+        );
+
+        self.reset_playback(true)
+    }
+
+    /// Applies `edit` to the active queue. Playback restarts if the cursor moved, and
+    /// the preloaded next track is dropped if the upcoming track changed.
+    fn handle_edit_queue(&mut self, edit: impl FnOnce(&mut Queue)) -> anyhow::Result<()> {
+        let is_available = |id: &Uuid| self.library.get(id).is_some_and(|track| track.available);
+        let queue = self.queues.active_mut();
+        let prev_curr = queue.curr();
+        let prev_next = queue.peek_next_available(is_available);
+
+        edit(queue);
+
+        let curr = queue.curr();
+        let next = queue.peek_next_available(is_available);
+
+        if curr != prev_curr {
+            let was_playing = self.audio_output.is_playing();
+            self.reset_playback(was_playing)?;
+        } else if next != prev_next {
+            self.preloader.next = TrackPreloaderState::NotPreloaded;
+        }
+
+        Ok(())
+    }
+
+    /// Drops whatever was preloaded for the previous cursor, optionally starting
+    /// playback of the active queue's current track.
+    fn reset_playback(&mut self, play: bool) -> anyhow::Result<()> {
+        // Late `PreloadedTrack` replies for the old tracks are ignored since neither
+        // slot is `Preloading` them anymore.
+        self.audio_output.pause_stream()?;
+        self.preloader.curr = TrackPreloaderState::NotPreloaded;
+        self.preloader.next = TrackPreloaderState::NotPreloaded;
+
+        if play {
+            self.handle_play_pause()?;
+        }
+
+        Ok(())
+    }
+
     fn handle_play_pause(&mut self) -> anyhow::Result<()> {
         log::info!("handle_play_pause");
 
-        if let Some(id) = self.queue.curr()
+        if let Some(id) = self.queues.active_mut().curr()
             && (matches!(self.preloader.curr, TrackPreloaderState::NotPreloaded)
                 || matches!(self.preloader.curr, TrackPreloaderState::Preloading(curr_preloading_id) if curr_preloading_id != id))
+            && self.preload(id)?
         {
-            self.preloader
-                .tx
-                .try_send(MainPreloaderMsg::PreloadTrack {
-                    id,
-                    src: self.library[&id].filepath.clone(),
-                })
-                .map_err(|_| anyhow::anyhow!("Unable to send MainPreloaderMsg::PreloadCurr(..)"))?;
             self.preloader.curr = TrackPreloaderState::Preloading(id);
         }
 
@@ -189,7 +406,12 @@ impl State {
     }
 
     fn handle_play_next(&mut self) -> anyhow::Result<()> {
-        if let Some(id) = self.queue.next() {
+        // Tracks of an offline library are passed over rather than stopped at.
+        if let Some(id) = self
+            .queues
+            .active_mut()
+            .next_available(|id| self.library.get(id).is_some_and(|track| track.available))
+        {
             if let TrackPreloaderState::Preloading(next_preloading_id) = self.preloader.next {
                 if id == next_preloading_id {
                     self.preloader.curr = TrackPreloaderState::Preloading(next_preloading_id);
@@ -250,23 +472,15 @@ impl State {
 
                             println!("started stream: {:#?}", preloaded_id);
 
-                            if let Some(next) = self.queue.peek_next() {
-                                self.preloader
-                                    .tx
-                                    .try_send(MainPreloaderMsg::PreloadTrack {
-                                        id: next,
-                                        src: self.library[&next].filepath.clone(),
-                                    })
-                                    .map_err(|_| {
-                                        anyhow::anyhow!(
-                                            "Unable to send MainPreloaderMsg::PreloadNext"
-                                        )
-                                    })?;
+                            if let Some(next) = self.queues.active().peek_next_available(|id| {
+                                self.library.get(id).is_some_and(|track| track.available)
+                            }) && self.preload(next)?
+                            {
                                 self.preloader.next = TrackPreloaderState::Preloading(next);
                             }
                         }
                     }
-                    TrackPreloaderState::Preloaded(curr_preloaded_id) => {}
+                    TrackPreloaderState::Preloaded(_) => {}
                 }
 
                 match self.preloader.next {
@@ -277,7 +491,7 @@ impl State {
                             log::info!("preloaded next: {:#?}", preloaded_id);
                         }
                     }
-                    TrackPreloaderState::Preloaded(next_preloaded_id) => {}
+                    TrackPreloaderState::Preloaded(_) => {}
                 }
             }
         }
@@ -289,11 +503,12 @@ impl State {
 pub fn main_thread(
     user_main_rx: crossbeam_channel::Receiver<UserMainMsg>,
     main_io_tx: flume::Sender<MainIoMsg>,
+    io_main_rx: crossbeam_channel::Receiver<IoMainMsg>,
 ) -> anyhow::Result<()> {
     let (main_preloader_tx, main_preloader_rx) = crossbeam_channel::unbounded();
     let (preloader_main_tx, preloader_main_rx) = crossbeam_channel::unbounded();
 
-    let preloader_join_handle = std::thread::spawn({
+    let _preloader_join_handle = std::thread::spawn({
         move || {
             preloader_thread(main_preloader_rx, preloader_main_tx)?;
             anyhow::Ok(())
@@ -305,15 +520,13 @@ pub fn main_thread(
         .try_send(MainIoMsg::FetchLibrary { reply: tx })
         .unwrap();
 
-    let FetchLibraryRes::Snapshot(library) = rx.recv().unwrap() else {
-        unreachable!()
-    };
+    let FetchLibraryRes::Snapshot(library) = rx.recv().unwrap();
 
     let (stream_main_tx, stream_main_rx) = crossbeam_channel::unbounded();
 
     let mut state = State {
         audio_output: AudioOutput::new(stream_main_tx)?,
-        queue: Queue::new(&library),
+        queues: Queues::new(Queue::new("Library", &library)),
         library,
         preloader: Preloader {
             curr: TrackPreloaderState::NotPreloaded,
@@ -352,6 +565,15 @@ pub fn main_thread(
                     }
                 }
             }
+
+            recv(io_main_rx) -> msg => {
+                match msg {
+                    Ok(msg) => state.handle_io_main_msg(msg)?,
+                    Err(e) => {
+                        log::error!("IoMainMsg: {:#?}", e);
+                    }
+                }
+            }
         }
     }
 }
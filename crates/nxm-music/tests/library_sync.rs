@@ -0,0 +1,1661 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use lofty::{
+    config::WriteOptions,
+    picture::{MimeType, Picture, PictureType},
+    tag::{ItemKey, Tag, TagExt as _, TagType},
+};
+use nxm_music::{
+    ArtworkCache, ArtworkOwner, DuplicateGroup, DuplicateKind, ExtensionFilter, LibraryPathError,
+    MIGRATOR, PersistenceState, PlayContext, QueryError, ScanCancelled, ScanControl, ScanEvent,
+    ScanJob, ScanProgress, ScanTarget, SyncOptions, TagEdit, TrackQuery,
+    types::{ArtistRole, ScanErrorKind, TagField},
+};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+/// A valid, untagged 16-bit mono WAV file with a few milliseconds of audio, which is
+/// different for every path.
+fn write_wav(path: &Path) {
+    const SAMPLE_RATE: u32 = 8000;
+    const NUM_SAMPLES: u32 = 80;
+
+    let data_len = NUM_SAMPLES * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // channels
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // block align
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    bytes.extend(
+        path.to_string_lossy()
+            .bytes()
+            .cycle()
+            .take(data_len as usize),
+    );
+
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, bytes).unwrap();
+}
+
+/// Writes an ID3v2 tag to the file at `path`.
+fn write_tag(path: &Path, tag: &[(ItemKey, &str)]) {
+    let mut id3 = Tag::new(TagType::Id3v2);
+    for (key, value) in tag {
+        id3.insert_text(key.clone(), value.to_string());
+    }
+    id3.save_to_path(path, WriteOptions::default()).unwrap();
+}
+
+/// A PNG of `width` by `height` pixels filled with `shade`.
+fn png(width: u32, height: u32, shade: u8) -> Vec<u8> {
+    let mut bytes = std::io::Cursor::new(vec![]);
+    image::RgbImage::from_pixel(width, height, image::Rgb([shade; 3]))
+        .write_to(&mut bytes, image::ImageFormat::Png)
+        .unwrap();
+    bytes.into_inner()
+}
+
+struct Fixture {
+    dir: TempDir,
+    state: PersistenceState,
+    db: sqlx::SqlitePool,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        Self::with_sync_options(SyncOptions::default()).await
+    }
+
+    async fn with_sync_options(sync_options: SyncOptions) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Music")).unwrap();
+
+        let db = sqlx::SqlitePool::connect_with(
+            sqlx::sqlite::SqliteConnectOptions::new()
+                .filename(dir.path().join("storage.db"))
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        MIGRATOR.run(&db).await.unwrap();
+
+        sqlx::query("INSERT INTO libraries (id, path) VALUES (?, ?)")
+            .bind(Uuid::new_v4())
+            .bind(dir.path().join("Music").to_str().unwrap())
+            .execute(&db)
+            .await
+            .unwrap();
+
+        Self {
+            state: PersistenceState::new(db.clone())
+                .with_sync_options(sync_options)
+                .with_artwork_cache(ArtworkCache::new(dir.path().join("artwork"))),
+            dir,
+            db,
+        }
+    }
+
+    fn music(&self) -> PathBuf {
+        self.dir.path().join("Music")
+    }
+
+    async fn count(&self, query: &'static str) -> i64 {
+        sqlx::query_scalar(query).fetch_one(&self.db).await.unwrap()
+    }
+
+    /// Track ids keyed by their path relative to the library root.
+    async fn tracks(&self) -> Vec<(String, Uuid)> {
+        let music = self.music();
+        let mut tracks = self
+            .state
+            .library_snapshot()
+            .await
+            .unwrap()
+            .into_values()
+            .map(|track| {
+                let path = Path::new(&track.filepath)
+                    .strip_prefix(&music)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string();
+                (path, track.id)
+            })
+            .collect::<Vec<_>>();
+        tracks.sort();
+        tracks
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fresh_library_is_populated() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("root.wav"));
+    write_wav(&fixture.music().join("Artist/Album/01.wav"));
+    write_wav(&fixture.music().join("Artist/Album/02.wav"));
+    write_wav(&fixture.music().join("Other/03.wav"));
+    fs::write(fixture.music().join("Artist/Album/notes.txt"), "not audio").unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+
+    let paths = fixture
+        .tracks()
+        .await
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            "Artist/Album/01.wav",
+            "Artist/Album/02.wav",
+            "Other/03.wav",
+            "root.wav"
+        ]
+    );
+
+    // Root, `Artist`, `Album`, `Other` and the four files.
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM filenodes").await, 8);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resync_is_idempotent() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("B/02.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    let before = fixture.tracks().await;
+
+    fixture.state.sync_libraries().await.unwrap();
+    let after = fixture.tracks().await;
+
+    assert_eq!(before, after);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM filenodes").await, 5);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn new_files_are_picked_up() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    let before = fixture.tracks().await;
+
+    write_wav(&fixture.music().join("A/02.wav"));
+    write_wav(&fixture.music().join("C/03.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+    let after = fixture.tracks().await;
+
+    assert_eq!(after.len(), 3);
+    assert!(after.contains(&before[0]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn moved_file_keeps_its_track() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    fs::create_dir(fixture.music().join("B")).unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+    assert_eq!(tracks.len(), 1);
+    let track_id = tracks[0].1;
+
+    fs::rename(
+        fixture.music().join("A/01.wav"),
+        fixture.music().join("B/01.wav"),
+    )
+    .unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(fixture.tracks().await, [("B/01.wav".to_string(), track_id)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deleted_files_and_directories_are_removed() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("A/Sub/02.wav"));
+    write_wav(&fixture.music().join("B/03.wav"));
+    write_wav(&fixture.music().join("B/04.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(fixture.tracks().await.len(), 4);
+
+    fs::remove_dir_all(fixture.music().join("A")).unwrap();
+    fs::remove_file(fixture.music().join("B/03.wav")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    let paths = fixture
+        .tracks()
+        .await
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["B/04.wav"]);
+
+    // Root, `B` and `B/04.wav`.
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM filenodes").await, 3);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn renamed_file_is_not_deleted() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    let track_id = fixture.tracks().await[0].1;
+
+    fs::rename(
+        fixture.music().join("A/01.wav"),
+        fixture.music().join("A/renamed.wav"),
+    )
+    .unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(
+        fixture.tracks().await,
+        [("A/renamed.wav".to_string(), track_id)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_tracks_can_be_kept() {
+    let fixture = Fixture::with_sync_options(SyncOptions {
+        keep_missing_tracks: true,
+    })
+    .await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("B/02.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+
+    fs::remove_dir_all(fixture.music().join("A")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(fixture.tracks().await.len(), 1);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 2);
+    assert_eq!(
+        fixture
+            .count("SELECT COUNT(*) FROM tracks WHERE filenode_id IS NULL")
+            .await,
+        1
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_paths_are_synced_into_a_delta() {
+    let mut fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("B/02.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(
+        fixture
+            .state
+            .refresh_snapshot()
+            .await
+            .unwrap()
+            .upserted
+            .len(),
+        2
+    );
+
+    // A new album in a known directory, and a file in a directory that isn't part of
+    // the rescan.
+    write_wav(&fixture.music().join("A/Album/03.wav"));
+    write_wav(&fixture.music().join("A/Album/04.wav"));
+    write_wav(&fixture.music().join("B/05.wav"));
+    fixture
+        .state
+        .sync_paths(vec![fixture.music().join("A/Album")])
+        .await
+        .unwrap();
+
+    let delta = fixture.state.refresh_snapshot().await.unwrap();
+    let mut paths = delta
+        .upserted
+        .iter()
+        .map(|track| track.filepath.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            fixture.music().join("A/Album/03.wav").to_str().unwrap(),
+            fixture.music().join("A/Album/04.wav").to_str().unwrap(),
+        ]
+    );
+    assert!(delta.removed.is_empty());
+
+    let removed_id = fixture.tracks().await[0].1;
+    fs::remove_file(fixture.music().join("A/01.wav")).unwrap();
+    fixture
+        .state
+        .sync_paths(vec![fixture.music().join("A/01.wav")])
+        .await
+        .unwrap();
+
+    let delta = fixture.state.refresh_snapshot().await.unwrap();
+    assert!(delta.upserted.is_empty());
+    assert_eq!(delta.removed, [removed_id]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn moved_file_keeps_its_track_when_syncing_paths() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/Sub/01.wav"));
+    fs::create_dir(fixture.music().join("B")).unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+    let track_id = fixture.tracks().await[0].1;
+
+    fs::rename(
+        fixture.music().join("A/Sub/01.wav"),
+        fixture.music().join("B/01.wav"),
+    )
+    .unwrap();
+    fixture
+        .state
+        .sync_paths(vec![
+            fixture.music().join("A/Sub/01.wav"),
+            fixture.music().join("B/01.wav"),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.tracks().await, [("B/01.wav".to_string(), track_id)]);
+    // Root, `A`, `A/Sub`, `B` and `B/01.wav`.
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM filenodes").await, 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tags_are_read_across_batches() {
+    let fixture = Fixture::new().await;
+    // More than a single tag read batch.
+    for i in 0..600 {
+        write_wav(&fixture.music().join(format!("{:02}/{i:03}.wav", i % 7)));
+    }
+
+    fixture.state.sync_libraries().await.unwrap();
+
+    let paths = fixture
+        .tracks()
+        .await
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    let mut expected = (0..600)
+        .map(|i| format!("{:02}/{i:03}.wav", i % 7))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(paths, expected);
+    assert_eq!(
+        fixture
+            .count("SELECT COUNT(*) FROM tracks WHERE artist IS NULL AND title IS NULL")
+            .await,
+        600
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scans_report_progress() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("A/02.wav"));
+    write_wav(&fixture.music().join("B/03.wav"));
+
+    let (job, control) = ScanJob::new();
+    let res = fixture.state.scan(ScanTarget::Libraries, &control).await;
+    control.finish(&res);
+
+    let expected = ScanProgress {
+        // The root, `A` and `B`.
+        dirs_visited: 3,
+        files_examined: 3,
+        tags_read: 3,
+        tags_total: 3,
+        // Every node is inserted.
+        changes: 6,
+        errors: 0,
+    };
+    assert_eq!(res.unwrap(), expected);
+
+    let events = job.events().drain().collect::<Vec<_>>();
+    assert!(matches!(events.last(), Some(ScanEvent::Finished(progress)) if *progress == expected));
+
+    // Nothing changed since.
+    let progress = fixture
+        .state
+        .scan(ScanTarget::Libraries, &ScanControl::default())
+        .await
+        .unwrap();
+    assert_eq!(progress.changes, 0);
+    assert_eq!(progress.tags_read, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelled_scans_write_nothing() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+
+    let (job, control) = ScanJob::new();
+    job.cancel();
+    let res = fixture.state.scan(ScanTarget::Libraries, &control).await;
+    control.finish(&res);
+
+    assert!(res.unwrap_err().is::<ScanCancelled>());
+    assert!(matches!(
+        job.events().drain().last(),
+        Some(ScanEvent::Cancelled)
+    ));
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM filenodes").await, 0);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subtrees_can_be_scanned_on_their_own() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    fs::create_dir(fixture.music().join("B")).unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+
+    write_wav(&fixture.music().join("A/02.wav"));
+    write_wav(&fixture.music().join("B/03.wav"));
+    let progress = fixture
+        .state
+        .scan(
+            ScanTarget::Subtree(fixture.music().join("B")),
+            &ScanControl::default(),
+        )
+        .await
+        .unwrap();
+
+    // `B` and `B/03.wav`.
+    assert_eq!(progress.dirs_visited, 1);
+    assert_eq!(progress.files_examined, 1);
+    let paths = fixture
+        .tracks()
+        .await
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["A/01.wav", "B/03.wav"]);
+
+    assert!(
+        fixture
+            .state
+            .scan(
+                ScanTarget::Subtree(fixture.dir.path().to_path_buf()),
+                &ScanControl::default(),
+            )
+            .await
+            .is_err()
+    );
+    assert!(
+        fixture
+            .state
+            .scan(ScanTarget::Library(Uuid::new_v4()), &ScanControl::default())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreadable_files_are_reported_without_aborting() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    fs::write(fixture.music().join("A/02.wav"), "truncated").unwrap();
+    write_wav(&fixture.music().join("B/03.wav"));
+
+    let progress = fixture
+        .state
+        .scan(ScanTarget::Libraries, &ScanControl::default())
+        .await
+        .unwrap();
+    assert_eq!(progress.errors, 1);
+
+    // Still listed, it may be fixed or replaced later on.
+    assert_eq!(fixture.tracks().await.len(), 3);
+
+    let errors = fixture.state.scan_errors().await.unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].path, fixture.music().join("A/02.wav"));
+    assert_eq!(errors[0].kind, ScanErrorKind::Tags);
+
+    write_wav(&fixture.music().join("A/02.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert!(fixture.state.scan_errors().await.unwrap().is_empty());
+    assert_eq!(fixture.tracks().await.len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreadable_directories_keep_their_tracks() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("B/C/02.wav"));
+    write_wav(&fixture.music().join("B/03.wav"));
+    write_wav(&fixture.music().join("D/04.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+    assert_eq!(tracks.len(), 4);
+
+    // `B` can be listed but nothing in it examined, `D` can't be listed at all.
+    let set_mode = |dir: &str, mode| {
+        fs::set_permissions(fixture.music().join(dir), fs::Permissions::from_mode(mode)).unwrap();
+    };
+    set_mode("B", 0o444);
+    set_mode("D", 0o000);
+    if fs::read_dir(fixture.music().join("D")).is_ok() {
+        // Permissions aren't checked for root.
+        set_mode("B", 0o755);
+        set_mode("D", 0o755);
+        return;
+    }
+
+    let res = fixture.state.sync_libraries().await;
+    set_mode("B", 0o755);
+    set_mode("D", 0o755);
+    res.unwrap();
+
+    assert_eq!(fixture.tracks().await, tracks);
+    let errors = fixture
+        .state
+        .scan_errors()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|error| (error.path, error.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        [
+            (fixture.music().join("B/03.wav"), ScanErrorKind::Metadata),
+            (fixture.music().join("B/C"), ScanErrorKind::Metadata),
+            (fixture.music().join("D"), ScanErrorKind::Walk),
+        ]
+    );
+
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(fixture.tracks().await, tracks);
+    assert!(fixture.state.scan_errors().await.unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn files_read_with_an_older_version_are_read_again() {
+    let fixture = Fixture::new().await;
+    let path = fixture.music().join("A/01.wav");
+    write_wav(&path);
+    write_tag(&path, &[(ItemKey::TrackTitle, "Song")]);
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+
+    let title = async || {
+        let library = fixture.state.library_snapshot().await.unwrap();
+        library.values().next().unwrap().title.clone()
+    };
+
+    // As if the file had been indexed before titles were read.
+    sqlx::query("UPDATE tracks SET title = NULL")
+        .execute(&fixture.db)
+        .await
+        .unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(title().await, "");
+
+    sqlx::query("UPDATE filenodes SET read_version = 0")
+        .execute(&fixture.db)
+        .await
+        .unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(title().await, "Song");
+    assert_eq!(fixture.tracks().await, tracks);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn copied_file_keeps_its_track() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("A/02.wav"));
+
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(
+        fixture
+            .count("SELECT COUNT(*) FROM filenodes WHERE audio_hash IS NOT NULL")
+            .await,
+        2
+    );
+    let tracks = fixture.tracks().await;
+
+    // A copy has a new inode, as if it was moved to another disk.
+    fs::create_dir(fixture.music().join("B")).unwrap();
+    fs::copy(
+        fixture.music().join("A/01.wav"),
+        fixture.music().join("B/copy.wav"),
+    )
+    .unwrap();
+    fs::remove_file(fixture.music().join("A/01.wav")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(
+        fixture.tracks().await,
+        [
+            ("A/02.wav".to_string(), tracks[1].1),
+            ("B/copy.wav".to_string(), tracks[0].1),
+        ]
+    );
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn detached_track_is_relinked_when_its_file_is_back() {
+    let fixture = Fixture::with_sync_options(SyncOptions {
+        keep_missing_tracks: true,
+    })
+    .await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    // An empty root would look like an offline library.
+    fs::create_dir(fixture.music().join("B")).unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+    let track_id = fixture.tracks().await[0].1;
+    let bytes = fs::read(fixture.music().join("A/01.wav")).unwrap();
+
+    fs::remove_dir_all(fixture.music().join("A")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert!(fixture.tracks().await.is_empty());
+
+    // Downloaded again, somewhere else.
+    fs::write(fixture.music().join("B/01.wav"), bytes).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(fixture.tracks().await, [("B/01.wav".to_string(), track_id)]);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn duplicates_are_grouped_and_can_be_hidden() {
+    let mut fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("A/02.wav"));
+    write_wav(&fixture.music().join("A/03.wav"));
+    fs::create_dir(fixture.music().join("B")).unwrap();
+    fs::copy(
+        fixture.music().join("A/01.wav"),
+        fixture.music().join("B/01.wav"),
+    )
+    .unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+    let [a1, a2, a3, b1] = [tracks[0].1, tracks[1].1, tracks[2].1, tracks[3].1];
+
+    // The same song in another format, as far as the tags can tell.
+    sqlx::query("UPDATE tracks SET artist = 'Artist', title = 'Song' WHERE id IN (?, ?)")
+        .bind(a2)
+        .bind(a3)
+        .execute(&fixture.db)
+        .await
+        .unwrap();
+
+    let mut groups = fixture.state.duplicates().await.unwrap();
+    for group in &mut groups {
+        group.tracks.sort();
+    }
+    let mut exact = [a1, b1];
+    exact.sort();
+    let mut recording = [a2, a3];
+    recording.sort();
+    assert_eq!(
+        groups,
+        [
+            DuplicateGroup {
+                kind: DuplicateKind::Exact,
+                tracks: exact.to_vec(),
+                preferred: None,
+            },
+            DuplicateGroup {
+                kind: DuplicateKind::SameRecording,
+                tracks: recording.to_vec(),
+                preferred: None,
+            },
+        ]
+    );
+
+    fixture.state.refresh_snapshot().await.unwrap();
+    fixture.state.prefer_duplicate(b1).await.unwrap();
+    assert_eq!(
+        fixture.state.duplicates().await.unwrap()[0].preferred,
+        Some(b1)
+    );
+    let delta = fixture.state.refresh_snapshot().await.unwrap();
+    assert_eq!(delta.removed, [a1]);
+    assert!(!fixture.tracks().await.iter().any(|(_, id)| *id == a1));
+
+    // Other versions of a recording are never hidden.
+    assert!(fixture.state.prefer_duplicate(a2).await.is_err());
+
+    // The hidden copy is back once the preferred one is gone.
+    fs::remove_dir_all(fixture.music().join("B")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(
+        fixture.tracks().await,
+        [
+            ("A/01.wav".to_string(), a1),
+            ("A/02.wav".to_string(), a2),
+            ("A/03.wav".to_string(), a3),
+        ]
+    );
+
+    assert!(fixture.state.prefer_duplicate(a1).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn audio_is_detected_by_content_and_filtered_by_extension() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    // Audio with an extension that says nothing about it.
+    write_wav(&fixture.music().join("A/02.track"));
+    fs::write(fixture.music().join("A/cover.jpg"), b"not audio").unwrap();
+    fs::write(fixture.music().join("A/notes.track"), b"not audio either").unwrap();
+
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(
+        fixture
+            .tracks()
+            .await
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>(),
+        ["A/01.wav", "A/02.track"]
+    );
+    let formats: Vec<(String, Option<String>)> =
+        sqlx::query_as("SELECT container, codec FROM tracks ORDER BY container")
+            .fetch_all(&fixture.db)
+            .await
+            .unwrap();
+    assert_eq!(
+        formats,
+        [
+            ("wav".to_string(), Some("pcm_s16le".to_string())),
+            ("wav".to_string(), Some("pcm_s16le".to_string())),
+        ]
+    );
+
+    let library_id: Uuid = sqlx::query_scalar("SELECT id FROM libraries")
+        .fetch_one(&fixture.db)
+        .await
+        .unwrap();
+    fixture
+        .state
+        .set_extension_filter(
+            library_id,
+            &ExtensionFilter {
+                include: None,
+                exclude: vec!["wav".to_string()],
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(fixture.tracks().await.len(), 1);
+    assert_eq!(fixture.tracks().await[0].0, "A/02.track");
+
+    fixture
+        .state
+        .set_extension_filter(
+            library_id,
+            &ExtensionFilter {
+                include: Some(vec!["wav".to_string()]),
+                exclude: vec!["track".to_string()],
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(fixture.tracks().await[0].0, "A/01.wav");
+    assert_eq!(fixture.tracks().await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ignored_directories_are_left_out() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("Album/01.wav"));
+    write_wav(&fixture.music().join("Samples/kick.wav"));
+    write_wav(&fixture.music().join("Album/Samples/02.wav"));
+    write_wav(&fixture.music().join("Song Project/bounce.wav"));
+    write_wav(&fixture.music().join("Podcasts/episode.wav"));
+    fs::write(fixture.music().join("Podcasts/.nomedia"), b"").unwrap();
+
+    let library_id: Uuid = sqlx::query_scalar("SELECT id FROM libraries")
+        .fetch_one(&fixture.db)
+        .await
+        .unwrap();
+    assert!(
+        fixture
+            .state
+            .set_ignore_patterns(library_id, vec!["[".to_string()])
+            .await
+            .is_err()
+    );
+    fixture
+        .state
+        .set_ignore_patterns(
+            library_id,
+            vec!["Samples".to_string(), "**/* Project".to_string()],
+        )
+        .await
+        .unwrap();
+
+    let paths =
+        |tracks: Vec<(String, Uuid)>| tracks.into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+    assert_eq!(
+        paths(fixture.tracks().await),
+        ["Album/01.wav", "Album/Samples/02.wav"]
+    );
+
+    // Marked after it was indexed.
+    fs::write(fixture.music().join("Album/Samples/.ignore"), b"").unwrap();
+    fixture
+        .state
+        .sync_paths(vec![fixture.music().join("Album/Samples/.ignore")])
+        .await
+        .unwrap();
+    assert_eq!(paths(fixture.tracks().await), ["Album/01.wav"]);
+
+    fixture
+        .state
+        .set_ignore_patterns(library_id, vec![])
+        .await
+        .unwrap();
+    assert_eq!(
+        paths(fixture.tracks().await),
+        [
+            "Album/01.wav",
+            "Samples/kick.wav",
+            "Song Project/bounce.wav"
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn libraries_can_be_added_relocated_and_removed() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.dir.path().join("Other/02.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+    let music_tracks = fixture.tracks().await;
+
+    let other_id = fixture
+        .state
+        .add_library(&fixture.dir.path().join("Other"), Some("Other".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(fixture.state.library_snapshot().await.unwrap().len(), 2);
+
+    let music_id = fixture
+        .state
+        .libraries()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|l| l.id != other_id)
+        .unwrap()
+        .id;
+    let err = fixture
+        .state
+        .add_library(&fixture.music().join("A"), None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<LibraryPathError>(),
+        Some(LibraryPathError::Overlaps { library_id, .. }) if *library_id == music_id
+    ));
+    assert!(matches!(
+        fixture
+            .state
+            .add_library(Path::new("Music"), None)
+            .await
+            .unwrap_err()
+            .downcast_ref::<LibraryPathError>(),
+        Some(LibraryPathError::NotAbsolute(_))
+    ));
+
+    fixture
+        .state
+        .relabel_library(music_id, Some("Music".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        fixture
+            .state
+            .libraries()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.label)
+            .collect::<Vec<_>>(),
+        [Some("Music".to_string()), Some("Other".to_string())]
+    );
+
+    // As if the drive was mounted somewhere else.
+    let moved = fixture.dir.path().join("Moved");
+    fs::rename(fixture.music(), &moved).unwrap();
+    fixture
+        .state
+        .relocate_library(music_id, &moved)
+        .await
+        .unwrap();
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    assert_eq!(
+        snapshot[&music_tracks[0].1].filepath,
+        moved.join("A/01.wav").to_str().unwrap()
+    );
+
+    fixture.state.remove_library(other_id).await.unwrap();
+    assert_eq!(
+        fixture
+            .state
+            .library_snapshot()
+            .await
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>(),
+        [music_tracks[0].1]
+    );
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM tracks").await, 1);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM libraries").await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn offline_libraries_keep_their_tracks() {
+    let fixture = Fixture::new().await;
+    write_wav(&fixture.music().join("A/01.wav"));
+    write_wav(&fixture.music().join("A/02.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+
+    // As if the drive was unplugged.
+    let unplugged = fixture.dir.path().join("Unplugged");
+    fs::rename(fixture.music(), &unplugged).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    assert_eq!(fixture.tracks().await, tracks);
+    assert!(
+        fixture
+            .state
+            .library_snapshot()
+            .await
+            .unwrap()
+            .values()
+            .all(|track| !track.available)
+    );
+    assert_eq!(
+        fixture
+            .count("SELECT COUNT(*) FROM libraries WHERE online")
+            .await,
+        0
+    );
+
+    // An empty mount point is offline as well.
+    fs::create_dir(fixture.music()).unwrap();
+    assert!(
+        fixture
+            .state
+            .check_library_roots()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    fs::remove_dir(fixture.music()).unwrap();
+
+    fs::rename(&unplugged, fixture.music()).unwrap();
+    let changed = fixture.state.check_library_roots().await.unwrap();
+    assert_eq!(changed.len(), 1);
+    assert!(changed[0].online);
+
+    assert_eq!(fixture.tracks().await, tracks);
+    assert!(
+        fixture
+            .state
+            .library_snapshot()
+            .await
+            .unwrap()
+            .values()
+            .all(|track| track.available)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn common_tags_are_indexed() {
+    let fixture = Fixture::new().await;
+    for (name, title, track) in [("b.wav", "Second", "2"), ("a.wav", "First", "1")] {
+        let path = fixture.music().join("Album").join(name);
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, "Artist"),
+                (ItemKey::TrackTitle, title),
+                (ItemKey::AlbumTitle, " Album "),
+                (ItemKey::AlbumArtist, "Various"),
+                (ItemKey::TrackNumber, track),
+                (ItemKey::DiscNumber, "1"),
+                (ItemKey::RecordingDate, "2004-05-01"),
+                (ItemKey::Genre, "Jazz"),
+                (ItemKey::Composer, "Composer"),
+            ],
+        );
+    }
+    write_wav(&fixture.music().join("Album/untagged.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    let first = snapshot
+        .values()
+        .find(|track| track.title == "First")
+        .unwrap();
+    assert_eq!(first.artist, "Artist");
+    assert_eq!(first.album.as_deref(), Some("Album"));
+    assert_eq!(first.album_artist.as_deref(), Some("Various"));
+    assert_eq!(first.track_number, Some(1));
+    assert_eq!(first.disc_number, Some(1));
+    assert_eq!(first.year, Some(2004));
+    assert_eq!(first.genre.as_deref(), Some("Jazz"));
+    assert_eq!(first.composer.as_deref(), Some("Composer"));
+
+    let untagged = snapshot
+        .values()
+        .find(|track| track.filepath.ends_with("untagged.wav"))
+        .unwrap();
+    assert_eq!(untagged.album, None);
+    assert_eq!(untagged.year, None);
+
+    let album = fixture
+        .state
+        .resolve_context(PlayContext::Album(first.album_id.unwrap()))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|id| snapshot[&id].title.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(album, ["First", "Second"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn artists_and_genres_are_split_into_credits() {
+    let fixture = Fixture::new().await;
+    // In a directory, since an empty root would look like an offline library.
+    let path = fixture.music().join("A/song.wav");
+    write_wav(&path);
+    write_tag(
+        &path,
+        &[
+            (ItemKey::TrackArtist, "Main feat. Guest & Other"),
+            (ItemKey::TrackTitle, "Song (Mixer Remix)"),
+            (ItemKey::Composer, "Writer"),
+            (ItemKey::Genre, "Rock/Pop"),
+        ],
+    );
+    fixture.state.sync_libraries().await.unwrap();
+    let track_id = fixture.tracks().await[0].1;
+
+    let artists = fixture.state.artists().await.unwrap();
+    assert_eq!(
+        artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>(),
+        ["Guest", "Main", "Mixer", "Other", "Writer"]
+    );
+
+    let guest = artists.iter().find(|a| a.name == "Guest").unwrap();
+    let credits = fixture.state.artist_credits(guest.id).await.unwrap();
+    assert_eq!(credits.len(), 1);
+    assert_eq!(credits[0].track_id, track_id);
+    assert_eq!(credits[0].role, ArtistRole::Feature);
+
+    let mixer = artists.iter().find(|a| a.name == "Mixer").unwrap();
+    assert_eq!(
+        fixture.state.artist_credits(mixer.id).await.unwrap()[0].role,
+        ArtistRole::Remixer
+    );
+
+    let genres = fixture.state.genres().await.unwrap();
+    assert_eq!(
+        genres.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(),
+        ["Pop", "Rock"]
+    );
+    assert_eq!(
+        fixture.state.genre_tracks(genres[0].id).await.unwrap(),
+        [track_id]
+    );
+
+    // Artists and genres that are no longer credited go away.
+    fs::remove_file(&path).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert!(fixture.state.artists().await.unwrap().is_empty());
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM genres").await, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tracks_are_grouped_into_albums() {
+    let fixture = Fixture::new().await;
+    for (disc, track, title) in [
+        ("CD2", "1", "Third"),
+        ("CD1", "2", "Second"),
+        ("CD1", "1", "First"),
+    ] {
+        let path = fixture
+            .music()
+            .join("Album")
+            .join(disc)
+            .join(format!("{title}.wav"));
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, "Band"),
+                (ItemKey::AlbumArtist, "Band"),
+                (ItemKey::TrackTitle, title),
+                (ItemKey::AlbumTitle, &format!("Album ({disc})")),
+                (ItemKey::TrackNumber, track),
+                (ItemKey::DiscNumber, &disc[2..]),
+                (ItemKey::RecordingDate, "1999"),
+            ],
+        );
+    }
+    for artist in ["One", "Two"] {
+        let path = fixture.music().join("Mix").join(format!("{artist}.wav"));
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, artist),
+                (ItemKey::TrackTitle, artist),
+                (ItemKey::AlbumTitle, "Mix"),
+            ],
+        );
+    }
+    // The same title in another folder is another album.
+    let path = fixture.music().join("Other/Mix.wav");
+    write_wav(&path);
+    write_tag(&path, &[(ItemKey::AlbumTitle, "Mix")]);
+    fixture.state.sync_libraries().await.unwrap();
+
+    let albums = fixture.state.albums().await.unwrap();
+    assert_eq!(albums.len(), 3);
+
+    let album = &albums[0];
+    assert_eq!(album.title, "Album (CD1)");
+    assert_eq!(album.artist.as_deref(), Some("Band"));
+    assert!(!album.compilation);
+    assert_eq!(album.year, Some(1999));
+    assert_eq!((album.discs, album.tracks), (2, 3));
+    assert!(album.duration_ms > 0);
+
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    let titles = fixture
+        .state
+        .album_tracks(album.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|id| snapshot[&id].title.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["First", "Second", "Third"]);
+    assert!(
+        snapshot
+            .values()
+            .filter(|track| track.artist == "Band")
+            .all(|track| track.album_id == Some(album.id))
+    );
+
+    let mix = albums
+        .iter()
+        .find(|album| album.title == "Mix" && album.tracks == 2)
+        .unwrap();
+    assert!(mix.compilation);
+    assert_eq!(mix.artist, None);
+
+    // Albums without tracks go away.
+    fs::remove_dir_all(fixture.music().join("Other")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(fixture.state.albums().await.unwrap().len(), 2);
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM albums").await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn artwork_is_cached_and_deduplicated() {
+    let fixture = Fixture::new().await;
+    let cover = png(600, 400, 200);
+    for title in ["One", "Two"] {
+        let path = fixture.music().join("Album").join(format!("{title}.wav"));
+        write_wav(&path);
+        let mut id3 = Tag::new(TagType::Id3v2);
+        id3.insert_text(ItemKey::AlbumArtist, "Band".to_string());
+        id3.insert_text(ItemKey::AlbumTitle, "Album".to_string());
+        id3.push_picture(Picture::new_unchecked(
+            PictureType::CoverFront,
+            Some(MimeType::Png),
+            None,
+            cover.clone(),
+        ));
+        id3.save_to_path(&path, WriteOptions::default()).unwrap();
+    }
+    // Untagged tracks in a disc folder get the artwork of the folder above.
+    write_wav(&fixture.music().join("Folder/CD1/01.wav"));
+    fs::write(fixture.music().join("Folder/folder.png"), png(64, 64, 10)).unwrap();
+    fs::write(fixture.music().join("Folder/cover.png"), png(64, 64, 20)).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+
+    // The picture of both tracks is stored once.
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM artworks").await, 2);
+    let tracks = fixture.tracks().await;
+    let one = fixture
+        .state
+        .artwork(ArtworkOwner::Track(tracks[0].1), Some(200))
+        .await
+        .unwrap()
+        .unwrap();
+    let two = fixture
+        .state
+        .artwork(ArtworkOwner::Track(tracks[1].1), None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(one.artwork, two.artwork);
+    assert_eq!((one.artwork.width, one.artwork.height), (600, 400));
+    assert_eq!(one.artwork.mime, "image/png");
+    assert_eq!(fs::read(&two.path).unwrap(), cover);
+    let thumbnail = image::open(&one.path).unwrap();
+    assert_eq!(thumbnail.width(), 256);
+    assert!(thumbnail.height() < 256);
+
+    let album = fixture.state.albums().await.unwrap()[0].id;
+    let album_artwork = fixture
+        .state
+        .artwork(ArtworkOwner::Album(album), None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(album_artwork.artwork, one.artwork);
+
+    // `cover` is preferred over `folder`.
+    let folder = fixture
+        .state
+        .artwork(ArtworkOwner::Track(tracks[2].1), None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fs::read(&folder.path).unwrap(), png(64, 64, 20));
+
+    // Pictures nothing refers to anymore are removed from the cache.
+    fs::remove_dir_all(fixture.music().join("Album")).unwrap();
+    fs::remove_file(fixture.music().join("Folder/cover.png")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    assert_eq!(fixture.count("SELECT COUNT(*) FROM artworks").await, 1);
+    assert!(!one.path.exists() && !two.path.exists());
+    let folder = fixture
+        .state
+        .artwork(ArtworkOwner::Track(tracks[2].1), None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fs::read(&folder.path).unwrap(), png(64, 64, 10));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tag_edits_are_written_to_files_and_can_be_undone() {
+    let fixture = Fixture::new().await;
+    for title in ["Frist", "Second"] {
+        let path = fixture.music().join(format!("{title}.wav"));
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, "Band"),
+                (ItemKey::TrackTitle, title),
+                (ItemKey::AlbumTitle, "Album"),
+            ],
+        );
+    }
+    fixture.state.sync_libraries().await.unwrap();
+    let tracks = fixture.tracks().await;
+    let first = fixture.music().join("Frist.wav");
+    let file_title = |path: &Path| {
+        use lofty::{file::TaggedFileExt as _, tag::Accessor as _};
+        lofty::read_from_path(path)
+            .unwrap()
+            .primary_tag()
+            .unwrap()
+            .title()
+            .map(|title| title.to_string())
+    };
+
+    let fix_title = [TagEdit {
+        field: TagField::Title,
+        value: Some(" First ".to_string()),
+    }];
+    let preview = fixture
+        .state
+        .edit_tags(&[tracks[0].1], &fix_title, true)
+        .await
+        .unwrap();
+    assert_eq!(preview.id, None);
+    assert_eq!(preview.changes.len(), 1);
+    assert_eq!(preview.changes[0].old.as_deref(), Some("Frist"));
+    assert_eq!(preview.changes[0].new.as_deref(), Some("First"));
+    assert_eq!(file_title(&first).as_deref(), Some("Frist"));
+
+    let outcome = fixture
+        .state
+        .edit_tags(&[tracks[0].1], &fix_title, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.changes, preview.changes);
+    assert!(outcome.errors.is_empty());
+    assert_eq!(file_title(&first).as_deref(), Some("First"));
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    assert_eq!(snapshot[&tracks[0].1].title, "First");
+
+    // The files and their rows were updated together, so nothing is read again.
+    let progress = fixture
+        .state
+        .scan(ScanTarget::Libraries, &ScanControl::default())
+        .await
+        .unwrap();
+    assert_eq!(progress.tags_read, 0);
+
+    // Fields that have the value already are left alone, so nothing is written or
+    // logged.
+    let rename_album = [TagEdit {
+        field: TagField::Album,
+        value: Some("Album".to_string()),
+    }];
+    let ids = tracks.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+    let outcome = fixture
+        .state
+        .edit_tags(&ids, &rename_album, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.id, None);
+    assert!(outcome.changes.is_empty());
+
+    let invalid = [TagEdit {
+        field: TagField::TrackNumber,
+        value: Some("two".to_string()),
+    }];
+    assert!(
+        fixture
+            .state
+            .edit_tags(&ids, &invalid, false)
+            .await
+            .is_err()
+    );
+
+    let log = fixture.state.tag_edits().await.unwrap();
+    assert_eq!(log.len(), 1);
+    assert!(!log[0].undone);
+    assert_eq!(log[0].changes, preview.changes);
+
+    fixture.state.undo_tag_edit(log[0].id).await.unwrap();
+    assert_eq!(file_title(&first).as_deref(), Some("Frist"));
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    assert_eq!(snapshot[&tracks[0].1].title, "Frist");
+    assert!(fixture.state.tag_edits().await.unwrap()[0].undone);
+    assert!(fixture.state.undo_tag_edit(log[0].id).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_tolerates_typos_and_diacritics() {
+    let fixture = Fixture::new().await;
+    for (artist, title, album) in [
+        ("Björk", "Jóga", "Homogenic"),
+        ("The Beatles", "Help!", "Help!"),
+        ("U2", "One", "Achtung Baby"),
+    ] {
+        let path = fixture.music().join(format!("{title}.wav"));
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, artist),
+                (ItemKey::AlbumArtist, artist),
+                (ItemKey::TrackTitle, title),
+                (ItemKey::AlbumTitle, album),
+            ],
+        );
+    }
+    write_wav(&fixture.music().join("field recording.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    let titles = |ids: &[Uuid]| {
+        ids.iter()
+            .map(|id| snapshot[id].title.clone())
+            .collect::<Vec<_>>()
+    };
+    let artists = fixture.state.artists().await.unwrap();
+    let artist_names = |ids: &[Uuid]| {
+        ids.iter()
+            .map(|id| artists.iter().find(|a| a.id == *id).unwrap().name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let results = fixture.state.search("bjork joga", 10).await.unwrap();
+    assert_eq!(titles(&results.tracks), ["Jóga"]);
+    assert!(results.albums.is_empty());
+
+    let results = fixture.state.search("BJÖRK", 10).await.unwrap();
+    assert_eq!(artist_names(&results.artists), ["Björk"]);
+    assert_eq!(results.albums.len(), 1);
+
+    // A swap, a wrong letter, and a word that's still being typed.
+    for query in ["beatels", "hekp", "achtung ba"] {
+        let results = fixture.state.search(query, 10).await.unwrap();
+        assert_eq!(results.tracks.len(), 1, "{query}");
+    }
+    assert!(
+        fixture
+            .state
+            .search("portishead", 10)
+            .await
+            .unwrap()
+            .tracks
+            .is_empty()
+    );
+
+    // Too short to be looked up by trigrams.
+    let results = fixture.state.search("u2", 10).await.unwrap();
+    assert_eq!(artist_names(&results.artists), ["U2"]);
+
+    // Untagged files are found by their name.
+    let results = fixture.state.search("recording", 10).await.unwrap();
+    assert_eq!(results.tracks.len(), 1);
+
+    // The index follows the scans.
+    fs::remove_file(fixture.music().join("Jóga.wav")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    let results = fixture.state.search("bjork", 10).await.unwrap();
+    assert_eq!(results, Default::default());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tracks_can_be_filtered_and_sorted_by_query() {
+    let fixture = Fixture::new().await;
+    for (artist, title, year, genre) in [
+        ("Boards of Canada", "Roygbiv", "1998", "Ambient/Electronic"),
+        ("Boards of Canada", "Dayvan Cowboy", "2005", "Ambient"),
+        ("Boards of Canada", "Aquarius (Live)", "1999", "Ambient"),
+        ("Aphex Twin", "Xtal", "1992", "Electronic"),
+    ] {
+        let path = fixture.music().join(format!("{title}.wav"));
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, artist),
+                (ItemKey::TrackTitle, title),
+                (ItemKey::RecordingDate, year),
+                (ItemKey::Genre, genre),
+            ],
+        );
+    }
+    write_wav(&fixture.music().join("100% untagged.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    let query = async |query: &str| {
+        let query = TrackQuery::parse(query).unwrap();
+        let ids = fixture.state.query_tracks(&query).await.unwrap();
+        ids.iter()
+            .map(|id| snapshot[id].title.clone())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        query(r#"artist:"boards of canada" year:>1997 genre:ambient -live sort:-year"#).await,
+        ["Dayvan Cowboy", "Roygbiv"]
+    );
+    // Credited genres match as well as the genre tag.
+    assert_eq!(
+        query("genre:=electronic sort:title").await,
+        ["Roygbiv", "Xtal"]
+    );
+    assert_eq!(
+        query("(xtal OR cowboy) OR year:1990..1998").await,
+        ["Xtal", "Roygbiv", "Dayvan Cowboy"]
+    );
+    // A negated comparison matches the tracks without a year.
+    assert_eq!(query("-year:<2000").await, ["Dayvan Cowboy", ""]);
+    assert_eq!(query("file:%").await, [""]);
+
+    assert_eq!(
+        TrackQuery::parse("year:soon"),
+        Err(QueryError::InvalidValue {
+            field: "year".into(),
+            value: "soon".into(),
+        })
+    );
+    assert_eq!(
+        TrackQuery::parse("mood:calm"),
+        Err(QueryError::UnknownField("mood".into()))
+    );
+    assert_eq!(
+        TrackQuery::parse("(a OR b"),
+        Err(QueryError::UnbalancedParentheses)
+    );
+
+    let queued = fixture
+        .state
+        .resolve_context(PlayContext::Query(
+            TrackQuery::parse("aphex OR dayvan").unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn library_can_be_paged_through_in_order() {
+    let fixture = Fixture::new().await;
+    for i in 0..7 {
+        let path = fixture.music().join(format!("{i}.wav"));
+        let title = format!("Track {i}");
+        write_wav(&path);
+        write_tag(
+            &path,
+            &[
+                (ItemKey::TrackArtist, ["b", "A", "a"][i % 3]),
+                (ItemKey::TrackTitle, title.as_str()),
+            ],
+        );
+    }
+    fixture.state.sync_libraries().await.unwrap();
+
+    let pages = async |query: &str| {
+        let query = TrackQuery::parse(query).unwrap();
+        let mut titles = vec![];
+        let mut after = None;
+        loop {
+            let page = fixture
+                .state
+                .track_page(&query, after.as_ref(), 3)
+                .await
+                .unwrap();
+            assert!(page.tracks.len() <= 3);
+            titles.extend(page.tracks.iter().map(|track| track.title.clone()));
+
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break titles,
+            }
+        }
+    };
+
+    let snapshot = fixture.state.library_snapshot().await.unwrap();
+    for query in ["", "sort:-title", "sort:artist sort:-title", "-artist:b"] {
+        let ids = fixture
+            .state
+            .query_tracks(&TrackQuery::parse(query).unwrap())
+            .await
+            .unwrap();
+        let titles = ids
+            .iter()
+            .map(|id| snapshot[id].title.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(pages(query).await, titles, "{query}");
+    }
+
+    let query = TrackQuery::parse("sort:title").unwrap();
+    let page = fixture.state.track_page(&query, None, 3).await.unwrap();
+    // Tracks are the same as in the snapshot, paths included.
+    for track in &page.tracks {
+        assert_eq!(track, &snapshot[&track.id]);
+    }
+
+    // The cursor holds on to where the page ended, even once its last track is gone.
+    fs::remove_file(fixture.music().join("2.wav")).unwrap();
+    fixture.state.sync_libraries().await.unwrap();
+    let next = fixture
+        .state
+        .track_page(&query, page.next.as_ref(), 3)
+        .await
+        .unwrap();
+    assert_eq!(next.tracks[0].title, "Track 3");
+
+    // Cursors only fit the sort they come from.
+    assert!(
+        fixture
+            .state
+            .track_page(&TrackQuery::default(), page.next.as_ref(), 3)
+            .await
+            .is_err()
+    );
+
+    // Tracks are sorted by when they were first scanned.
+    write_wav(&fixture.music().join("new.wav"));
+    fixture.state.sync_libraries().await.unwrap();
+    let query = TrackQuery::parse("sort:-added").unwrap();
+    let page = fixture.state.track_page(&query, None, 1).await.unwrap();
+    assert!(page.tracks[0].filepath.ends_with("new.wav"));
+}